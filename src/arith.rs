@@ -0,0 +1,257 @@
+use crate::{runner::CommandRunner, Result, ShellError};
+
+/// Operators, longest first so that `<=` isn't read as `<`
+const OPERATORS: &[&str] = &[
+    "**", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "+", "-", "*", "/",
+    "%", "<", ">", "=", "!", "(", ")",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+fn error(msg: impl Into<String>) -> ShellError {
+    ShellError::InvalidArgument(msg.into())
+}
+
+/// The value of a number: hexadecimal with `0x` in front, octal with `0`,
+/// and decimal otherwise, like in C
+fn number(text: &str) -> Result<i64> {
+    let (digits, radix) = match text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None if text.len() > 1 && text.starts_with('0') => (&text[1..], 8),
+        None => (text, 10),
+    };
+    i64::from_str_radix(digits, radix).map_err(|_| error(format!("{text}: invalid number")))
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = expr.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..len])?));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else {
+            let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) else {
+                return Err(error(format!("{expr}: syntax error in expression")));
+            };
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent evaluator, one method per precedence level
+struct Evaluator<'a> {
+    expr: &'a str,
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    runner: &'a mut CommandRunner,
+    /// Whether the value doesn't matter, like on the right of `0 &&`, so
+    /// that nothing is assigned and nothing fails
+    skipping: bool,
+}
+
+impl Evaluator<'_> {
+    fn syntax_error(&self) -> ShellError {
+        error(format!("{}: syntax error in expression", self.expr))
+    }
+
+    /// Consume the next token if it is one of `ops`
+    fn next_op(&mut self, ops: &[&str]) -> Option<&'static str> {
+        match self.tokens.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.tokens.next();
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    /// `name = value` and friends, which bind loosest and to the right
+    fn assignment(&mut self) -> Result<i64> {
+        let mut lookahead = self.tokens.clone();
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) = (lookahead.next(), lookahead.next())
+        {
+            if ["=", "+=", "-=", "*=", "/=", "%="].contains(&op) {
+                self.tokens = lookahead;
+                let rhs = self.assignment()?;
+                let value = match op {
+                    "=" => rhs,
+                    op => self.apply(&op[..1], self.lookup(&name)?, rhs)?,
+                };
+                if !self.skipping {
+                    self.runner.set_var(&name, value.to_string());
+                }
+                return Ok(value);
+            }
+        }
+        self.binary(0)
+    }
+
+    /// Binary operators, from the loosest binding `level` on
+    fn binary(&mut self, level: usize) -> Result<i64> {
+        const LEVELS: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        let Some(ops) = LEVELS.get(level) else {
+            return self.power();
+        };
+
+        let mut value = self.binary(level + 1)?;
+        while let Some(op) = self.next_op(ops) {
+            // Like in C, the right side of `&&` and `||` only counts when the
+            // left side doesn't decide already
+            let skipping = self.skipping;
+            self.skipping |= matches!((op, value), ("&&", 0)) || op == "||" && value != 0;
+            let rhs = self.binary(level + 1);
+            self.skipping = skipping;
+            value = self.apply(op, value, rhs?)?;
+        }
+        Ok(value)
+    }
+
+    /// `**`, which binds to the right
+    fn power(&mut self) -> Result<i64> {
+        let base = self.unary()?;
+        if self.next_op(&["**"]).is_none() {
+            return Ok(base);
+        }
+        let exponent = self.power()?;
+        self.apply("**", base, exponent)
+    }
+
+    fn unary(&mut self) -> Result<i64> {
+        match self.next_op(&["!", "-", "+"]) {
+            Some("!") => Ok((self.unary()? == 0) as i64),
+            Some("-") => Ok(self.unary()?.wrapping_neg()),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64> {
+        match self.tokens.next() {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Name(name)) => self.lookup(&name),
+            Some(Token::Op("(")) => {
+                let value = self.assignment()?;
+                match self.tokens.next() {
+                    Some(Token::Op(")")) => Ok(value),
+                    _ => Err(self.syntax_error()),
+                }
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    /// The value of a variable; unset or empty ones count as 0
+    fn lookup(&self, name: &str) -> Result<i64> {
+        match self.runner.var(name) {
+            Some(value) if !value.trim().is_empty() && !self.skipping => {
+                number(value.trim()).map_err(|_| error(format!("{name}: {value}: not a number")))
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn apply(&self, op: &str, lhs: i64, rhs: i64) -> Result<i64> {
+        if self.skipping {
+            return Ok(0);
+        }
+        Ok(match op {
+            "||" => (lhs != 0 || rhs != 0) as i64,
+            "&&" => (lhs != 0 && rhs != 0) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "<" => (lhs < rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err(error("division by zero")),
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "**" => {
+                let exponent = u32::try_from(rhs).map_err(|_| error("exponent less than 0"))?;
+                lhs.wrapping_pow(exponent)
+            }
+            _ => unreachable!("{op} is not a binary operator"),
+        })
+    }
+}
+
+/// Evaluate an arithmetic expression, like in `let` or `(( ))`
+///
+/// Works on 64 bit integers and supports the usual C operators, with
+/// variables referenced by name. Assignments like `x = 1` or `x += 2` set
+/// shell variables.
+pub(crate) fn eval(expr: &str, runner: &mut CommandRunner) -> Result<i64> {
+    let mut evaluator = Evaluator {
+        expr,
+        tokens: tokenize(expr)?.into_iter().peekable(),
+        runner,
+        skipping: false,
+    };
+    let value = evaluator.assignment()?;
+    match evaluator.tokens.next() {
+        None => Ok(value),
+        Some(_) => Err(evaluator.syntax_error()),
+    }
+}
+
+/// The exit status of `let` and `(( ))`: success unless the value is 0
+pub(crate) fn status(value: i64) -> i32 {
+    (value == 0) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn and_or_skip_the_right_side_when_the_left_decides() {
+        let mut runner = CommandRunner::new();
+        assert_eq!(eval("0 && (y = 5)", &mut runner).unwrap(), 0);
+        assert_eq!(eval("1 || (y = 5)", &mut runner).unwrap(), 1);
+        assert_eq!(runner.var("y"), None);
+        assert_eq!(eval("x != 0 && (y = 1 / x)", &mut runner).unwrap(), 0);
+        assert_eq!(eval("0 || (y = 5)", &mut runner).unwrap(), 1);
+        assert_eq!(runner.var("y").as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn numbers_can_be_hexadecimal_or_octal() {
+        let mut runner = CommandRunner::new();
+        assert_eq!(eval("0x10 + 0X1f", &mut runner).unwrap(), 47);
+        assert_eq!(eval("010", &mut runner).unwrap(), 8);
+        assert_eq!(eval("0", &mut runner).unwrap(), 0);
+        assert!(eval("08", &mut runner).is_err());
+        runner.set_var("n", "0x20".into());
+        assert_eq!(eval("n + 1", &mut runner).unwrap(), 33);
+    }
+}
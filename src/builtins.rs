@@ -0,0 +1,2201 @@
+use std::{
+    collections::HashMap,
+    env,
+    io::{IsTerminal, Read as _, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    arith,
+    command::{command_position, is_executable, is_name, Environment},
+    history::format_time,
+    interrupt,
+    jobs::{Job, JobState, Process},
+    print_error,
+    redirect::dup_fd,
+    runner::{canonicalize, read_script, script_body, CommandRunner, Flow, Options},
+    Command, Result, ShellError,
+};
+
+/// A command implemented by the shell itself
+pub(crate) trait Builtin: Send + Sync {
+    /// The name it is called by
+    fn name(&self) -> &str;
+
+    /// How to use it, for `help`
+    ///
+    /// The first line is a short summary, shown when listing all builtins.
+    fn help(&self) -> &str;
+
+    /// Run with the already expanded `args`, writing output to `out`
+    ///
+    /// Returns the exit status.
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32>;
+}
+
+/// All builtins, by name
+pub(crate) fn registry() -> HashMap<String, Box<dyn Builtin>> {
+    let builtins: Vec<Box<dyn Builtin>> = vec![
+        Box::new(Alias),
+        #[cfg(unix)]
+        Box::new(Bg),
+        Box::new(LoopControl("break")),
+        Box::new(Cd),
+        Box::new(Clear),
+        Box::new(Complete),
+        #[cfg(unix)]
+        Box::new(Coproc),
+        Box::new(LoopControl("continue")),
+        Box::new(Dirs),
+        Box::new(Echo),
+        Box::new(Enable),
+        Box::new(Exec),
+        Box::new(Exit),
+        Box::new(Export),
+        Box::new(Status("false", 1)),
+        Box::new(Fg),
+        Box::new(Hash),
+        Box::new(Help),
+        Box::new(History),
+        Box::new(Jobs),
+        #[cfg(unix)]
+        Box::new(Kill),
+        Box::new(Let),
+        Box::new(Popd),
+        Box::new(Printenv),
+        Box::new(Pushd),
+        Box::new(Pwd),
+        Box::new(Read),
+        Box::new(Set),
+        Box::new(Shift),
+        Box::new(Source("source")),
+        Box::new(Source(".")),
+        Box::new(Test("test")),
+        Box::new(Test("[")),
+        Box::new(Timeout),
+        Box::new(Status("true", 0)),
+        Box::new(Type),
+        #[cfg(unix)]
+        Box::new(Umask),
+        Box::new(Unalias),
+        Box::new(Unset),
+        Box::new(Wait),
+    ];
+    builtins
+        .into_iter()
+        .map(|builtin| (builtin.name().to_string(), builtin))
+        .collect()
+}
+
+/// Print the directory stack, starting with the working directory
+fn print_dirs(runner: &CommandRunner, out: &mut dyn Write) -> Result<()> {
+    let dirs: Vec<String> = std::iter::once(&runner.pwd)
+        .chain(runner.dir_stack.iter().rev())
+        .map(|dir| runner.tilde_path(dir))
+        .collect();
+    writeln!(out, "{}", dirs.join(" "))?;
+    Ok(())
+}
+
+/// Define aliases, or list them when called without arguments
+struct Alias;
+
+impl Builtin for Alias {
+    fn name(&self) -> &str {
+        "alias"
+    }
+
+    fn help(&self) -> &str {
+        "Define or list aliases\n\
+         \n\
+         usage: alias [-s] [name[=value] ...]\n\
+         \n\
+         Without arguments, lists all aliases. `name=value` defines an alias,\n\
+         and a bare `name` shows its value. With -s, all aliases are saved\n\
+         afterwards, for interactive sessions to start with."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if args.first().is_some_and(|arg| arg == "-s") {
+            let Some(path) = runner.aliases_file.clone() else {
+                return Err(ShellError::InvalidArgument(
+                    "alias: -s: no aliases file to save to".into(),
+                ));
+            };
+            // Listing the aliases isn't what `alias -s` on its own is for
+            let status = match &args[1..] {
+                [] => 0,
+                args => self.run(runner, args, out)?,
+            };
+            runner.save_aliases().map_err(|e| {
+                ShellError::InvalidArgument(format!("alias: {}: {e}", path.display()))
+            })?;
+            return Ok(status);
+        }
+        if args.is_empty() {
+            let mut aliases: Vec<_> = runner.aliases.iter().collect();
+            aliases.sort();
+            for (name, value) in aliases {
+                writeln!(out, "alias {name}='{value}'")?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) => {
+                    runner.aliases.insert(name.to_string(), value.to_string());
+                }
+                None => match runner.aliases.get(arg) {
+                    Some(value) => writeln!(out, "alias {arg}='{value}'")?,
+                    None => {
+                        print_error(format_args!("alias: {arg}: not found"));
+                        status = 1;
+                    }
+                },
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Remove aliases
+struct Unalias;
+
+impl Builtin for Unalias {
+    fn name(&self) -> &str {
+        "unalias"
+    }
+
+    fn help(&self) -> &str {
+        "Remove aliases\n\
+         \n\
+         usage: unalias [-a] name ...\n\
+         \n\
+         Removes the named aliases, or all of them with -a."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        if args.first().is_some_and(|arg| arg == "-a") {
+            runner.aliases.clear();
+            return Ok(0);
+        }
+        if args.is_empty() {
+            return Err(ShellError::InvalidArgument(
+                "unalias: usage: unalias [-a] name ...".into(),
+            ));
+        }
+
+        let mut status = 0;
+        for name in args {
+            if runner.aliases.remove(name).is_none() {
+                print_error(format_args!("unalias: {name}: not found"));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Leave loops with `break`, or go on with their next round with `continue`
+struct LoopControl(&'static str);
+
+impl Builtin for LoopControl {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn help(&self) -> &str {
+        if self.0 == "break" {
+            "Leave a loop\n\
+             \n\
+             usage: break [n]\n\
+             \n\
+             Leaves the n innermost loops, 1 by default."
+        } else {
+            "Go on with the next round of a loop\n\
+             \n\
+             usage: continue [n]\n\
+             \n\
+             Skips the rest of the innermost loop, or with n, leaves n - 1 loops\n\
+             and goes on with the next round of the one around them."
+        }
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let levels = match args.first() {
+            Some(levels) => match levels.parse() {
+                Ok(levels) if levels > 0 => levels,
+                _ => {
+                    return Err(ShellError::InvalidArgument(format!(
+                        "{}: {levels}: loop count out of range",
+                        self.0
+                    )))
+                }
+            },
+            None => 1,
+        };
+        if runner.loops == 0 {
+            return Err(ShellError::InvalidArgument(format!(
+                "{}: only meaningful in a `for', `while' or `until' loop",
+                self.0
+            )));
+        }
+        // Like in bash, leaving more loops than there are leaves them all
+        let levels = levels.min(runner.loops);
+        runner.flow = Some(if self.0 == "break" {
+            Flow::Break(levels)
+        } else {
+            Flow::Continue(levels)
+        });
+        Ok(0)
+    }
+}
+
+/// Change the working directory
+///
+/// Goes home without an argument, and back to the previous directory with
+/// `-`, printing it like bash does.
+struct Cd;
+
+impl Builtin for Cd {
+    fn name(&self) -> &str {
+        "cd"
+    }
+
+    fn help(&self) -> &str {
+        "Change the working directory\n\
+         \n\
+         usage: cd [dir | -]\n\
+         \n\
+         Goes to $HOME without an argument, and back to the previous directory\n\
+         with `-`, printing it."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let path = match args.first().map(String::as_str) {
+            Some("-") => runner
+                .prev_dir
+                .clone()
+                .ok_or_else(|| ShellError::InvalidArgument("cd: OLDPWD not set".into()))?,
+            Some(path) => PathBuf::from(path),
+            None => runner
+                .var("HOME")
+                .map(PathBuf::from)
+                .or_else(env::home_dir)
+                .ok_or_else(|| ShellError::InvalidArgument("cd: HOME not set".into()))?,
+        };
+        match change_dir(runner, &path) {
+            #[cfg(unix)]
+            Err(ShellError::Io(e)) if e.raw_os_error() == Some(libc::ELOOP) => {
+                return Err(ShellError::InvalidArgument(
+                    "cd: too many levels of symbolic links".into(),
+                ));
+            }
+            result => result?,
+        }
+        if args.first().is_some_and(|arg| arg == "-") {
+            writeln!(out, "{}", runner.pwd.display())?;
+        }
+
+        Ok(0)
+    }
+}
+
+/// Make `path`, relative to the current directory, the new working
+/// directory
+fn change_dir(runner: &mut CommandRunner, path: &Path) -> Result<()> {
+    let pwd = canonicalize(&runner.pwd.join(path))?;
+    runner.confine(&pwd)?;
+    let previous = std::mem::replace(&mut runner.pwd, pwd);
+
+    // Keep the variables in sync, so that child processes see them too
+    for (name, dir) in [("PWD", &runner.pwd), ("OLDPWD", &previous)] {
+        runner.vars.insert(name.into(), dir.display().to_string());
+        runner.exported.insert(name.into());
+    }
+    runner.prev_dir = Some(previous);
+    Ok(())
+}
+
+/// Clear the terminal, without running a `clear` program
+///
+/// Does nothing if stdout isn't a terminal.
+struct Clear;
+
+impl Builtin for Clear {
+    fn name(&self) -> &str {
+        "clear"
+    }
+
+    fn help(&self) -> &str {
+        "Clear the terminal screen\n\
+         \n\
+         usage: clear"
+    }
+
+    fn run(&self, _: &mut CommandRunner, _: &[String], out: &mut dyn Write) -> Result<i32> {
+        if std::io::stdout().is_terminal() {
+            // Clear the screen and move the cursor to the top left
+            write!(out, "\x1b[2J\x1b[H")?;
+        }
+        Ok(0)
+    }
+}
+
+/// Set what the arguments of commands complete to at the prompt
+struct Complete;
+
+impl Builtin for Complete {
+    fn name(&self) -> &str {
+        "complete"
+    }
+
+    fn help(&self) -> &str {
+        "Set what the arguments of commands complete to\n\
+         \n\
+         usage: complete [-p] [-r] [-W words] [name ...]\n\
+         \n\
+         With -W, the arguments of the named commands complete to the words\n\
+         in `words` rather than to file names. This also applies after\n\
+         wrappers like `sudo git`. -r removes what was set for the named\n\
+         commands, or for all of them. Without options, or with -p, lists\n\
+         what is set."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let usage = || {
+            ShellError::InvalidArgument(
+                "complete: usage: complete [-p] [-r] [-W words] [name ...]".into(),
+            )
+        };
+        match args.first().map(String::as_str) {
+            None | Some("-p") => {
+                let names = args.get(1..).unwrap_or_default();
+                let mut completions: Vec<_> = runner
+                    .completions
+                    .iter()
+                    .filter(|(name, _)| names.is_empty() || names.contains(name))
+                    .collect();
+                completions.sort();
+                for (name, words) in completions {
+                    writeln!(out, "complete -W '{}' {name}", words.join(" "))?;
+                }
+                Ok(0)
+            }
+            Some("-r") if args.len() == 1 => {
+                runner.completions.clear();
+                Ok(0)
+            }
+            Some("-r") => {
+                let mut status = 0;
+                for name in &args[1..] {
+                    if runner.completions.remove(name).is_none() {
+                        print_error(format_args!(
+                            "complete: {name}: no completion specification"
+                        ));
+                        status = 1;
+                    }
+                }
+                Ok(status)
+            }
+            Some("-W") => {
+                let (Some(words), names) = (args.get(1), args.get(2..).unwrap_or_default()) else {
+                    return Err(ShellError::InvalidArgument(
+                        "complete: -W: option requires an argument".into(),
+                    ));
+                };
+                if names.is_empty() {
+                    return Err(usage());
+                }
+                let words: Vec<String> = words.split_whitespace().map(String::from).collect();
+                for name in names {
+                    runner.completions.insert(name.clone(), words.clone());
+                }
+                Ok(0)
+            }
+            Some(_) => Err(usage()),
+        }
+    }
+}
+
+/// Start a command in the background with pipes to its stdin and stdout:
+/// `coproc NAME command [args]`
+///
+/// `${NAME[0]}` is the descriptor to read its output from and `${NAME[1]}`
+/// the one to write its input to, as in `echo hi >&${NAME[1]}`.
+#[cfg(unix)]
+struct Coproc;
+
+#[cfg(unix)]
+impl Builtin for Coproc {
+    fn name(&self) -> &str {
+        "coproc"
+    }
+
+    fn help(&self) -> &str {
+        "Run a command in the background with pipes to it\n\
+         \n\
+         usage: coproc NAME command [args]\n\
+         \n\
+         ${NAME[0]} is the descriptor to read its output from and ${NAME[1]}\n\
+         the one to write its input to, and $NAME_PID its process id."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        use std::os::fd::{AsRawFd, OwnedFd};
+
+        let [name, bin, args @ ..] = args else {
+            return Err(ShellError::InvalidArgument(
+                "coproc: usage: coproc NAME command [args]".into(),
+            ));
+        };
+        if !is_name(name) {
+            return Err(ShellError::InvalidArgument(format!(
+                "coproc: `{name}': not a valid identifier"
+            )));
+        }
+
+        let command = Command {
+            assignments: vec![],
+            bin: bin.clone(),
+            args: args.to_vec(),
+            redirects: vec![],
+            program: runner.resolve_in_path(bin),
+        };
+        let command = runner.vet(command)?;
+        let mut child = command.spawn_piped(&runner.pwd, &runner.env(), runner.umask)?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            unreachable!("coproc was spawned with piped stdio");
+        };
+        let pipes = [OwnedFd::from(stdout), OwnedFd::from(stdin)];
+
+        runner.arrays.insert(
+            name.clone(),
+            pipes.iter().map(|fd| fd.as_raw_fd().to_string()).collect(),
+        );
+        runner
+            .vars
+            .insert(format!("{name}_PID"), child.id().to_string());
+        runner
+            .coprocs
+            .insert(name.clone(), pipes.map(|fd| Some(std::sync::Arc::new(fd))));
+
+        let job = runner.jobs.add(
+            format!("coproc {name} {command}"),
+            Process::Child(child),
+            None,
+        );
+        eprintln!("[{}] {}", job.id, job.pid().unwrap_or_default());
+        Ok(0)
+    }
+}
+
+/// List the directory stack
+struct Dirs;
+
+impl Builtin for Dirs {
+    fn name(&self) -> &str {
+        "dirs"
+    }
+
+    fn help(&self) -> &str {
+        "List the directory stack\n\
+         \n\
+         usage: dirs"
+    }
+
+    fn run(&self, runner: &mut CommandRunner, _: &[String], out: &mut dyn Write) -> Result<i32> {
+        print_dirs(runner, out)?;
+        Ok(0)
+    }
+}
+
+/// Print the arguments, separated by spaces
+///
+/// `-n` leaves out the trailing newline, and `-e` turns on backslash escapes
+/// like `\n` and `\t`, with `\c` stopping the output.
+struct Echo;
+
+impl Builtin for Echo {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn help(&self) -> &str {
+        "Print the arguments\n\
+         \n\
+         usage: echo [-neE] [arg ...]\n\
+         \n\
+         The arguments are separated by spaces and followed by a newline,\n\
+         unless -n is given. -e interprets backslash escapes like \\n and \\t,\n\
+         and -E, the default, turns that off again."
+    }
+
+    fn run(&self, _: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (mut newline, mut escapes) = (true, false);
+        let mut words = args;
+        // Like in bash, anything that isn't only made of flags is printed
+        while let Some((flag, rest)) = words.split_first() {
+            let Some(letters) = flag.strip_prefix('-') else {
+                break;
+            };
+            if letters.is_empty() || !letters.chars().all(|c| "neE".contains(c)) {
+                break;
+            }
+            for letter in letters.chars() {
+                match letter {
+                    'n' => newline = false,
+                    'e' => escapes = true,
+                    _ => escapes = false,
+                }
+            }
+            words = rest;
+        }
+
+        let text = words.join(" ");
+        if escapes {
+            let (text, stop) = unescape(&text);
+            write!(out, "{text}")?;
+            if stop {
+                return Ok(0);
+            }
+        } else {
+            write!(out, "{text}")?;
+        }
+        if newline {
+            writeln!(out)?;
+        }
+        Ok(0)
+    }
+}
+
+/// Interpret backslash escapes for `echo -e`
+///
+/// Also returns whether `\c` asked to stop printing.
+fn unescape(text: &str) -> (String, bool) {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('\\') => '\\',
+            Some('a') => '\x07',
+            Some('b') => '\x08',
+            Some('c') => return (unescaped, true),
+            Some('e') => '\x1b',
+            Some('f') => '\x0c',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('v') => '\x0b',
+            // Anything else is left alone
+            Some(other) => {
+                unescaped.push('\\');
+                other
+            }
+            None => '\\',
+        };
+        unescaped.push(escaped);
+    }
+    (unescaped, false)
+}
+
+/// Turn builtins off (`enable -n name`) so that the external command of the
+/// same name runs instead, or back on (`enable name`)
+struct Enable;
+
+impl Builtin for Enable {
+    fn name(&self) -> &str {
+        "enable"
+    }
+
+    fn help(&self) -> &str {
+        "Turn builtins on or off\n\
+         \n\
+         usage: enable [-n] [name ...]\n\
+         \n\
+         With -n, the named builtins are turned off so that the external command\n\
+         of the same name runs instead. Without names, lists the enabled\n\
+         builtins, or the disabled ones with -n."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (disable, names) = match args.split_first() {
+            Some((flag, names)) if flag == "-n" => (true, names),
+            _ => (false, args),
+        };
+
+        if names.is_empty() {
+            // List the enabled builtins, or the disabled ones with `-n`
+            let mut listed: Vec<_> = runner
+                .builtins
+                .keys()
+                .filter(|name| runner.disabled.contains(*name) == disable)
+                .collect();
+            listed.sort();
+            let flag = if disable { "-n " } else { "" };
+            for name in listed {
+                writeln!(out, "enable {flag}{name}")?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for name in names {
+            if !runner.builtins.contains_key(name) {
+                print_error(format_args!("enable: {name}: not a shell builtin"));
+                status = 1;
+            } else if disable {
+                runner.disabled.insert(name.clone());
+            } else {
+                runner.disabled.remove(name);
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Run a command in place of the shell, which then exits with its status
+///
+/// Where the shell may, the runner replaces its process with the command
+/// instead. Without a command, the redirections stay open in the shell:
+/// the runner takes care of those too.
+struct Exec;
+
+impl Builtin for Exec {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn help(&self) -> &str {
+        "Replace the shell with a command, or open descriptors\n\
+         \n\
+         usage: exec [command [args]] [redirections]\n\
+         \n\
+         With a command, the shell is replaced by it, or when embedded, exits\n\
+         with its status once it is done. If it can't be run, the shell goes\n\
+         on. Without one, redirections like `3< file` stay open for the rest of\n\
+         the session, and `3<&-` closes them again."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let Some((bin, args)) = args.split_first() else {
+            return Ok(0);
+        };
+        let command = Command {
+            assignments: vec![],
+            bin: bin.clone(),
+            args: args.to_vec(),
+            redirects: vec![],
+            program: runner.resolve_in_path(bin),
+        };
+        // The shell only goes away along with a command that ran
+        let output = runner.run_command(&command, out)?;
+        runner.exit = Some(output.status);
+        out.write_all(&output.stdout)?;
+        Ok(output.status)
+    }
+}
+
+struct Exit;
+
+impl Builtin for Exit {
+    fn name(&self) -> &str {
+        "exit"
+    }
+
+    fn help(&self) -> &str {
+        "Exit the shell\n\
+         \n\
+         usage: exit [status]\n\
+         \n\
+         Exits with the status of the last command if none is given."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let exit_code = match args.first() {
+            Some(exit_code) => exit_code.parse().map_err(|_| {
+                ShellError::InvalidArgument(format!("exit: {exit_code}: numeric argument required"))
+            })?,
+            None => runner.last_status,
+        };
+        runner.exit = Some(exit_code);
+        Ok(exit_code)
+    }
+}
+
+/// Mark variables to be passed to child processes, optionally setting them
+struct Export;
+
+impl Builtin for Export {
+    fn name(&self) -> &str {
+        "export"
+    }
+
+    fn help(&self) -> &str {
+        "Pass variables to child processes\n\
+         \n\
+         usage: export [name[=value] ...]\n\
+         \n\
+         Without arguments, lists the exported variables."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if args.is_empty() {
+            let mut exported: Vec<_> = runner.env().vars.into_iter().collect();
+            exported.sort();
+            for (name, value) in exported {
+                writeln!(out, "export {name}=\"{value}\"")?;
+            }
+            return Ok(0);
+        }
+
+        for arg in args {
+            let name = match arg.split_once('=') {
+                Some((name, value)) => {
+                    runner.set_var(name, value.to_string());
+                    name
+                }
+                None => arg,
+            };
+            if !is_name(name) {
+                return Err(ShellError::InvalidArgument(format!(
+                    "export: `{arg}': not a valid identifier"
+                )));
+            }
+            if !runner.vars.contains_key(name) {
+                if let Some(value) = runner.var(name) {
+                    runner.vars.insert(name.to_string(), value);
+                }
+            }
+            runner.exported.insert(name.to_string());
+        }
+        Ok(0)
+    }
+}
+
+/// Look up the job number given as `spec`, like `%2` or `2`
+///
+/// Without `spec`, this is the most recent job.
+fn job_id(runner: &mut CommandRunner, builtin: &str, spec: Option<&String>) -> Result<usize> {
+    runner.jobs.poll()?;
+    let id = match spec {
+        Some(spec) => spec.strip_prefix('%').unwrap_or(spec).parse().ok(),
+        None => runner.jobs.current(),
+    };
+    match id {
+        Some(id) if runner.jobs.iter().any(|job| job.id == id) => Ok(id),
+        _ => Err(ShellError::InvalidArgument(format!(
+            "{builtin}: {}: no such job",
+            spec.map_or("current", |spec| spec.as_str())
+        ))),
+    }
+}
+
+/// Bring a job to the foreground and wait for it
+///
+/// The job keeps the stdio it was started with: background jobs never read
+/// from the terminal, but their output already goes there.
+struct Fg;
+
+impl Builtin for Fg {
+    fn name(&self) -> &str {
+        "fg"
+    }
+
+    fn help(&self) -> &str {
+        "Bring a job to the foreground\n\
+         \n\
+         usage: fg [%job]\n\
+         \n\
+         Without a job, brings back the most recent one."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let id = job_id(runner, "fg", args.first())?;
+        let Some(mut job) = runner.jobs.remove(id) else {
+            unreachable!("job {id} was just found");
+        };
+        writeln!(out, "{}", job.command)?;
+        out.flush()?;
+        #[cfg(unix)]
+        if let JobState::Stopped(_) = job.state {
+            job.resume()?;
+        }
+
+        // Ctrl-C and Ctrl-Z are for the job now
+        interrupt::set_foreground(job.group);
+        let status = job.wait();
+        interrupt::set_foreground(None);
+        if let JobState::Stopped(_) = job.state {
+            eprintln!("\n[{}]+  {:<24}{}", job.id, job.state, job.command);
+            runner.jobs.restore(job);
+        }
+        status
+    }
+}
+
+/// Let a stopped job carry on in the background
+#[cfg(unix)]
+struct Bg;
+
+#[cfg(unix)]
+impl Builtin for Bg {
+    fn name(&self) -> &str {
+        "bg"
+    }
+
+    fn help(&self) -> &str {
+        "Resume a stopped job in the background\n\
+         \n\
+         usage: bg [%job]\n\
+         \n\
+         Without a job, resumes the most recent one."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let id = job_id(runner, "bg", args.first())?;
+        let current = runner.jobs.current();
+        let Some(job) = runner.jobs.get_mut(id) else {
+            unreachable!("job {id} was just found");
+        };
+        match job.state {
+            JobState::Stopped(_) => {
+                job.resume()?;
+                let marker = if Some(id) == current { "+" } else { " " };
+                writeln!(out, "[{id}]{marker} {} &", job.command)?;
+                Ok(0)
+            }
+            JobState::Running => {
+                print_error(format!("bg: job {id} already in background"));
+                Ok(0)
+            }
+            JobState::Done(_) => Err(ShellError::InvalidArgument(format!(
+                "bg: job {id} has terminated"
+            ))),
+        }
+    }
+}
+
+/// List the commands remembered from `PATH`, forget them all with `-r`,
+/// or look up the ones given
+struct Hash;
+
+impl Builtin for Hash {
+    fn name(&self) -> &str {
+        "hash"
+    }
+
+    fn help(&self) -> &str {
+        "Remember where commands are in PATH\n\
+         \n\
+         usage: hash [-r] [name ...]\n\
+         \n\
+         Commands are remembered the first time they run. Without arguments,\n\
+         lists them. -r forgets them all, and names are looked up again. With\n\
+         `set -o checkhash`, a remembered command that has gone away is looked\n\
+         up again instead of failing."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let names = match args.split_first() {
+            Some((flag, names)) if flag == "-r" => {
+                runner.path_cache.clear();
+                names
+            }
+            _ if args.is_empty() => {
+                if runner.path_cache.is_empty() {
+                    print_error("hash: hash table empty");
+                }
+                let mut commands: Vec<_> = runner.path_cache.iter().collect();
+                commands.sort();
+                for (name, path) in commands {
+                    writeln!(out, "{name}\t{}", path.display())?;
+                }
+                return Ok(0);
+            }
+            _ => args,
+        };
+
+        let mut status = 0;
+        for name in names {
+            runner.path_cache.remove(name);
+            if !runner.is_builtin(name) && runner.resolve_in_path(name).is_none() {
+                print_error(format_args!("hash: {name}: not found"));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Describe the builtins, or how to use the ones given
+struct Help;
+
+impl Builtin for Help {
+    fn name(&self) -> &str {
+        "help"
+    }
+
+    fn help(&self) -> &str {
+        "Show help for builtins\n\
+         \n\
+         usage: help [name]\n\
+         \n\
+         Without a name, lists the builtins with a short description of each."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if args.is_empty() {
+            let mut builtins: Vec<_> = runner.builtins.iter().collect();
+            builtins.sort_by_key(|(name, _)| *name);
+            for (name, builtin) in builtins {
+                let summary = builtin.help().lines().next().unwrap_or_default();
+                writeln!(out, "{name:<10} {summary}")?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for name in args {
+            match runner.builtins.get(name) {
+                Some(builtin) => writeln!(out, "{}", builtin.help())?,
+                None => {
+                    print_error(format_args!("help: no help topic for {name}"));
+                    status = 1;
+                }
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// List the commands run so far
+struct History;
+
+impl Builtin for History {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn help(&self) -> &str {
+        "List or edit the command history\n\
+         \n\
+         usage: history [-d offset | -d start-end | --json]\n\
+         \n\
+         Without arguments, lists the commands run so far. -d deletes an entry or\n\
+         a range of them, and --json prints the history as JSON, with the time\n\
+         and exit status of each command where known."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        match args {
+            [] => (),
+            [flag, position] if flag == "-d" => {
+                let range = history_range(position, runner.history.len())?;
+                runner.history.remove(range)?;
+                return Ok(0);
+            }
+            [flag] if flag == "--json" => {
+                write_history_json(runner, out)?;
+                return Ok(0);
+            }
+            _ => {
+                return Err(ShellError::InvalidArgument(
+                    "history: usage: history [-d offset | -d start-end | --json]".into(),
+                ));
+            }
+        }
+
+        // Prefix entries with their time if a format is set
+        let format = runner.var("HISTTIMEFORMAT");
+        for (number, entry) in (1..).zip(runner.history.iter()) {
+            write!(out, "{number:5}  ")?;
+            if let Some(format) = &format {
+                match entry.time {
+                    Some(time) => write!(out, "{}", format_time(time, format))?,
+                    None => write!(out, "?? ")?,
+                }
+            }
+            writeln!(out, "{}", entry.command)?;
+        }
+        Ok(0)
+    }
+}
+
+/// Write the history as a JSON array, for `history --json`
+///
+/// Each entry is an object with its `command`, and its `time` in seconds
+/// since the epoch and exit `status` or `null` where those are unknown.
+fn write_history_json(runner: &CommandRunner, out: &mut dyn Write) -> Result<()> {
+    let entries: Vec<_> = runner.history.iter().collect();
+    serde_json::to_writer_pretty(&mut *out, &entries).map_err(std::io::Error::from)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// The entries, counting from 0, that `history -d` refers to with
+/// `position`, a number or a range like `3-5` counting from 1
+fn history_range(position: &str, len: usize) -> Result<std::ops::RangeInclusive<usize>> {
+    let out_of_range = || {
+        ShellError::InvalidArgument(format!(
+            "history: {position}: history position out of range"
+        ))
+    };
+    let number = |n: &str| match n.parse::<usize>() {
+        Ok(n) if (1..=len).contains(&n) => Ok(n - 1),
+        Ok(_) => Err(out_of_range()),
+        Err(_) => Err(ShellError::InvalidArgument(format!(
+            "history: {position}: numeric argument required"
+        ))),
+    };
+
+    let (start, end) = match position.split_once('-') {
+        Some((start, end)) => (number(start)?, number(end)?),
+        None => (number(position)?, number(position)?),
+    };
+    if start > end {
+        return Err(out_of_range());
+    }
+    Ok(start..=end)
+}
+
+/// List background jobs
+struct Jobs;
+
+impl Builtin for Jobs {
+    fn name(&self) -> &str {
+        "jobs"
+    }
+
+    fn help(&self) -> &str {
+        "List background jobs\n\
+         \n\
+         usage: jobs"
+    }
+
+    fn run(&self, runner: &mut CommandRunner, _: &[String], out: &mut dyn Write) -> Result<i32> {
+        runner.jobs.poll()?;
+        let current = runner.jobs.current();
+        for job in runner.jobs.iter() {
+            let marker = if Some(job.id) == current { "+" } else { " " };
+            let command = match job.state {
+                JobState::Running => format!("{} &", job.command),
+                JobState::Stopped(_) | JobState::Done(_) => job.command.clone(),
+            };
+            writeln!(out, "[{}]{marker}  {:<24}{command}", job.id, job.state)?;
+        }
+        // Finished jobs are only reported once
+        runner.jobs.reap()?;
+        Ok(0)
+    }
+}
+
+/// Send a signal, `TERM` unless another one is given, to jobs and processes
+///
+/// Targets are either job specs like `%1` or process IDs.
+#[cfg(unix)]
+struct Kill;
+
+#[cfg(unix)]
+impl Builtin for Kill {
+    fn name(&self) -> &str {
+        "kill"
+    }
+
+    fn help(&self) -> &str {
+        "Send a signal to jobs or processes\n\
+         \n\
+         usage: kill [-s signal | -signal] %job | pid ...\n\
+         \n\
+         The signal is a name like TERM or KILL, with or without SIG in front,\n\
+         or a number. It defaults to TERM."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let (signal, targets) = match args {
+            [flag, signal, targets @ ..] if flag == "-s" => (signal_number(signal)?, targets),
+            [flag, targets @ ..] if flag.len() > 1 && flag.starts_with('-') => {
+                (signal_number(&flag[1..])?, targets)
+            }
+            targets => (libc::SIGTERM, targets),
+        };
+        if targets.is_empty() {
+            return Err(ShellError::InvalidArgument(
+                "kill: usage: kill [-s signal | -signal] %job | pid ...".into(),
+            ));
+        }
+
+        let mut status = 0;
+        for target in targets {
+            if let Err(e) = signal_target(runner, target, signal) {
+                print_error(e);
+                status = 1;
+            }
+        }
+        // Catch up with the jobs that ended because of it
+        runner.jobs.poll()?;
+        Ok(status)
+    }
+}
+
+/// Signals `kill` knows by name
+#[cfg(unix)]
+const SIGNALS: [(&str, i32); 13] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+];
+
+/// The number of a signal given by number or name, like `9`, `KILL` or
+/// `SIGKILL`
+#[cfg(unix)]
+fn signal_number(spec: &str) -> Result<i32> {
+    let name = spec.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    spec.parse()
+        .ok()
+        .filter(|number| *number >= 0)
+        .or_else(|| {
+            SIGNALS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(_, number)| *number)
+        })
+        .ok_or_else(|| {
+            ShellError::InvalidArgument(format!("kill: {spec}: invalid signal specification"))
+        })
+}
+
+/// Send `signal` to a job given as `%n`, or a process ID
+#[cfg(unix)]
+fn signal_target(runner: &mut CommandRunner, target: &str, signal: i32) -> Result<()> {
+    if target.starts_with('%') {
+        let id = job_id(runner, "kill", Some(&target.to_string()))?;
+        let Some(job) = runner.jobs.get_mut(id) else {
+            unreachable!("job {id} was just found");
+        };
+        job.signal(signal)?;
+        // A stopped job would only get the signal once it carries on
+        if let (JobState::Stopped(_), libc::SIGTERM | libc::SIGHUP) = (job.state, signal) {
+            job.resume()?;
+        }
+        return Ok(());
+    }
+
+    let pid: u32 = target.parse().map_err(|_| {
+        ShellError::InvalidArgument(format!(
+            "kill: {target}: arguments must be process or job IDs"
+        ))
+    })?;
+
+    let pid = libc::pid_t::try_from(pid).map_err(|_| {
+        ShellError::InvalidArgument(format!(
+            "kill: {target}: arguments must be process or job IDs"
+        ))
+    })?;
+    // SAFETY: kill only sends a signal, and reports failures through errno
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(ShellError::InvalidArgument(format!("kill: ({pid}) - {e}")));
+    }
+    Ok(())
+}
+
+/// Evaluate each argument as an arithmetic expression
+struct Let;
+
+impl Builtin for Let {
+    fn name(&self) -> &str {
+        "let"
+    }
+
+    fn help(&self) -> &str {
+        "Evaluate arithmetic expressions\n\
+         \n\
+         usage: let expression ...\n\
+         \n\
+         Exits with status 1 if the last expression is 0, and 0 otherwise."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        if args.is_empty() {
+            return Err(ShellError::InvalidArgument(
+                "let: expression expected".into(),
+            ));
+        }
+        let mut value = 0;
+        for arg in args {
+            value = arith::eval(arg, runner)?;
+        }
+        Ok(arith::status(value))
+    }
+}
+
+/// Go back to the directory on top of the directory stack, removing it
+struct Popd;
+
+impl Builtin for Popd {
+    fn name(&self) -> &str {
+        "popd"
+    }
+
+    fn help(&self) -> &str {
+        "Go back to the directory on top of the directory stack\n\
+         \n\
+         usage: popd"
+    }
+
+    fn run(&self, runner: &mut CommandRunner, _: &[String], out: &mut dyn Write) -> Result<i32> {
+        let Some(dir) = runner.dir_stack.last() else {
+            return Err(ShellError::InvalidArgument(
+                "popd: directory stack empty".into(),
+            ));
+        };
+        change_dir(runner, &dir.clone())?;
+        runner.dir_stack.pop();
+        print_dirs(runner, out)?;
+        Ok(0)
+    }
+}
+
+/// Print the environment that commands get, or the values of some of its
+/// variables
+struct Printenv;
+
+impl Builtin for Printenv {
+    fn name(&self) -> &str {
+        "printenv"
+    }
+
+    fn help(&self) -> &str {
+        "Print the environment of commands\n\
+         \n\
+         usage: printenv [-s] [name ...]\n\
+         \n\
+         Without names, prints all variables as NAME=value, sorted by name with\n\
+         -s. With names, prints their values, and fails if one isn't set. The\n\
+         environment is the one the shell started with, plus exported variables."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let (sort, names) = match args.split_first() {
+            Some((flag, names)) if flag == "-s" => (true, names),
+            _ => (false, args),
+        };
+
+        // Exported variables override inherited ones, and come after them
+        let Environment {
+            vars: mut exported,
+            unset,
+        } = runner.env();
+        let mut environment: Vec<(String, String)> = env::vars_os()
+            .filter(|(name, _)| !name.to_str().is_some_and(|name| unset.contains(name)))
+            .map(|(name, value)| {
+                let name = name.to_string_lossy().into_owned();
+                let value = exported
+                    .remove(&name)
+                    .unwrap_or_else(|| value.to_string_lossy().into_owned());
+                (name, value)
+            })
+            .collect();
+        let mut added: Vec<_> = exported.into_iter().collect();
+        added.sort();
+        environment.extend(added);
+
+        if names.is_empty() {
+            if sort {
+                environment.sort();
+            }
+            for (name, value) in environment {
+                writeln!(out, "{name}={value}")?;
+            }
+            return Ok(0);
+        }
+
+        let mut status = 0;
+        for name in names {
+            match environment.iter().find(|(candidate, _)| candidate == name) {
+                Some((_, value)) => writeln!(out, "{value}")?,
+                None => status = 1,
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Change to a directory, keeping the current one on the directory stack
+///
+/// Without an argument, swaps the working directory with the one on top of
+/// the stack.
+struct Pushd;
+
+impl Builtin for Pushd {
+    fn name(&self) -> &str {
+        "pushd"
+    }
+
+    fn help(&self) -> &str {
+        "Change directory, keeping the current one on the stack\n\
+         \n\
+         usage: pushd [dir]\n\
+         \n\
+         Without a directory, swaps the working directory with the one on top of\n\
+         the stack."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let previous = runner.pwd.clone();
+        match args.first() {
+            Some(path) => change_dir(runner, Path::new(path))?,
+            None => {
+                let Some(dir) = runner.dir_stack.pop() else {
+                    return Err(ShellError::InvalidArgument(
+                        "pushd: no other directory".into(),
+                    ));
+                };
+                if let Err(e) = change_dir(runner, &dir) {
+                    runner.dir_stack.push(dir);
+                    return Err(e);
+                }
+            }
+        }
+        runner.dir_stack.push(previous);
+        print_dirs(runner, out)?;
+        Ok(0)
+    }
+}
+
+struct Pwd;
+
+impl Builtin for Pwd {
+    fn name(&self) -> &str {
+        "pwd"
+    }
+
+    fn help(&self) -> &str {
+        "Print the working directory\n\
+         \n\
+         usage: pwd"
+    }
+
+    fn run(&self, runner: &mut CommandRunner, _: &[String], out: &mut dyn Write) -> Result<i32> {
+        writeln!(out, "{}", runner.pwd.display())?;
+        Ok(0)
+    }
+}
+
+/// Read a line of input into variables, splitting it into fields by `IFS`
+///
+/// The last variable gets all the remaining fields, and `REPLY` the whole
+/// line if no variables are named. Unless `-r` is given, a backslash
+/// escapes the next character and joins a line with the following one.
+struct Read;
+
+impl Builtin for Read {
+    fn name(&self) -> &str {
+        "read"
+    }
+
+    fn help(&self) -> &str {
+        "Read a line of input into variables\n\
+         \n\
+         usage: read [-r] [-u fd] [name ...]\n\
+         \n\
+         The line is split into fields by $IFS, and the last variable gets all\n\
+         the remaining fields. Without names, the whole line goes into $REPLY.\n\
+         Unless -r is given, a backslash escapes the next character and joins a\n\
+         line with the following one. -u reads from descriptor fd instead of\n\
+         stdin, like one opened with `exec 3< file`."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let mut raw = false;
+        let mut fd = None;
+        let mut names = args;
+        while let Some((flag, rest)) = names.split_first() {
+            match flag.as_str() {
+                "-r" => raw = true,
+                "-u" => {
+                    let Some((number, rest)) = rest.split_first() else {
+                        return Err(ShellError::InvalidArgument(
+                            "read: -u: option requires an argument".into(),
+                        ));
+                    };
+                    fd = Some(number.parse().map_err(|_| {
+                        ShellError::InvalidArgument(format!(
+                            "read: {number}: invalid file descriptor specification"
+                        ))
+                    })?);
+                    names = rest;
+                    continue;
+                }
+                "--" => {
+                    names = rest;
+                    break;
+                }
+                flag if flag.starts_with('-') => {
+                    return Err(ShellError::InvalidArgument(format!(
+                        "read: {flag}: invalid option"
+                    )));
+                }
+                _ => break,
+            }
+            names = rest;
+        }
+        if let Some(name) = names.iter().find(|name| !is_name(name)) {
+            return Err(ShellError::InvalidArgument(format!(
+                "read: `{name}': not a valid identifier"
+            )));
+        }
+
+        let (line, complete) = read_line(runner, raw, fd)?;
+        if names.is_empty() {
+            let line = line.into_iter().map(|(c, _)| c).collect();
+            runner.set_var("REPLY", line);
+        } else {
+            let ifs = runner.var("IFS").unwrap_or(" \t\n".into());
+            let mut fields = split_fields(&line, &ifs, names.len()).into_iter();
+            for name in names {
+                runner.set_var(name, fields.next().unwrap_or_default());
+            }
+        }
+        // Like in bash, hitting EOF fails even if part of a line was read
+        Ok(if complete { 0 } else { 1 })
+    }
+}
+
+/// Read a line for `read`, from descriptor `fd` if given, where stdin is
+/// redirected or else the shell's own stdin
+///
+/// Reads a byte at a time so that nothing after the line is used up.
+/// Each character comes with whether it was escaped by a backslash, which
+/// keeps it from splitting fields. Also returns whether the line ended in
+/// a newline rather than EOF.
+fn read_line(
+    runner: &CommandRunner,
+    raw: bool,
+    fd: Option<u32>,
+) -> Result<(Vec<(char, bool)>, bool)> {
+    let mut input: Box<dyn std::io::Read> = match (fd, &runner.stdin) {
+        (Some(fd), _) => Box::new(dup_fd(fd).map_err(|_| {
+            ShellError::InvalidArgument(format!("read: {fd}: invalid file descriptor"))
+        })?),
+        (None, Some(file)) => Box::new(file.try_clone()?),
+        // Not through `std::io::stdin()`, whose buffer would take the lines
+        // after this one away from the shell
+        #[cfg(unix)]
+        (None, None) => Box::new(dup_fd(0)?),
+        #[cfg(not(unix))]
+        (None, None) => Box::new(std::io::stdin()),
+    };
+
+    let mut bytes = vec![];
+    let mut complete = false;
+    let mut byte = [0];
+    while input.read(&mut byte)? > 0 {
+        let byte = byte[0];
+        if byte != b'\n' {
+            bytes.push(byte);
+            continue;
+        }
+        let backslashes = bytes.iter().rev().take_while(|&&b| b == b'\\').count();
+        if raw || backslashes % 2 == 0 {
+            complete = true;
+            break;
+        }
+        // An escaped newline goes on with the next line
+        bytes.pop();
+    }
+
+    let mut line = vec![];
+    let text = String::from_utf8_lossy(&bytes);
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !raw => {
+                if let Some(c) = chars.next() {
+                    line.push((c, true));
+                }
+            }
+            c => line.push((c, false)),
+        }
+    }
+    Ok((line, complete))
+}
+
+/// Split `line` into at most `count` fields, separated by characters in
+/// `ifs`
+///
+/// Runs of whitespace in `ifs` count as one separator and are trimmed at
+/// both ends, while any other character in it separates fields on its own.
+/// The last field holds the rest of the line, separators and all.
+fn split_fields(line: &[(char, bool)], ifs: &str, count: usize) -> Vec<String> {
+    let is_space = |&(c, escaped): &(char, bool)| !escaped && c.is_whitespace() && ifs.contains(c);
+    let is_separator = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+
+    let mut fields = vec![];
+    let mut rest = line;
+    while let Some(start) = rest.iter().position(|c| !is_space(c)) {
+        rest = &rest[start..];
+        if fields.len() + 1 == count {
+            let end = rest.iter().rposition(|c| !is_space(c)).map_or(0, |i| i + 1);
+            fields.push(rest[..end].iter().map(|(c, _)| c).collect());
+            break;
+        }
+        let end = rest.iter().position(is_separator).unwrap_or(rest.len());
+        fields.push(rest[..end].iter().map(|(c, _)| c).collect());
+        rest = &rest[end..];
+
+        // Skip the separator: whitespace around at most one other character
+        let spaces = rest.iter().take_while(|c| is_space(c)).count();
+        rest = &rest[spaces..];
+        if rest.first().is_some_and(is_separator) {
+            rest = &rest[1..];
+        }
+    }
+    fields
+}
+
+/// Turn options on (`set -o name` or `set -e`) or off (`set +o name`)
+struct Set;
+
+impl Builtin for Set {
+    fn name(&self) -> &str {
+        "set"
+    }
+
+    fn help(&self) -> &str {
+        "Turn shell options on or off\n\
+         \n\
+         usage: set [-o | +o] [option] | set -flags | set +flags\n\
+         \n\
+         Without arguments or with only -o, lists the options. -o turns an option\n\
+         on and +o turns it off, and single letters like -e, -n or +x do the same\n\
+         for errexit, noexec and xtrace. noexec prints commands instead of\n\
+         running them, and is ignored by interactive shells."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if args.is_empty() || args == ["-o"] {
+            for (name, enabled) in runner.options.list() {
+                writeln!(out, "{name}\t{}", if enabled { "on" } else { "off" })?;
+            }
+            return Ok(0);
+        }
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let enable = match flag.as_str() {
+                "-o" => true,
+                "+o" => false,
+                _ => {
+                    set_flags(runner, flag)?;
+                    continue;
+                }
+            };
+            let Some(name) = args.next() else {
+                return Err(ShellError::InvalidArgument(format!(
+                    "set: {flag}: option name required"
+                )));
+            };
+            let Some(option) = runner.options.get_mut(name) else {
+                return Err(ShellError::InvalidArgument(format!(
+                    "set: {name}: invalid option name"
+                )));
+            };
+            *option = enable;
+        }
+        Ok(0)
+    }
+}
+
+/// Apply single letter flags like `-e` or `+ex`
+fn set_flags(runner: &mut CommandRunner, flags: &str) -> Result<()> {
+    let invalid = || ShellError::InvalidArgument(format!("set: {flags}: invalid option"));
+    let (enable, letters) = match flags.split_at_checked(1) {
+        Some(("-", letters)) if !letters.is_empty() => (true, letters),
+        Some(("+", letters)) if !letters.is_empty() => (false, letters),
+        _ => return Err(invalid()),
+    };
+    for letter in letters.chars() {
+        let option = Options::long_name(letter)
+            .and_then(|name| runner.options.get_mut(name))
+            .ok_or_else(invalid)?;
+        *option = enable;
+    }
+    Ok(())
+}
+
+/// Tell what kind of command each name is, and where it is
+struct Type;
+
+impl Builtin for Type {
+    fn name(&self) -> &str {
+        "type"
+    }
+
+    fn help(&self) -> &str {
+        "Tell how command names would be interpreted\n\
+         \n\
+         usage: type name ...\n\
+         \n\
+         Says whether each name is an alias, a builtin or a program in PATH.\n\
+         Like for completion, wrappers with their options and variable\n\
+         assignments in front are skipped, so `type sudo git` is about git.\n\
+         Fails if one of the names isn't a command."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        if args.is_empty() {
+            return Err(ShellError::InvalidArgument(
+                "type: usage: type name ...".into(),
+            ));
+        }
+        let words: Vec<&str> = args.iter().map(String::as_str).collect();
+        let names = match command_position(&words) {
+            // Only wrappers, which are commands themselves
+            start if start == words.len() => &words[..],
+            start => &words[start..],
+        };
+
+        let mut status = 0;
+        for &name in names {
+            if let Some(value) = runner.aliases.get(name) {
+                writeln!(out, "{name} is aliased to `{value}'")?;
+            } else if runner.is_builtin(name) {
+                writeln!(out, "{name} is a shell builtin")?;
+            } else if let Some(path) = runner.resolve_in_path(name) {
+                writeln!(out, "{name} is {}", path.display())?;
+            } else if name.contains(std::path::is_separator)
+                && is_executable(&runner.pwd.join(name))
+            {
+                writeln!(out, "{name} is {name}")?;
+            } else {
+                print_error(format_args!("type: {name}: not found"));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Remove variables, so that they are neither expanded nor passed to
+/// commands anymore
+struct Unset;
+
+impl Builtin for Unset {
+    fn name(&self) -> &str {
+        "unset"
+    }
+
+    fn help(&self) -> &str {
+        "Remove variables\n\
+         \n\
+         usage: unset [-v] name ...\n\
+         \n\
+         Removes the named variables and arrays, including ones from the\n\
+         environment the shell started with. Names that aren't set are\n\
+         skipped."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let names = match args.split_first() {
+            Some((flag, names)) if flag == "-v" => names,
+            Some((flag, _)) if flag == "-f" => {
+                return Err(ShellError::InvalidArgument(
+                    "unset: -f: there are no shell functions".into(),
+                ));
+            }
+            _ => args,
+        };
+
+        let mut status = 0;
+        for name in names {
+            if is_name(name) {
+                runner.unset_var(name);
+            } else {
+                print_error(format_args!("unset: `{name}': not a valid identifier"));
+                status = 1;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Drop the first positional parameters, so that `$2` becomes `$1` and so on
+struct Shift;
+
+impl Builtin for Shift {
+    fn name(&self) -> &str {
+        "shift"
+    }
+
+    fn help(&self) -> &str {
+        "Drop the first positional parameters\n\
+         \n\
+         usage: shift [n]\n\
+         \n\
+         Drops $1 to $n, 1 by default, and renumbers the rest. Fails without\n\
+         dropping any if there are fewer than n."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let count = match args.first() {
+            Some(count) => count.parse().map_err(|_| {
+                ShellError::InvalidArgument(format!("shift: {count}: numeric argument required"))
+            })?,
+            None => 1,
+        };
+        if count > runner.args.len() {
+            return Err(ShellError::InvalidArgument(
+                "shift: shift count out of range".into(),
+            ));
+        }
+        runner.args.drain(..count);
+        Ok(0)
+    }
+}
+
+/// Run a script file in the current shell, as `source` or `.`
+struct Source(&'static str);
+
+impl Builtin for Source {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn help(&self) -> &str {
+        "Run a script in the current shell\n\
+         \n\
+         usage: source file | . file"
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let Some(path) = args.first() else {
+            return Err(ShellError::InvalidArgument(format!(
+                "{}: filename argument required",
+                self.0
+            )));
+        };
+        let contents = read_script(&runner.pwd.join(path))
+            .map_err(|e| ShellError::InvalidArgument(format!("{}: {path}: {e}", self.0)))?;
+        runner.run_script(path, script_body(&contents), out)
+    }
+}
+
+/// Do nothing but exit with a fixed status, as `true` or `false`
+struct Status(&'static str, i32);
+
+impl Builtin for Status {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn help(&self) -> &str {
+        if self.1 == 0 {
+            "Do nothing, successfully\n\nusage: true"
+        } else {
+            "Do nothing, unsuccessfully\n\nusage: false"
+        }
+    }
+
+    fn run(&self, _: &mut CommandRunner, _: &[String], _: &mut dyn Write) -> Result<i32> {
+        Ok(self.1)
+    }
+}
+
+/// Evaluate a conditional expression, as `test` or `[`
+///
+/// Succeeds if it's true, fails with status 1 if it's false and with
+/// status 2 if it's malformed.
+struct Test(&'static str);
+
+impl Builtin for Test {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn help(&self) -> &str {
+        "Evaluate a conditional expression\n\
+         \n\
+         usage: test expression | [ expression ]\n\
+         \n\
+         Files: -e exists, -f regular file, -d directory, -s not empty,\n\
+         -L symlink, -r/-w/-x readable/writable/executable, and a -nt/-ot b\n\
+         newer/older than, a -ef b same file.\n\
+         Strings: -n not empty, -z empty, a = b, a != b, a < b, a > b.\n\
+         Integers: a -eq b, -ne, -lt, -le, -gt, -ge.\n\
+         Combine with ! expr, expr -a expr, expr -o expr and ( expr )."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let args = match args.split_last() {
+            _ if self.0 != "[" => args,
+            Some((last, args)) if last == "]" => args,
+            _ => {
+                print_error("[: missing `]'");
+                return Ok(2);
+            }
+        };
+        if args.is_empty() {
+            return Ok(1);
+        }
+
+        let mut tokens = args;
+        let result = test_or(runner, &mut tokens).and_then(|value| match tokens.first() {
+            Some(extra) => Err(format!("{extra}: unexpected argument")),
+            None => Ok(value),
+        });
+        match result {
+            Ok(value) => Ok(if value { 0 } else { 1 }),
+            Err(msg) => {
+                print_error(format_args!("{}: {msg}", self.0));
+                Ok(2)
+            }
+        }
+    }
+}
+
+/// The value of a `test` expression, or why it's malformed
+type Condition = std::result::Result<bool, String>;
+
+/// Operators of `test` that take an operand on each side
+const TEST_BINARY: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// Operators of `test` that take a single operand
+const TEST_UNARY: [&str; 11] = [
+    "-n", "-z", "-e", "-f", "-d", "-s", "-L", "-h", "-r", "-w", "-x",
+];
+
+/// Evaluate `expr -o expr ...` at the start of `tokens`, and skip past it
+fn test_or(runner: &CommandRunner, tokens: &mut &[String]) -> Condition {
+    let mut value = test_and(runner, tokens)?;
+    while tokens.first().is_some_and(|token| token == "-o") {
+        *tokens = &tokens[1..];
+        value |= test_and(runner, tokens)?;
+    }
+    Ok(value)
+}
+
+fn test_and(runner: &CommandRunner, tokens: &mut &[String]) -> Condition {
+    let mut value = test_not(runner, tokens)?;
+    while tokens.first().is_some_and(|token| token == "-a") {
+        *tokens = &tokens[1..];
+        value &= test_not(runner, tokens)?;
+    }
+    Ok(value)
+}
+
+fn test_not(runner: &CommandRunner, tokens: &mut &[String]) -> Condition {
+    match tokens {
+        // A lone `!` is just a string that isn't empty
+        [not, _, ..] if not == "!" => {
+            *tokens = &tokens[1..];
+            Ok(!test_not(runner, tokens)?)
+        }
+        _ => test_primary(runner, tokens),
+    }
+}
+
+/// Evaluate a single comparison, file test or parenthesized expression
+///
+/// Anything else is a string, which is true unless it's empty.
+fn test_primary(runner: &CommandRunner, tokens: &mut &[String]) -> Condition {
+    let Some((first, rest)) = tokens.split_first() else {
+        return Err("argument expected".into());
+    };
+    if let [left, op, right, ..] = tokens {
+        if TEST_BINARY.contains(&op.as_str()) {
+            *tokens = &tokens[3..];
+            return test_binary(runner, left, op, right);
+        }
+    }
+    if first == "(" {
+        *tokens = rest;
+        let value = test_or(runner, tokens)?;
+        return match tokens.split_first() {
+            Some((close, rest)) if close == ")" => {
+                *tokens = rest;
+                Ok(value)
+            }
+            _ => Err("`)' expected".into()),
+        };
+    }
+    match rest.split_first() {
+        Some((operand, rest)) if TEST_UNARY.contains(&first.as_str()) => {
+            *tokens = rest;
+            Ok(test_unary(runner, first, operand))
+        }
+        _ => {
+            *tokens = rest;
+            Ok(!first.is_empty())
+        }
+    }
+}
+
+fn test_unary(runner: &CommandRunner, op: &str, operand: &str) -> bool {
+    let path = runner.pwd.join(operand);
+    match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-e" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-s" => path.metadata().is_ok_and(|metadata| metadata.len() > 0),
+        "-L" | "-h" => path.is_symlink(),
+        _ => permitted(&path, op),
+    }
+}
+
+fn test_binary(runner: &CommandRunner, left: &str, op: &str, right: &str) -> Condition {
+    let integer = |operand: &str| {
+        operand
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| format!("{operand}: integer expression expected"))
+    };
+    let modified = |operand: &str| runner.pwd.join(operand).metadata()?.modified();
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        // A file that doesn't exist is older than any that does
+        "-nt" => modified(left).ok() > modified(right).ok(),
+        "-ot" => modified(left).ok() < modified(right).ok(),
+        _ => {
+            let (left, right) = (runner.pwd.join(left), runner.pwd.join(right));
+            matches!((canonicalize(&left), canonicalize(&right)), (Ok(left), Ok(right)) if left == right)
+        }
+    })
+}
+
+/// Whether the file at `path` can be read (`-r`), written (`-w`) or
+/// executed (`-x`) by the shell
+#[cfg(unix)]
+fn permitted(path: &Path, op: &str) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let mode = match op {
+        "-r" => libc::R_OK,
+        "-w" => libc::W_OK,
+        _ => libc::X_OK,
+    };
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid NUL terminated string
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+#[cfg(not(unix))]
+fn permitted(path: &Path, op: &str) -> bool {
+    match op {
+        "-r" => path.exists(),
+        "-w" => path
+            .metadata()
+            .is_ok_and(|metadata| !metadata.permissions().readonly()),
+        _ => is_executable(path),
+    }
+}
+
+/// Run an external command, stopping it if it takes too long
+struct Timeout;
+
+impl Builtin for Timeout {
+    fn name(&self) -> &str {
+        "timeout"
+    }
+
+    fn help(&self) -> &str {
+        "Run a command with a time limit\n\
+         \n\
+         usage: timeout duration command [args]\n\
+         \n\
+         The duration is in seconds, or minutes, hours or days with an m, h or\n\
+         d after it. A command still running by then gets SIGTERM, then\n\
+         SIGKILL a second later, and exits with status 124."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        let [limit, bin, args @ ..] = args else {
+            return Err(ShellError::InvalidArgument(
+                "timeout: usage: timeout duration command [args]".into(),
+            ));
+        };
+        let duration = parse_duration(limit).ok_or_else(|| {
+            ShellError::InvalidArgument(format!("timeout: {limit}: invalid time interval"))
+        })?;
+        // Like `true` and `echo`, a builtin can be timed if there's also a
+        // program by that name, which is what runs
+        let program = runner.resolve_in_path(bin);
+        if program.is_none() && runner.is_builtin(bin) {
+            return Err(ShellError::InvalidArgument(format!(
+                "timeout: {bin}: only external commands can be timed"
+            )));
+        }
+
+        let command = Command {
+            assignments: vec![],
+            bin: bin.clone(),
+            args: args.to_vec(),
+            redirects: vec![],
+            program,
+        };
+        let command = runner.vet(command)?;
+        let ran = command.execute(
+            &runner.pwd,
+            &runner.env(),
+            runner.umask,
+            None,
+            Some(duration),
+        )?;
+        // Stopped with Ctrl-Z, it becomes a job like any other command
+        let output = runner.foreground(ran, format!("timeout {limit} {command}"));
+        runner.ran(&command, output.status);
+        out.write_all(&output.stdout)?;
+        Ok(output.status)
+    }
+}
+
+/// A duration like `5`, `0.5s`, `2m`, `1h` or `1d`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.strip_suffix(['s', 'm', 'h', 'd']) {
+        Some(number) => (number, &text[number.len()..]),
+        None => (text, "s"),
+    };
+    let seconds: f64 = number.parse().ok()?;
+    let scale = match unit {
+        "m" => 60.0,
+        "h" => 60.0 * 60.0,
+        "d" => 24.0 * 60.0 * 60.0,
+        _ => 1.0,
+    };
+    Duration::try_from_secs_f64(seconds * scale).ok()
+}
+
+/// Show or set the file creation mask
+///
+/// The mask is the runner's, so that changes in a subshell stay there,
+/// just like with `cd`.
+#[cfg(unix)]
+struct Umask;
+
+#[cfg(unix)]
+impl Builtin for Umask {
+    fn name(&self) -> &str {
+        "umask"
+    }
+
+    fn help(&self) -> &str {
+        "Show or set the file creation mask\n\
+         \n\
+         usage: umask [mode]\n\
+         \n\
+         The mode is an octal number like 022, whose bits are left out of the\n\
+         permissions of new files. Without it, shows the current mask."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], out: &mut dyn Write) -> Result<i32> {
+        match args {
+            [] => {
+                let mask = runner.umask.unwrap_or_else(|| {
+                    // SAFETY: `umask` can't fail, and the mask is put back
+                    // right away
+                    unsafe {
+                        let mask = libc::umask(0);
+                        libc::umask(mask);
+                        mask as u32
+                    }
+                });
+                writeln!(out, "{mask:04o}")?;
+            }
+            [mode] => {
+                let mask = u32::from_str_radix(mode, 8)
+                    .ok()
+                    .filter(|&mask| mask <= 0o777)
+                    .ok_or_else(|| {
+                        ShellError::InvalidArgument(format!(
+                            "umask: {mode}: octal number out of range"
+                        ))
+                    })?;
+                runner.umask = Some(mask);
+            }
+            _ => {
+                return Err(ShellError::InvalidArgument(
+                    "umask: usage: umask [mode]".into(),
+                ))
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Wait for the given jobs, or all of them, to finish
+///
+/// The status is that of the last job given, or 0 when waiting for all.
+struct Wait;
+
+impl Builtin for Wait {
+    fn name(&self) -> &str {
+        "wait"
+    }
+
+    fn help(&self) -> &str {
+        "Wait for jobs to finish\n\
+         \n\
+         usage: wait [%job ...]\n\
+         \n\
+         Without jobs, waits for all of them. Exits with the status of the last\n\
+         job given."
+    }
+
+    fn run(&self, runner: &mut CommandRunner, args: &[String], _: &mut dyn Write) -> Result<i32> {
+        let mut status = 0;
+        let jobs: Vec<Job> = if args.is_empty() {
+            runner.jobs.drain().collect()
+        } else {
+            let mut jobs = vec![];
+            for arg in args {
+                let id = job_id(runner, "wait", Some(arg))?;
+                jobs.extend(runner.jobs.remove(id));
+            }
+            jobs
+        };
+        for mut job in jobs {
+            let job_status = job.wait()?;
+            if !args.is_empty() {
+                status = job_status;
+            }
+            // Jobs that got stopped instead are still around
+            if let JobState::Stopped(_) = job.state {
+                runner.jobs.restore(job);
+            }
+        }
+        Ok(status)
+    }
+}
@@ -0,0 +1,46 @@
+//! ANSI colors for output going to a terminal
+use std::{env, fmt::Display, io::IsTerminal};
+
+/// Color of the prompt, unless `RUSTLAB_PROMPT_COLOR` says otherwise
+pub(crate) const PROMPT_COLOR: &str = "green";
+const ERROR_COLOR: &str = "31";
+
+/// Whether to color what's written to `stream`
+///
+/// Only terminals get colors, and only if `NO_COLOR` isn't set
+/// (<https://no-color.org>).
+pub(crate) fn enabled(stream: &impl IsTerminal) -> bool {
+    env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && stream.is_terminal()
+}
+
+/// The escape code for a color name like `cyan`, or for SGR parameters
+/// given as they are, like `1;35`
+///
+/// `None` for anything else, which leaves the text uncolored.
+pub(crate) fn code(color: &str) -> Option<String> {
+    let names = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    if let Some(i) = names.iter().position(|&name| name == color) {
+        return Some(format!("3{i}"));
+    }
+    let valid = !color.is_empty() && color.chars().all(|c| c.is_ascii_digit() || c == ';');
+    valid.then(|| color.to_string())
+}
+
+/// `text` in the color given by `code`
+pub(crate) fn paint(text: &str, code: &str) -> String {
+    format!("\x1b[{code}m{text}\x1b[0m")
+}
+
+/// Print an error message on stderr, prefixed with the name of the shell
+///
+/// It is shown in red when stderr is a terminal.
+pub fn print_error(message: impl Display) {
+    let message = format!("rush: {message}");
+    if enabled(&std::io::stderr()) {
+        eprintln!("{}", paint(&message, ERROR_COLOR));
+    } else {
+        eprintln!("{message}");
+    }
+}
@@ -0,0 +1,766 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{PipeWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Stdio},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    interrupt,
+    jobs::{JobState, Process},
+    redirect::{Redirect, Streams, Target},
+    Output, Result, ShellError,
+};
+
+/// What child processes get on top of the shell's own environment
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Environment {
+    /// Exported variables, by name
+    pub(crate) vars: HashMap<String, String>,
+    /// Variables the shell inherited that were unset since, which children
+    /// don't inherit either
+    pub(crate) unset: HashSet<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    /// Variables set for this command only, like `FOO=bar` in `FOO=bar cmd`
+    ///
+    /// A command that only consists of assignments has an empty `bin`.
+    pub assignments: Vec<(String, String)>,
+    pub bin: String,
+    pub args: Vec<String>,
+    /// Redirections, in the order they were given
+    pub redirects: Vec<Redirect>,
+    /// Where `bin` was found in `PATH`, once it has been looked up
+    pub program: Option<PathBuf>,
+}
+
+impl Command {
+    /// Prepare a child process for this command, without its stdio
+    ///
+    /// With a `umask`, the child gets that instead of the shell's.
+    fn process(
+        &self,
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+    ) -> std::process::Command {
+        let mut cmd = match &self.program {
+            Some(program) => {
+                let mut cmd = std::process::Command::new(program);
+                // Programs still see the name they were called by
+                #[cfg(unix)]
+                std::os::unix::process::CommandExt::arg0(&mut cmd, &self.bin);
+                cmd
+            }
+            None => std::process::Command::new(&self.bin),
+        };
+        cmd.args(&self.args).current_dir(cwd);
+        for name in &env.unset {
+            cmd.env_remove(name);
+        }
+        cmd.envs(&env.vars)
+            .envs(self.assignments.iter().map(|(name, value)| (name, value)));
+        #[cfg(unix)]
+        if let Some(umask) = umask {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: `umask` is async-signal-safe and can't fail
+            unsafe {
+                cmd.pre_exec(move || {
+                    libc::umask(umask as libc::mode_t);
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = umask;
+        cmd
+    }
+
+    fn spawn_error(&self, e: std::io::Error) -> ShellError {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => ShellError::CommandNotFound(self.bin.clone()),
+            std::io::ErrorKind::PermissionDenied => ShellError::PermissionDenied(self.bin.clone()),
+            _ => ShellError::Io(e),
+        }
+    }
+
+    /// Replace the shell's process with the command, like `execvp`
+    ///
+    /// It keeps the shell's streams, apart from those it redirects. Only
+    /// returns if the command couldn't be run.
+    #[cfg(unix)]
+    pub(crate) fn exec(&self, cwd: &PathBuf, env: &Environment, umask: Option<u32>) -> ShellError {
+        use std::os::unix::process::CommandExt;
+
+        let streams = match Streams::open(&self.redirects, cwd, umask) {
+            Ok(streams) => streams,
+            Err(e) => return e,
+        };
+        let mut cmd = self.process(cwd, env, umask);
+        if let Target::File(file) = streams.stdin {
+            cmd.stdin(file);
+        }
+        match streams.stdout {
+            Target::Piped => &mut cmd,
+            Target::Stderr => cmd.stdout(std::io::stderr()),
+            Target::File(file) => cmd.stdout(file),
+        };
+        match streams.stderr {
+            Target::Piped => cmd.stderr(std::io::stdout()),
+            Target::Stderr => &mut cmd,
+            Target::File(file) => cmd.stderr(file),
+        };
+        self.spawn_error(cmd.exec())
+    }
+
+    /// Run the command as a child process
+    ///
+    /// `env` holds the changes to the shell's own environment to pass on.
+    /// With a `timeout`, the command is stopped once it runs for longer
+    /// than that, see [`wait_for`]. Without one, it may be stopped with
+    /// Ctrl-Z instead.
+    pub(crate) fn execute(
+        &self,
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+        input: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+    ) -> Result<Ran> {
+        let streams = Streams::open(&self.redirects, cwd, umask)?;
+        let (reader, writer) = std::io::pipe()?;
+
+        let mut cmd = self.process(cwd, env, umask);
+        // In a process group of its own, so that anything it starts can be
+        // stopped along with it, and gets Ctrl-C and Ctrl-Z too
+        let group = timeout.is_some() || interrupt::caught();
+        #[cfg(unix)]
+        if group {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+        match streams.stdin {
+            Target::File(file) => cmd.stdin(file),
+            _ => cmd.stdin(Stdio::piped()),
+        };
+        match streams.stdout {
+            Target::Piped => cmd.stdout(writer.try_clone()?),
+            Target::Stderr => cmd.stdout(std::io::stderr()),
+            Target::File(file) => cmd.stdout(file),
+        };
+        match streams.stderr {
+            Target::Piped => cmd.stderr(writer.try_clone()?),
+            // Error messages go straight to the terminal, even in pipelines
+            Target::Stderr => cmd.stderr(Stdio::inherit()),
+            Target::File(file) => cmd.stderr(file),
+        };
+
+        let mut child = cmd.spawn().map_err(|e| self.spawn_error(e))?;
+        // Close our ends of the pipe, so that reading stops when the child exits
+        drop(cmd);
+        drop(writer);
+        let group = group.then(|| child.id());
+        interrupt::set_foreground(group);
+
+        // If we have input, write it to stdin
+        // This happens in the background, so that a command producing lots of
+        // output can't block us while we're still writing.
+        let stdin = child.stdin.take();
+        let input_writer = std::thread::spawn(move || {
+            if let (Some(input), Some(mut stdin)) = (input, stdin) {
+                // The command may exit without reading all of its input
+                let _ = stdin.write_all(&input);
+            }
+        });
+
+        // Keep reading the output while waiting, so that the command can't
+        // block on a full pipe
+        let capture = Capture::start(reader);
+        let state = match timeout {
+            Some(limit) => wait_for(&mut child, limit).map(JobState::Done),
+            None => wait_or_stop(&mut child),
+        };
+        interrupt::set_foreground(None);
+        match state? {
+            JobState::Stopped(status) => Ok(Ran::Stopped {
+                output: Output {
+                    stdout: capture.detach(),
+                    status,
+                },
+                process: Process::Child(child),
+                group,
+            }),
+            JobState::Running => unreachable!("{} was waited for", self.bin),
+            JobState::Done(status) => {
+                let stdout = capture.finish()?;
+                let _ = input_writer.join();
+                Ok(Ran::Finished(Output { stdout, status }))
+            }
+        }
+    }
+
+    /// Start the command with pipes to its stdin and stdout, for `coproc`
+    pub(crate) fn spawn_piped(
+        &self,
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+    ) -> Result<Child> {
+        let mut cmd = self.process(cwd, env, umask);
+        cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+        cmd.spawn().map_err(|e| self.spawn_error(e))
+    }
+
+    /// Start the command in the background without waiting for it
+    ///
+    /// Its output goes straight to the shell's stdout instead of being
+    /// captured, and it doesn't get any input. With `group`, it leads a
+    /// process group of its own.
+    pub(crate) fn spawn(
+        &self,
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+        group: bool,
+    ) -> Result<Child> {
+        self.spawn_with(cwd, env, umask, Stdio::null(), None, group.then_some(0))
+    }
+
+    /// Start the stages of a pipeline in the background, each one's output
+    /// going to the next one's input
+    ///
+    /// As with [`Command::spawn`], the first one gets no input and the last
+    /// one's output goes straight to the shell's stdout. With `group`, they
+    /// all go into the process group of the first one.
+    pub(crate) fn spawn_pipeline(
+        stages: &[Command],
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+        group: bool,
+    ) -> Result<Vec<Child>> {
+        Self::spawn_stages(stages, cwd, env, umask, None, group)
+    }
+
+    /// Run the stages of a pipeline side by side, each one's output going
+    /// to the next one's input, and wait for all of them
+    ///
+    /// The output of the last one is captured, and its status is the
+    /// pipeline's, or with `pipefail` that of the last one that failed.
+    /// When the shell catches Ctrl-C and Ctrl-Z, the stages get
+    /// a process group of their own that they are passed on to, so that they
+    /// all stop together.
+    pub(crate) fn run_pipeline(
+        stages: &[Command],
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+        pipefail: bool,
+    ) -> Result<Ran> {
+        let (reader, writer) = std::io::pipe()?;
+        let group = interrupt::caught();
+        let mut children = Self::spawn_stages(stages, cwd, env, umask, Some(writer), group)?;
+        let group = group.then(|| children.first().map(Child::id)).flatten();
+        interrupt::set_foreground(group);
+
+        let capture = Capture::start(reader);
+        let mut state = Ok(JobState::Done(0));
+        let mut failed = 0;
+        for child in &mut children {
+            state = wait_or_stop(child);
+            match state {
+                Ok(JobState::Done(0)) => (),
+                Ok(JobState::Done(status)) => failed = status,
+                _ => break,
+            }
+        }
+        interrupt::set_foreground(None);
+
+        match state? {
+            JobState::Stopped(status) => Ok(Ran::Stopped {
+                output: Output {
+                    stdout: capture.detach(),
+                    status,
+                },
+                process: Process::Pipeline(children),
+                group,
+            }),
+            JobState::Running => unreachable!("the pipeline was waited for"),
+            JobState::Done(status) => Ok(Ran::Finished(Output {
+                stdout: capture.finish()?,
+                status: if pipefail { failed } else { status },
+            })),
+        }
+    }
+
+    /// Start the stages of a pipeline, the last one writing to `output`, or
+    /// to the shell's stdout without one
+    ///
+    /// With `group`, they all go into the process group of the first one.
+    fn spawn_stages(
+        stages: &[Command],
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+        mut output: Option<PipeWriter>,
+        group: bool,
+    ) -> Result<Vec<Child>> {
+        let mut children: Vec<Child> = vec![];
+        let mut input = Stdio::null();
+        for (i, stage) in stages.iter().enumerate() {
+            // A pipe of our own rather than `Stdio::piped()`, so that `2>&1`
+            // can send the stage's errors into it as well
+            let (next_input, stdout) = if i + 1 == stages.len() {
+                (None, output.take())
+            } else {
+                let (reader, writer) = std::io::pipe()?;
+                (Some(reader), Some(writer))
+            };
+            let process_group = group.then(|| children.first().map_or(0, Child::id));
+            match stage.spawn_with(cwd, env, umask, input, stdout, process_group) {
+                Ok(child) => {
+                    input = next_input.map_or_else(Stdio::null, Stdio::from);
+                    children.push(child);
+                }
+                Err(e) => {
+                    // Don't leave the stages that did start behind
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(children)
+    }
+
+    /// Start the command with the given stdin and stdout, unless they are
+    /// redirected
+    ///
+    /// Without a `stdout`, it writes to the shell's. With a `process_group`,
+    /// it joins that group, or starts its own with 0.
+    pub(crate) fn spawn_with(
+        &self,
+        cwd: &PathBuf,
+        env: &Environment,
+        umask: Option<u32>,
+        stdin: Stdio,
+        stdout: Option<PipeWriter>,
+        process_group: Option<u32>,
+    ) -> Result<Child> {
+        let streams = Streams::open(&self.redirects, cwd, umask)?;
+
+        let mut cmd = self.process(cwd, env, umask);
+        #[cfg(unix)]
+        if let Some(group) = process_group {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(group as i32);
+        }
+        #[cfg(not(unix))]
+        let _ = process_group;
+        match streams.stdin {
+            Target::File(file) => cmd.stdin(file),
+            _ => cmd.stdin(stdin),
+        };
+        // `2>&1` goes wherever stdout would have gone without redirects
+        let unredirected = || -> Result<Stdio> {
+            Ok(match &stdout {
+                Some(pipe) => pipe.try_clone()?.into(),
+                None => std::io::stdout().into(),
+            })
+        };
+        match streams.stdout {
+            Target::Piped => cmd.stdout(unredirected()?),
+            Target::Stderr => cmd.stdout(std::io::stderr()),
+            Target::File(file) => cmd.stdout(file),
+        };
+        match streams.stderr {
+            Target::Piped => cmd.stderr(unredirected()?),
+            Target::Stderr => cmd.stderr(Stdio::inherit()),
+            Target::File(file) => cmd.stderr(file),
+        };
+
+        cmd.spawn().map_err(|e| self.spawn_error(e))
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let words: Vec<String> = self
+            .assignments
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .chain(std::iter::once(self.bin.clone()).filter(|bin| !bin.is_empty()))
+            .chain(self.args.iter().cloned())
+            .chain(self.redirects.iter().map(|redirect| redirect.to_string()))
+            .collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+/// A chain of commands
+///
+/// # Examples
+///
+/// ```sh
+/// echo 1
+/// echo 1; echo 2
+/// echo "hello world" | wc
+/// cat log | grep error | wc -l
+/// make && make install || echo failed
+/// { sleep 5; echo done; } &
+/// (( count += 1 ))
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandChain {
+    Command(Command),
+    Piped(Vec<Command>),
+    /// Commands grouped with `{ ...; }`
+    Group(Vec<CommandChain>),
+    /// `if cond; then ...; elif cond; then ...; else ...; fi`: the body of
+    /// the first branch whose condition succeeds runs, or else `otherwise`
+    If {
+        branches: Vec<(Vec<CommandChain>, Vec<CommandChain>)>,
+        otherwise: Vec<CommandChain>,
+    },
+    /// `for name in words; do ...; done`, running the body with `name` set
+    /// to each of the expanded words in turn, or to each positional
+    /// parameter without `in`
+    For {
+        name: String,
+        words: Option<Vec<String>>,
+        body: Vec<CommandChain>,
+    },
+    /// `while cond; do ...; done`, running the body as long as `cond`
+    /// succeeds, or as long as it fails with `until`
+    While {
+        condition: Vec<CommandChain>,
+        body: Vec<CommandChain>,
+        until: bool,
+    },
+    /// `a && b`: run `b` only if `a` succeeded
+    And(Box<CommandChain>, Box<CommandChain>),
+    /// `a || b`: run `b` only if `a` failed
+    Or(Box<CommandChain>, Box<CommandChain>),
+    /// A chain followed by `&`, running in the background
+    Background(Box<CommandChain>),
+    /// An arithmetic expression to evaluate with `(( ... ))`
+    Arith(String),
+}
+
+impl Display for CommandChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandChain::Command(command) => write!(f, "{command}"),
+            CommandChain::Piped(stages) => {
+                let stages: Vec<String> = stages.iter().map(|stage| stage.to_string()).collect();
+                write!(f, "{}", stages.join(" | "))
+            }
+            CommandChain::Group(chains) => {
+                write!(f, "{{ ")?;
+                for chain in chains {
+                    write!(f, "{chain}; ")?;
+                }
+                write!(f, "}}")
+            }
+            CommandChain::If {
+                branches,
+                otherwise,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    write!(f, "{} ", if i == 0 { "if" } else { "elif" })?;
+                    for chain in condition {
+                        write!(f, "{chain}; ")?;
+                    }
+                    write!(f, "then ")?;
+                    for chain in body {
+                        write!(f, "{chain}; ")?;
+                    }
+                }
+                if !otherwise.is_empty() {
+                    write!(f, "else ")?;
+                    for chain in otherwise {
+                        write!(f, "{chain}; ")?;
+                    }
+                }
+                write!(f, "fi")
+            }
+            CommandChain::For { name, words, body } => {
+                write!(f, "for {name}")?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for word in words {
+                        write!(f, " {word}")?;
+                    }
+                }
+                write!(f, "; do ")?;
+                for chain in body {
+                    write!(f, "{chain}; ")?;
+                }
+                write!(f, "done")
+            }
+            CommandChain::While {
+                condition,
+                body,
+                until,
+            } => {
+                write!(f, "{} ", if *until { "until" } else { "while" })?;
+                for chain in condition {
+                    write!(f, "{chain}; ")?;
+                }
+                write!(f, "do ")?;
+                for chain in body {
+                    write!(f, "{chain}; ")?;
+                }
+                write!(f, "done")
+            }
+            CommandChain::And(first, second) => write!(f, "{first} && {second}"),
+            CommandChain::Or(first, second) => write!(f, "{first} || {second}"),
+            CommandChain::Background(chain) => write!(f, "{chain} &"),
+            CommandChain::Arith(expr) => write!(f, "(({expr}))"),
+        }
+    }
+}
+
+/// Commands that run the command named after them, as in `sudo git`
+pub(crate) const WRAPPERS: &[&str] = &["sudo", "env", "nice", "command", "builtin", "time"];
+
+/// Where the name of the command that actually runs is among `words`
+///
+/// That's the first word, or the first one after wrappers like `sudo` or
+/// `env` with their options, and variable assignments. It is `words.len()`
+/// if all of them come before the name.
+pub(crate) fn command_position(words: &[&str]) -> usize {
+    let mut after_wrapper = false;
+    words
+        .iter()
+        .position(|&word| {
+            if WRAPPERS.contains(&word) || (after_wrapper && word.starts_with('-')) {
+                after_wrapper = true;
+                return false;
+            }
+            !word.split_once('=').is_some_and(|(name, _)| is_name(name))
+        })
+        .unwrap_or(words.len())
+}
+
+/// Whether `name` can be used as a variable name
+pub(crate) fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether `path` is a file that can be run
+///
+/// On Unix, that's a file with one of its execute bits set.
+#[cfg(unix)]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// How a command run in the foreground ended up
+pub(crate) enum Ran {
+    Finished(Output),
+    /// Stopped with Ctrl-Z before it finished, with its output so far
+    ///
+    /// Whatever it writes once it carries on goes straight to stdout.
+    Stopped {
+        output: Output,
+        process: Process,
+        /// The process group of its own it runs in, if any
+        group: Option<u32>,
+    },
+}
+
+/// The output of a child process, read from a pipe on a thread of its own
+///
+/// It is collected until it is [detached](Capture::detach), and goes
+/// straight to stdout after that.
+struct Capture {
+    buffer: Arc<Mutex<Option<Vec<u8>>>>,
+    reader: JoinHandle<std::io::Result<()>>,
+}
+
+impl Capture {
+    fn start(mut pipe: std::io::PipeReader) -> Self {
+        let buffer = Arc::new(Mutex::new(Some(vec![])));
+        let collected = Arc::clone(&buffer);
+        let reader = std::thread::spawn(move || {
+            let mut chunk = [0; 8192];
+            loop {
+                let n = pipe.read(&mut chunk)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                match collected.lock().expect("output lock poisoned").as_mut() {
+                    Some(buffer) => buffer.extend_from_slice(&chunk[..n]),
+                    None => std::io::stdout().write_all(&chunk[..n])?,
+                }
+            }
+        });
+        Self { buffer, reader }
+    }
+
+    /// Wait for the end of the output and return all of it
+    fn finish(self) -> Result<Vec<u8>> {
+        self.reader.join().expect("reading output panicked")?;
+        Ok(Self::take(&self.buffer))
+    }
+
+    /// Return the output so far, and let the rest go to stdout
+    fn detach(self) -> Vec<u8> {
+        Self::take(&self.buffer)
+    }
+
+    fn take(buffer: &Mutex<Option<Vec<u8>>>) -> Vec<u8> {
+        let mut buffer = buffer.lock().expect("output lock poisoned");
+        buffer.take().unwrap_or_default()
+    }
+}
+
+/// Wait for `child` to exit, or to be stopped, like with Ctrl-Z
+///
+/// The result is never [`JobState::Running`].
+pub(crate) fn wait_or_stop(child: &mut Child) -> Result<JobState> {
+    #[cfg(unix)]
+    if let Some(state) = wait_status(child, 0)? {
+        return Ok(state);
+    }
+    Ok(JobState::Done(exit_code(child.wait()?)))
+}
+
+/// Check whether `child` has exited or was stopped, without blocking
+#[cfg(unix)]
+pub(crate) fn try_wait_or_stop(child: &mut Child) -> Result<Option<JobState>> {
+    wait_status(child, libc::WNOHANG)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn try_wait_or_stop(child: &mut Child) -> Result<Option<JobState>> {
+    Ok(child
+        .try_wait()?
+        .map(|status| JobState::Done(exit_code(status))))
+}
+
+/// Look at the status of `child` with `waitid`, waiting according to
+/// `flags`
+///
+/// Only stops are taken from it: an exit is still collected through
+/// `child`, which remembers it.
+#[cfg(unix)]
+fn wait_status(child: &mut Child, flags: libc::c_int) -> Result<Option<JobState>> {
+    let pid = child.id() as libc::id_t;
+    loop {
+        // SAFETY: an all-zero siginfo_t is valid, and waitid only fills it in
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid,
+                &mut info,
+                libc::WEXITED | libc::WSTOPPED | libc::WNOWAIT | flags,
+            )
+        };
+        if result == -1 {
+            let error = std::io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                // Already collected by `child`
+                Some(libc::ECHILD) => break,
+                _ => return Err(error.into()),
+            }
+        }
+        // SAFETY: waitid filled in the fields for a child
+        let (child_pid, signal) = unsafe { (info.si_pid(), info.si_status()) };
+        if child_pid == 0 {
+            return Ok(None);
+        }
+        // Ctrl-C from the terminal only reaches the command in the
+        // foreground, but what the shell is running should stop as well
+        if info.si_code == libc::CLD_KILLED && signal == libc::SIGINT && flags & libc::WNOHANG == 0
+        {
+            interrupt::raise();
+        }
+        if info.si_code == libc::CLD_STOPPED {
+            // Consume the stop, so that it isn't reported again
+            // SAFETY: as above
+            unsafe { libc::waitid(libc::P_PID, pid, &mut info, libc::WSTOPPED) };
+            return Ok(Some(JobState::Stopped(128 + signal)));
+        }
+        break;
+    }
+    Ok(child
+        .try_wait()?
+        .map(|status| JobState::Done(exit_code(status))))
+}
+
+/// The status of a command that ran out of time, like with GNU `timeout`
+const TIMED_OUT: i32 = 124;
+
+/// Wait for `child` to exit for at most `limit`, then stop it
+///
+/// It gets `SIGTERM` first, and `SIGKILL` if it is still around a second
+/// later. Either way its status is [`TIMED_OUT`].
+fn wait_for(child: &mut Child, limit: Duration) -> Result<i32> {
+    let poll = |child: &mut Child, deadline: Instant| -> Result<Option<i32>> {
+        while Instant::now() < deadline {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(exit_code(status)));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(None)
+    };
+    if let Some(status) = poll(child, Instant::now() + limit)? {
+        return Ok(status);
+    }
+
+    #[cfg(unix)]
+    {
+        // The child leads its own process group, see `Command::execute`
+        let group = -(child.id() as i32);
+        // SAFETY: kill only sends a signal
+        unsafe { libc::kill(group, libc::SIGTERM) };
+        poll(child, Instant::now() + Duration::from_secs(1))?;
+        // Whatever it started may still be holding on to its output
+        // SAFETY: as above
+        unsafe { libc::kill(group, libc::SIGKILL) };
+    }
+    let _ = child.kill();
+    child.wait()?;
+    Ok(TIMED_OUT)
+}
+
+/// Convert a process exit status into a shell status code
+///
+/// Processes killed by a signal get `128 + signal`, like in other shells.
+pub(crate) fn exit_code(status: std::process::ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
@@ -0,0 +1,566 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    io::{BufRead, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use rustyline::{
+    completion::{Completer, Pair},
+    config::{CompletionType, Config, Configurer},
+    error::ReadlineError,
+    highlight::Highlighter,
+    history::DefaultHistory,
+    Cmd, ConditionalEventHandler, Context, Editor, Event, EventContext, EventHandler, Helper,
+    Hinter, KeyCode, KeyEvent, Modifiers, RepeatCount, Validator,
+};
+
+use crate::{
+    color,
+    command::{command_position, exit_code, is_executable},
+    print_error,
+    runner::CommandRunner,
+    LineSource, Lines, Result, ShellError,
+};
+
+/// Characters that end a word on the command line
+const SEPARATORS: &[char] = &[' ', '\t', ';', '|', '&', '<', '>'];
+
+/// What tab completion and the prompt need to know about the shell
+#[derive(Default, Helper, Hinter, Validator)]
+struct ShellHelper {
+    /// Escape code for the color of the prompt, if it gets one
+    prompt_color: Option<String>,
+    pwd: PathBuf,
+    /// Builtins and aliases
+    commands: Vec<String>,
+    /// `$PATH`, where to look for other commands
+    path: Option<String>,
+    /// The words that the arguments of some commands complete to, as set
+    /// with `complete -W`
+    completions: HashMap<String, Vec<String>>,
+}
+
+impl ShellHelper {
+    /// Commands starting with `prefix`, in code point order
+    fn commands(&self, prefix: &str) -> BTreeSet<String> {
+        let mut commands: BTreeSet<String> = self
+            .commands
+            .iter()
+            .filter(|command| command.starts_with(prefix))
+            .cloned()
+            .collect();
+
+        for dir in self
+            .path
+            .iter()
+            .flat_map(|path| std::env::split_paths(path))
+        {
+            let Ok(entries) = dir.read_dir() else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with(prefix) && is_executable(&entry.path()) {
+                    commands.insert(name);
+                }
+            }
+        }
+        commands
+    }
+
+    /// Paths starting with `prefix`, relative to the working directory
+    ///
+    /// Directories get a trailing `/`, so that completion can go on inside.
+    /// They are sorted by code point, whatever order the directory lists
+    /// them in and whatever the locale.
+    fn paths(&self, prefix: &str) -> Vec<String> {
+        let (dir, name) = match prefix.rfind('/') {
+            Some(slash) => prefix.split_at(slash + 1),
+            None => ("", prefix),
+        };
+        let Ok(entries) = self.pwd.join(dir).read_dir() else {
+            return vec![];
+        };
+
+        let mut paths: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                // Hidden files only show up when asked for
+                if !file_name.starts_with(name)
+                    || (file_name.starts_with('.') && !name.starts_with('.'))
+                {
+                    return None;
+                }
+                let slash = if entry.path().is_dir() { "/" } else { "" };
+                Some(format!("{dir}{file_name}{slash}"))
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Where the word being completed starts, and what it could become
+    ///
+    /// The first word of a command completes to a command, unless it's a
+    /// path. The arguments of commands with words set with `complete -W`
+    /// complete to those, and any others to paths.
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos].rfind(SEPARATORS).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let candidates = match command_of(&line[..start]) {
+            None if !word.contains('/') => self.commands(word).into_iter().collect(),
+            Some(command) if self.completions.contains_key(command) => {
+                let mut words: Vec<String> = self.completions[command]
+                    .iter()
+                    .filter(|candidate| candidate.starts_with(word))
+                    .cloned()
+                    .collect();
+                words.sort();
+                words.dedup();
+                words
+            }
+            _ => self.paths(word),
+        };
+        (start, candidates)
+    }
+}
+
+impl Highlighter for ShellHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        default: bool,
+    ) -> Cow<'b, str> {
+        // Leave the prompt of a history search alone
+        match &self.prompt_color {
+            Some(code) if default => Cow::Owned(color::paint(prompt, code)),
+            _ => Cow::Borrowed(prompt),
+        }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = self.candidates(line, pos);
+        let candidates = candidates
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate
+                    .trim_end_matches('/')
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+                    + if candidate.ends_with('/') { "/" } else { "" },
+                replacement: escape(&candidate),
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+/// The command that the word after `before` belongs to, or `None` if that
+/// word is where a command's name goes
+///
+/// Wrappers like `sudo` or `env` with their options, and variable
+/// assignments, are skipped, so that's `git` in `sudo git `.
+fn command_of(before: &str) -> Option<&str> {
+    let command = match before.rfind([';', '|', '&']) {
+        Some(i) => &before[i + 1..],
+        None => before,
+    };
+    let words: Vec<&str> = command.split_whitespace().collect();
+    words.get(command_position(&words)).copied()
+}
+
+/// Escape the characters in a completed word that the shell would treat
+/// specially
+fn escape(word: &str) -> String {
+    let mut escaped = String::new();
+    for c in word.chars() {
+        if SEPARATORS.contains(&c) || matches!(c, '\'' | '"' | '\\' | '$' | '#' | '(' | ')') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Makes Up and Down go through the matches of a Ctrl-R history search
+///
+/// The editor would otherwise end the search and go through the whole
+/// history instead.
+#[derive(Clone, Default)]
+struct SearchKeys {
+    searching: Arc<AtomicBool>,
+}
+
+impl SearchKeys {
+    /// Keys that start a search
+    const START: [KeyEvent; 2] = [
+        KeyEvent(KeyCode::Char('R'), Modifiers::CTRL),
+        KeyEvent(KeyCode::Char('S'), Modifiers::CTRL),
+    ];
+    /// Keys that end a search, other than Up and Down
+    const END: [KeyEvent; 9] = [
+        KeyEvent(KeyCode::Esc, Modifiers::NONE),
+        KeyEvent(KeyCode::Char('G'), Modifiers::CTRL),
+        KeyEvent(KeyCode::Enter, Modifiers::NONE),
+        KeyEvent(KeyCode::Left, Modifiers::NONE),
+        KeyEvent(KeyCode::Right, Modifiers::NONE),
+        KeyEvent(KeyCode::Home, Modifiers::NONE),
+        KeyEvent(KeyCode::End, Modifiers::NONE),
+        KeyEvent(KeyCode::Char('A'), Modifiers::CTRL),
+        KeyEvent(KeyCode::Char('E'), Modifiers::CTRL),
+    ];
+
+    fn bind(&self, editor: &mut Editor<ShellHelper, DefaultHistory>) {
+        let keys = Self::START.iter().chain(&Self::END).chain(&[
+            KeyEvent(KeyCode::Up, Modifiers::NONE),
+            KeyEvent(KeyCode::Down, Modifiers::NONE),
+        ]);
+        for key in keys {
+            editor.bind_sequence(*key, EventHandler::Conditional(Box::new(self.clone())));
+        }
+    }
+
+    fn reset(&self) {
+        self.searching.store(false, Ordering::Relaxed);
+    }
+}
+
+impl ConditionalEventHandler for SearchKeys {
+    fn handle(&self, event: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        let key = event.get(0)?;
+        let searching = self.searching.load(Ordering::Relaxed);
+        match key.0 {
+            KeyCode::Up if searching => Some(Cmd::ReverseSearchHistory),
+            KeyCode::Down if searching => Some(Cmd::ForwardSearchHistory),
+            _ => {
+                // Let the editor do its usual thing, but keep track of it
+                self.searching
+                    .store(Self::START.contains(key), Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+/// Ctrl-X Ctrl-E: accept the line, and have it edited in `$EDITOR` before
+/// running it
+#[derive(Clone, Default)]
+struct EditKey {
+    pressed: Arc<AtomicBool>,
+}
+
+impl ConditionalEventHandler for EditKey {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        // The editor can only take over the terminal once we're done with it
+        self.pressed.store(true, Ordering::Relaxed);
+        Some(Cmd::AcceptLine)
+    }
+}
+
+/// Let the user edit `text` in `editor`, a command like `vi` or `code -w`
+///
+/// The text is put into a temporary file, whose contents are returned once
+/// the editor exits successfully.
+pub(crate) fn edit_in_editor(text: &str, editor: &str) -> Result<String> {
+    let mut words = editor.split_whitespace();
+    let Some(program) = words.next() else {
+        return Err(ShellError::InvalidArgument("no editor set".into()));
+    };
+    let (path, mut file) = temp_file()?;
+    file.write_all(text.as_bytes())?;
+    drop(file);
+
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status();
+    let edited = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path).map_err(Into::into),
+        Ok(status) => Err(ShellError::InvalidArgument(format!(
+            "{editor}: exited with status {}",
+            exit_code(status)
+        ))),
+        Err(e) => Err(e.into()),
+    };
+    let _ = std::fs::remove_file(&path);
+    edited
+}
+
+/// Create a new file for [`edit_in_editor`] in the temporary directory,
+/// only readable by the user
+///
+/// The name can't be guessed ahead of time, and it is never an existing
+/// file, or a symlink someone put there to have us write elsewhere.
+fn temp_file() -> Result<(PathBuf, std::fs::File)> {
+    use std::hash::{BuildHasher, RandomState};
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut attempts = 0;
+    loop {
+        let random = RandomState::new().hash_one(std::time::SystemTime::now());
+        let name = format!("rush-edit-{}-{random:016x}.sh", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempts < 100 => {
+                attempts += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Input from the terminal, with line editing, tab completion, and the
+/// shell's history on the arrow keys
+pub(crate) struct LineEditor {
+    editor: Editor<ShellHelper, DefaultHistory>,
+    /// How many entries of the shell's history the editor has been given,
+    /// counting those since trimmed
+    synced: usize,
+    /// The shell history's count of removals when it was last synced
+    removals: usize,
+    search: SearchKeys,
+    edit: EditKey,
+    /// The command to edit lines with, for Ctrl-X Ctrl-E
+    external_editor: String,
+    /// Plain input, once the terminal turned out not to support editing
+    cooked: Option<Lines<Box<dyn BufRead>>>,
+    /// Where plain input comes from
+    plain_input: fn() -> Box<dyn BufRead>,
+}
+
+impl LineEditor {
+    pub(crate) fn new() -> Result<Self> {
+        // Complete the common prefix, then list the candidates. The size
+        // of the history follows the shell's once it is synced.
+        let config = Config::builder()
+            .completion_type(CompletionType::List)
+            .max_history_size(usize::MAX)
+            .map_err(std::io::Error::other)?
+            .build();
+        let mut editor = Editor::with_config(config).map_err(std::io::Error::other)?;
+        editor.set_helper(Some(ShellHelper::default()));
+        let search = SearchKeys::default();
+        search.bind(&mut editor);
+        let edit = EditKey::default();
+        editor.bind_sequence(
+            Event::KeySeq(vec![
+                KeyEvent(KeyCode::Char('X'), Modifiers::CTRL),
+                KeyEvent(KeyCode::Char('E'), Modifiers::CTRL),
+            ]),
+            EventHandler::Conditional(Box::new(edit.clone())),
+        );
+        // The shell itself isn't suspended, only the commands it runs
+        editor.bind_sequence(
+            KeyEvent(KeyCode::Char('Z'), Modifiers::CTRL),
+            EventHandler::Simple(Cmd::Noop),
+        );
+        Ok(Self {
+            editor,
+            synced: 0,
+            removals: 0,
+            search,
+            edit,
+            external_editor: String::new(),
+            cooked: None,
+            plain_input: || Box::new(std::io::stdin().lock()),
+        })
+    }
+
+    /// Turn what the editor read into a line for the shell
+    fn take_line(
+        &mut self,
+        read: std::result::Result<String, ReadlineError>,
+        prompt: Option<&str>,
+        out: &mut dyn Write,
+    ) -> Result<Option<String>> {
+        match read {
+            Ok(line) if self.edit.pressed.swap(false, Ordering::Relaxed) => {
+                match edit_in_editor(&(line + "\n"), &self.external_editor) {
+                    Ok(mut edited) => {
+                        if !edited.ends_with('\n') {
+                            edited.push('\n');
+                        }
+                        // Show what is about to run, like bash does
+                        write!(out, "{edited}")?;
+                        Ok(Some(edited))
+                    }
+                    Err(e) => {
+                        print_error(e);
+                        Ok(Some("\n".into()))
+                    }
+                }
+            }
+            Ok(line) => Ok(Some(line + "\n")),
+            // Ctrl-C throws away the line being edited
+            Err(ReadlineError::Interrupted) => Ok(Some("\n".into())),
+            Err(ReadlineError::Eof) => Ok(None),
+            // Most likely the terminal can't be put into raw mode, which
+            // only takes away the editing
+            Err(e) => {
+                print_error(format_args!("line editing unavailable: {e}"));
+                let lines = self.cooked.insert(Lines((self.plain_input)()));
+                lines.next_line(prompt, out)
+            }
+        }
+    }
+}
+
+impl LineSource for LineEditor {
+    fn next_line(&mut self, prompt: Option<&str>, out: &mut dyn Write) -> Result<Option<String>> {
+        if let Some(lines) = &mut self.cooked {
+            return lines.next_line(prompt, out);
+        }
+        self.search.reset();
+        let read = self.editor.readline(prompt.unwrap_or_default());
+        self.take_line(read, prompt, out)
+    }
+
+    fn refresh(&mut self, runner: &CommandRunner) {
+        // Start over when entries were removed, as with `history -d`
+        if runner.history.removals != self.removals {
+            let _ = self.editor.clear_history();
+            self.synced = 0;
+            self.removals = runner.history.removals;
+        }
+        // The shell keeps the history, so only pass on what's new. The
+        // oldest entries it dropped are dropped here by the same size limit.
+        let _ = self.editor.set_max_history_size(runner.history_size());
+        let skip = self.synced.saturating_sub(runner.history.trimmed);
+        for entry in runner.history.iter().skip(skip) {
+            // Only fails if the editor's history is kept in a file
+            let _ = self.editor.add_history_entry(entry.command.as_str());
+        }
+        self.synced = runner.history.trimmed + runner.history.len();
+
+        let Some(helper) = self.editor.helper_mut() else {
+            return;
+        };
+        helper.pwd = runner.pwd.clone();
+        helper.commands = runner
+            .builtins
+            .keys()
+            .filter(|name| !runner.disabled.contains(*name))
+            .chain(runner.aliases.keys())
+            .cloned()
+            .collect();
+        helper.path = runner.var("PATH");
+        helper.completions = runner.completions.clone();
+        self.external_editor = runner.var("EDITOR").unwrap_or("vi".into());
+        // The prompt turns red after a failed command
+        helper.prompt_color = color::enabled(&std::io::stdout())
+            .then(|| {
+                let color = runner.var("RUSTLAB_PROMPT_COLOR");
+                match runner.last_status {
+                    0 => color::code(color.as_deref().unwrap_or(color::PROMPT_COLOR)),
+                    _ => color::code("red"),
+                }
+            })
+            .flatten();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn editors_change_the_text_in_place() {
+        let edited = edit_in_editor("echo old\n", "sed -i s/old/new/").unwrap();
+        assert_eq!(edited, "echo new\n");
+        let error = edit_in_editor("echo old\n", "false").unwrap_err();
+        assert_eq!(error.to_string(), "false: exited with status 1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temp_files_are_new_and_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (first, _) = temp_file().unwrap();
+        let (second, _) = temp_file().unwrap();
+        assert_ne!(first, second);
+        let mode = fs::metadata(&first).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+    }
+
+    #[test]
+    fn plain_lines_are_read_once_raw_mode_fails() {
+        let mut editor = LineEditor::new().unwrap();
+        editor.plain_input = || Box::new(&b"echo one\necho two\n"[..]);
+        let failure = ReadlineError::Io(std::io::Error::other("raw mode unavailable"));
+        let mut out = vec![];
+        let line = editor.take_line(Err(failure), Some("> "), &mut out);
+        assert_eq!(line.unwrap().as_deref(), Some("echo one\n"));
+        let line = editor.next_line(Some("> "), &mut out).unwrap();
+        assert_eq!(line.as_deref(), Some("echo two\n"));
+        assert_eq!(editor.next_line(Some("> "), &mut out).unwrap(), None);
+        // Without the editor, prompts go to the output
+        assert_eq!(out, b"> > > ");
+    }
+
+    #[test]
+    fn arguments_complete_to_the_words_set_for_their_command() {
+        let git = ["push", "commit", "checkout"].map(String::from).to_vec();
+        let helper = ShellHelper {
+            completions: HashMap::from([("git".to_string(), git)]),
+            ..Default::default()
+        };
+        let words = ["checkout", "commit"];
+        assert_eq!(
+            helper.candidates("sudo git c", 10),
+            (9, words.map(String::from).to_vec())
+        );
+        assert_eq!(helper.candidates("git c", 5).1, words);
+        assert_eq!(helper.candidates("env -i A=1 git c", 16).1, words);
+        assert_eq!(helper.candidates("ls; time git c", 14).1, words);
+        assert!(helper.candidates("ls c", 4).1.is_empty());
+        assert_eq!(
+            helper.candidates("sudo git ", 9).1,
+            ["checkout", "commit", "push"]
+        );
+    }
+
+    #[test]
+    fn candidates_are_sorted_by_code_point() {
+        let dir = std::env::temp_dir().join(format!("rush-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("Dir")).unwrap();
+        for name in ["b", "a", "B", "10", "9", "A", "_x"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let helper = ShellHelper {
+            pwd: dir.clone(),
+            commands: ["zip", "Zap", "a2", "a10"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let paths = helper.paths("");
+        assert_eq!(paths, ["10", "9", "A", "B", "Dir/", "_x", "a", "b"]);
+        let commands: Vec<String> = helper.commands("").into_iter().collect();
+        assert_eq!(commands, ["Zap", "a10", "a2", "zip"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt::Display;
+
+/// Everything that can go wrong while running a line of input
+#[derive(Debug)]
+pub enum ShellError {
+    /// The input isn't valid shell syntax
+    ParseError(String),
+    /// There is no builtin or binary with this name
+    CommandNotFound(String),
+    /// The binary exists but can't be executed
+    PermissionDenied(String),
+    /// The command isn't allowed to run by the shell it runs in, see
+    /// [`Shell::set_denied_commands`](crate::Shell::set_denied_commands)
+    Denied(String),
+    /// A builtin was called with arguments it doesn't understand
+    InvalidArgument(String),
+    Io(std::io::Error),
+    /// A command wrote something to stdout that isn't valid UTF-8
+    NonUtf8Output,
+    /// A script to run isn't a text file
+    BinaryFile,
+}
+
+impl Display for ShellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShellError::ParseError(msg) => write!(f, "parse error: {msg}"),
+            ShellError::CommandNotFound(bin) => write!(f, "{bin}: command not found"),
+            ShellError::PermissionDenied(bin) => write!(f, "{bin}: permission denied"),
+            ShellError::Denied(bin) => write!(f, "{bin}: command not permitted"),
+            ShellError::InvalidArgument(msg) => write!(f, "{msg}"),
+            ShellError::Io(e) => write!(f, "{e}"),
+            ShellError::NonUtf8Output => write!(f, "output is not valid UTF-8"),
+            ShellError::BinaryFile => write!(f, "cannot execute binary file"),
+        }
+    }
+}
+
+impl ShellError {
+    /// The exit status (`$?`) a command failing with this error gets
+    ///
+    /// Follows the usual shell conventions: 127 if the command wasn't found,
+    /// 126 if it couldn't be executed and 2 for syntax errors.
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::ParseError(_) => 2,
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::PermissionDenied(_) | ShellError::Denied(_) | ShellError::BinaryFile => 126,
+            _ => 1,
+        }
+    }
+}
+
+impl std::error::Error for ShellError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ShellError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ShellError {
+    fn from(e: std::io::Error) -> Self {
+        ShellError::Io(e)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for ShellError {
+    fn from(_: std::string::FromUtf8Error) -> Self {
+        ShellError::NonUtf8Output
+    }
+}
@@ -0,0 +1,1050 @@
+use std::{iter::Peekable, ops::Range, str::Chars};
+
+use crate::{arith, command::is_name, glob, runner::CommandRunner, Result, ShellError};
+
+/// Expand a word as written by the user into its final value
+///
+/// Removes quotes and backslash escapes, replaces `$VAR` with the value of
+/// the variable, `$(...)` with the output of the commands inside and
+/// `$((...))` with the value of the arithmetic expression inside. A
+/// leading `~` or `~user` becomes the home directory.
+pub(crate) fn expand_word(word: &str, runner: &mut CommandRunner) -> Result<String> {
+    Ok(expand(word, runner)?.text)
+}
+
+/// Expand a word like [`expand_word`], then split what unquoted expansions
+/// turned into at the characters in `IFS`
+///
+/// So `$files` becomes a field for each name in `files`, while `"$files"`
+/// stays a single one. `"$@"` and `"${list[@]}"` become a field for each
+/// element. A word that expands to nothing, without any quotes, goes away
+/// entirely, and so does `"$@"` without any arguments.
+///
+/// Fields with unquoted wildcards, like `*.rs`, are then replaced by the
+/// paths they match, if there are any.
+pub(crate) fn expand_fields(word: &str, runner: &mut CommandRunner) -> Result<Vec<String>> {
+    let expansion = expand(word, runner)?;
+    let ifs = runner.var("IFS").unwrap_or(" \t\n".into());
+    let mut fields = vec![];
+    for field in expansion.split(&ifs) {
+        let paths = match glob::has_wildcards(&field.pattern) {
+            true => glob::glob(&field.pattern, &runner.pwd),
+            false => vec![],
+        };
+        if paths.is_empty() {
+            fields.push(field.text);
+        } else {
+            fields.extend(paths);
+        }
+    }
+    Ok(fields)
+}
+
+/// A field of an expanded word
+#[derive(Default)]
+struct Field {
+    text: String,
+    /// `text` as a pattern for pathname expansion, where the wildcards
+    /// that were quoted are escaped
+    pattern: String,
+}
+
+impl Field {
+    fn push(&mut self, c: char, literal: bool) {
+        self.text.push(c);
+        if c == '\\' || (literal && matches!(c, '*' | '?' | '[')) {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+    }
+}
+
+/// A word after expansion
+struct Expansion {
+    text: String,
+    /// Where in `text` the results of unquoted expansions are, which are
+    /// subject to field splitting
+    unquoted: Vec<Range<usize>>,
+    /// Where in `text` the quoted or escaped characters are, which aren't
+    /// wildcards
+    literal: Vec<Range<usize>>,
+    /// The spaces in `text` between the elements of `$@` or `${list[@]}`,
+    /// which always separate fields, and whether they were in quotes
+    breaks: Vec<(usize, bool)>,
+    /// Whether the word had quotes or escapes, which keep it as a field of
+    /// its own even when it is empty
+    quoted: bool,
+}
+
+impl Expansion {
+    /// Split into fields, like other shells do
+    ///
+    /// Runs of whitespace in `ifs` separate fields and are dropped at the
+    /// start and end, while every other character in `ifs` ends a field,
+    /// even an empty one.
+    fn split(self, ifs: &str) -> Vec<Field> {
+        let mut fields = vec![];
+        let mut field = Field::default();
+        // Whether `field` has begun, even if it is still empty
+        let mut started = false;
+        // Whether the last field was ended by whitespace
+        let mut after_space = false;
+        for (i, c) in self.text.char_indices() {
+            // Quoted elements are fields even when empty, unquoted ones
+            // only when there's something to them
+            if let Some(&(_, quoted)) = self.breaks.iter().find(|&&(at, _)| at == i) {
+                if started || quoted {
+                    fields.push(std::mem::take(&mut field));
+                    after_space = true;
+                }
+                started = quoted;
+                continue;
+            }
+            let splits = ifs.contains(c) && self.unquoted.iter().any(|range| range.contains(&i));
+            if !splits {
+                field.push(c, self.literal.iter().any(|range| range.contains(&i)));
+                started = true;
+                after_space = false;
+            } else if c.is_whitespace() {
+                if started {
+                    fields.push(std::mem::take(&mut field));
+                    started = false;
+                    after_space = true;
+                }
+            } else {
+                if started || !after_space {
+                    fields.push(std::mem::take(&mut field));
+                }
+                started = false;
+                after_space = false;
+            }
+        }
+        if started {
+            fields.push(field);
+        }
+        if fields.is_empty() && self.quoted {
+            fields.push(Field::default());
+        }
+        fields
+    }
+}
+
+fn expand(word: &str, runner: &mut CommandRunner) -> Result<Expansion> {
+    let mut expanded = String::new();
+    let mut unquoted = vec![];
+    let mut literal = vec![];
+    let mut breaks = vec![];
+    let mut quoted = false;
+    let (home, rest) = tilde(word, runner);
+    expanded.extend(home);
+    literal.push(0..expanded.len());
+    let mut chars = rest.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let start = expanded.len();
+        match c {
+            '\\' => {
+                quoted = true;
+                if let Some(next) = chars.next() {
+                    expanded.push(next);
+                }
+            }
+            '\'' => {
+                quoted = true;
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    expanded.push(c);
+                }
+            }
+            '"' => {
+                quoted |= expand_quoted(&mut chars, runner, &mut expanded, &mut breaks, true)?;
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                quoted = true;
+                chars.next();
+                ansi_c(&mut chars, &mut expanded);
+            }
+            '$' => {
+                match chars.peek() {
+                    Some('(') => expanded.push_str(&substitute(&mut chars, runner)?),
+                    _ => match parameter(&mut chars, runner)? {
+                        Value::Text(text) => expanded.push_str(&text),
+                        Value::Fields(fields) => {
+                            push_fields(&fields, &mut expanded, &mut breaks, false)
+                        }
+                    },
+                }
+                unquoted.push(start..expanded.len());
+                continue;
+            }
+            c => {
+                expanded.push(c);
+                continue;
+            }
+        }
+        literal.push(start..expanded.len());
+    }
+
+    Ok(Expansion {
+        text: expanded,
+        unquoted,
+        literal,
+        breaks,
+        quoted,
+    })
+}
+
+/// Copy the rest of a `$'...'` string to `expanded`, interpreting its
+/// backslash escapes like C does
+///
+/// Supports `\n`, `\t` and the other single letter escapes, `\xHH`, `\uHHHH`,
+/// `\UHHHHHHHH` and octal `\NNN`. Unknown escapes are kept as they are.
+fn ansi_c(chars: &mut Peekable<Chars>, expanded: &mut String) {
+    // The character with the code in up to `max_digits` more digits
+    let code = |chars: &mut Peekable<Chars>, mut digits: String, radix, max_digits| {
+        digits.extend((0..max_digits).map_while(|_| chars.next_if(|c| c.is_digit(radix))));
+        u32::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+    };
+
+    while let Some(c) = chars.next() {
+        let escape = match c {
+            '\'' => break,
+            '\\' => chars.next(),
+            c => {
+                expanded.push(c);
+                continue;
+            }
+        };
+        let unescaped = match escape {
+            Some('a') => Some('\x07'),
+            Some('b') => Some('\x08'),
+            Some('e' | 'E') => Some('\x1b'),
+            Some('f') => Some('\x0c'),
+            Some('n') => Some('\n'),
+            Some('r') => Some('\r'),
+            Some('t') => Some('\t'),
+            Some('v') => Some('\x0b'),
+            Some(c @ ('\\' | '\'' | '"' | '?')) => Some(c),
+            Some('x') => code(chars, String::new(), 16, 2),
+            Some('u') => code(chars, String::new(), 16, 4),
+            Some('U') => code(chars, String::new(), 16, 8),
+            Some(digit @ '0'..='7') => code(chars, digit.into(), 8, 2),
+            _ => None,
+        };
+        match (unescaped, escape) {
+            // Arguments can't hold NUL characters
+            (Some('\0'), _) => (),
+            (Some(c), _) => expanded.push(c),
+            (None, Some(c)) => {
+                expanded.push('\\');
+                expanded.push(c);
+            }
+            (None, None) => expanded.push('\\'),
+        }
+    }
+}
+
+/// Expand `{a,b,c}` alternatives and `{1..5}` sequences in a word that is
+/// otherwise still unexpanded
+///
+/// Braces in quotes or in `${...}` and `$(...)` are left alone, just like
+/// ones that aren't a list or a sequence, such as `{a}` or `{`.
+pub(crate) fn expand_braces(word: &str) -> Vec<String> {
+    let bytes = word.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'{' {
+            if let Some(end) = closing(bytes, i) {
+                let body = &word[i + 1..end];
+                let alternatives = match top_level_commas(body) {
+                    commas if !commas.is_empty() => Some(split_at(body, &commas)),
+                    _ => sequence(body),
+                };
+                if let Some(alternatives) = alternatives {
+                    let (prefix, suffix) = (&word[..i], &word[end + 1..]);
+                    return alternatives
+                        .iter()
+                        .flat_map(|alternative| {
+                            expand_braces(&format!("{prefix}{alternative}{suffix}"))
+                        })
+                        .collect();
+                }
+            }
+        }
+        i = skip(bytes, i) + 1;
+    }
+    vec![word.to_string()]
+}
+
+/// Where the construct that starts at `i` ends: a quoted string, an escaped
+/// character, `${...}` or `$(...)`
+///
+/// For anything else, that's `i` itself.
+fn skip(bytes: &[u8], i: usize) -> usize {
+    let find = |from: usize, byte: u8| {
+        (from..bytes.len())
+            .find(|&j| bytes[j] == byte && bytes[j - 1] != b'\\')
+            .unwrap_or(bytes.len())
+    };
+    match (bytes[i], bytes.get(i + 1)) {
+        (b'\\', _) => i + 1,
+        (b'\'', _) => (i + 1..bytes.len())
+            .find(|&j| bytes[j] == b'\'')
+            .unwrap_or(bytes.len()),
+        (b'"', _) => find(i + 1, b'"'),
+        (b'$', Some(b'{')) => find(i + 2, b'}'),
+        (b'$', Some(b'\'')) => find(i + 2, b'\''),
+        (b'$', Some(b'(')) => {
+            let mut depth = 0;
+            let mut j = i + 1;
+            while j < bytes.len() {
+                match bytes[j] {
+                    b'(' => depth += 1,
+                    b')' => depth -= 1,
+                    _ => j = skip(bytes, j),
+                }
+                if depth == 0 {
+                    break;
+                }
+                j += 1;
+            }
+            j
+        }
+        _ => i,
+    }
+}
+
+/// The index of the `}` matching the `{` at `start`
+fn closing(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => i = skip(bytes, i),
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The indices of the commas in `body` that aren't inside nested braces
+fn top_level_commas(body: &str) -> Vec<usize> {
+    let bytes = body.as_bytes();
+    let mut commas = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b',' => commas.push(i),
+            b'{' => i = closing(bytes, i).unwrap_or(i),
+            _ => i = skip(bytes, i),
+        }
+        i += 1;
+    }
+    commas
+}
+
+fn split_at(body: &str, commas: &[usize]) -> Vec<String> {
+    let mut parts = vec![];
+    let mut start = 0;
+    for &comma in commas {
+        parts.push(body[start..comma].to_string());
+        start = comma + 1;
+    }
+    parts.push(body[start..].to_string());
+    parts
+}
+
+/// The elements of a sequence like `1..5`, `1..10..2`, `05..10` or `a..e`
+fn sequence(body: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = body.split("..").collect();
+    let (start, end, step) = match parts[..] {
+        [start, end] => (start, end, 1),
+        [start, end, step] => (start, end, step.parse::<i64>().ok()?),
+        _ => return None,
+    };
+    let step = step.unsigned_abs().max(1) as usize;
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        // Leading zeros pad all numbers to the same width
+        let padded = |n: &str| {
+            n.trim_start_matches('-').len() > 1 && n.trim_start_matches('-').starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        let numbers: Vec<i64> = if first <= last {
+            (first..=last).step_by(step).collect()
+        } else {
+            (last..=first).rev().step_by(step).collect()
+        };
+        return Some(numbers.iter().map(|n| format!("{n:0width$}")).collect());
+    }
+
+    let letter = |s: &str| match s.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Some(*c),
+        _ => None,
+    };
+    let (first, last) = (letter(start)?, letter(end)?);
+    let letters: Vec<u8> = if first <= last {
+        (first..=last).step_by(step).collect()
+    } else {
+        (last..=first).rev().step_by(step).collect()
+    };
+    Some(letters.iter().map(|&c| char::from(c).to_string()).collect())
+}
+
+/// Expand the body of a here-document
+///
+/// Like in double quotes, except that `"` has no special meaning.
+pub(crate) fn expand_heredoc(body: &str, runner: &mut CommandRunner) -> Result<String> {
+    let mut expanded = String::new();
+    let mut chars = body.chars().peekable();
+    expand_quoted(&mut chars, runner, &mut expanded, &mut vec![], false)?;
+    Ok(expanded)
+}
+
+/// Expand the rest of a double-quoted string into `expanded`, up to its
+/// closing `"`, or everything if not `quoted`
+///
+/// Returns whether the string is a field of its own, which it isn't if all
+/// there was to it is `$@` without any arguments.
+fn expand_quoted(
+    chars: &mut Peekable<Chars>,
+    runner: &mut CommandRunner,
+    expanded: &mut String,
+    breaks: &mut Vec<(usize, bool)>,
+    quoted: bool,
+) -> Result<bool> {
+    let mut empty_list = false;
+    let mut other = false;
+    while let Some(c) = chars.next() {
+        if !matches!(c, '"' | '$') {
+            other = true;
+        }
+        match c {
+            '"' if quoted => break,
+            // Inside double quotes, a backslash only escapes characters
+            // that are special there
+            '\\' => match chars.peek() {
+                Some(&next @ ('\\' | '$' | '`')) => {
+                    expanded.push(next);
+                    chars.next();
+                }
+                Some('"') if quoted => {
+                    expanded.push('"');
+                    chars.next();
+                }
+                // An escaped newline joins the lines
+                Some('\n') => {
+                    chars.next();
+                }
+                _ => expanded.push('\\'),
+            },
+            '$' if chars.peek() == Some(&'(') => {
+                other = true;
+                expanded.push_str(&substitute(chars, runner)?);
+            }
+            '$' => match parameter(chars, runner)? {
+                Value::Fields(fields) if fields.is_empty() => empty_list = true,
+                Value::Fields(fields) => {
+                    other = true;
+                    push_fields(&fields, expanded, breaks, true);
+                }
+                Value::Text(text) => {
+                    other = true;
+                    expanded.push_str(&text);
+                }
+            },
+            c => expanded.push(c),
+        }
+    }
+    Ok(other || !empty_list)
+}
+
+/// Add the elements of `$@` or `${list[@]}` to `expanded`, separated by
+/// spaces that are recorded as field breaks
+fn push_fields(
+    fields: &[String],
+    expanded: &mut String,
+    breaks: &mut Vec<(usize, bool)>,
+    quoted: bool,
+) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            breaks.push((expanded.len(), quoted));
+            expanded.push(' ');
+        }
+        expanded.push_str(field);
+    }
+}
+
+/// The elements of `$*` or `${list[*]}` as a single string, separated by
+/// the first character of `IFS`
+fn join(fields: &[String], runner: &CommandRunner) -> String {
+    let separator = match runner.var("IFS") {
+        Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+        None => " ".to_string(),
+    };
+    fields.join(&separator)
+}
+
+/// Expand the `~` or `~user` at the start of `word`, up to the first `/`
+///
+/// Returns the home directory, if any, and what's left of the word. Like
+/// in other shells, the word is left alone if the user doesn't exist or
+/// the name is quoted.
+fn tilde<'a>(word: &'a str, runner: &CommandRunner) -> (Option<String>, &'a str) {
+    let Some(rest) = word.strip_prefix('~') else {
+        return (None, word);
+    };
+    let (name, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    if name.contains(['\'', '"', '\\', '$']) {
+        return (None, word);
+    }
+
+    let home = if name.is_empty() {
+        runner
+            .var("HOME")
+            .or_else(|| std::env::home_dir().map(|home| home.to_string_lossy().into_owned()))
+    } else {
+        home_of(name)
+    };
+    match home {
+        Some(home) => (Some(home), rest),
+        None => (None, word),
+    }
+}
+
+/// The home directory of `user`, from the user database
+#[cfg(unix)]
+fn home_of(user: &str) -> Option<String> {
+    use std::ffi::{CStr, CString};
+
+    let user = CString::new(user).ok()?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    // SAFETY: `getpwnam_r` only writes to `entry` and `buf`, and the
+    // strings it points `entry` at live in `buf`, which outlives them
+    unsafe {
+        let mut entry: libc::passwd = std::mem::zeroed();
+        let mut found = std::ptr::null_mut();
+        let error = libc::getpwnam_r(
+            user.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        );
+        if error != 0 || found.is_null() || entry.pw_dir.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr(entry.pw_dir).to_string_lossy().into_owned())
+    }
+}
+
+/// There is no user database to look up other users' home directories in
+#[cfg(not(unix))]
+fn home_of(_user: &str) -> Option<String> {
+    None
+}
+
+/// Run the `$(...)` substitution starting at `chars` and return its output,
+/// or the value of a `$((...))` arithmetic expansion
+fn substitute(chars: &mut Peekable<Chars>, runner: &mut CommandRunner) -> Result<String> {
+    // Skip the opening parenthesis
+    chars.next();
+
+    let mut script = String::new();
+    let mut depth = 1;
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            // Quotes in `$'...'` can be escaped
+            ('\'', None) if script.ends_with('$') => quote = Some('$'),
+            ('\'', Some('$')) => quote = None,
+            ('\\', Some('$')) => {
+                script.push(c);
+                script.extend(chars.next());
+                continue;
+            }
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => (),
+        }
+        script.push(c);
+    }
+
+    // `$(( expr ))` is arithmetic rather than a command in a subshell
+    if let Some(expr) = script
+        .strip_prefix('(')
+        .and_then(|script| script.strip_suffix(')'))
+    {
+        let expr = expand_heredoc(expr, runner)?;
+        return Ok(arith::eval(&expr, runner)?.to_string());
+    }
+
+    let output = runner.substitute(&script)?;
+    // Trailing newlines are removed, just like in other shells
+    Ok(output.trim_end_matches('\n').to_string())
+}
+
+/// What a parameter expands to
+enum Value {
+    Text(String),
+    /// The elements of `$@` or `${list[@]}`, which stay separate fields
+    Fields(Vec<String>),
+}
+
+impl Value {
+    /// Whether there is nothing to it, for `${name:-word}` and friends
+    fn is_null(&self) -> bool {
+        match self {
+            Value::Text(text) => text.is_empty(),
+            Value::Fields(fields) => fields.iter().all(String::is_empty),
+        }
+    }
+}
+
+/// Expand the `$NAME`, `${...}` or `$?` reference following a `$`
+///
+/// Unset variables expand to nothing. A `$` that isn't followed by a
+/// variable name is kept as is.
+fn parameter(chars: &mut Peekable<Chars>, runner: &mut CommandRunner) -> Result<Value> {
+    let name = match chars.peek() {
+        Some('{') => {
+            chars.next();
+            // Up to the matching `}`, so that words in there can have some
+            let mut depth = 0;
+            let braced: String = chars
+                .by_ref()
+                .take_while(|&c| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 0 => return false,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    true
+                })
+                .collect();
+            return braced_parameter(&braced, runner);
+        }
+        Some('@') => {
+            chars.next();
+            return Ok(Value::Fields(runner.args.clone()));
+        }
+        Some('*') => {
+            chars.next();
+            return Ok(Value::Text(join(&runner.args, runner)));
+        }
+        // Only single digits, so that `$10` is `$1` followed by a 0
+        Some(&c @ ('?' | '$' | '#' | '0'..='9')) => {
+            chars.next();
+            c.to_string()
+        }
+        Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            name
+        }
+        _ => return Ok(Value::Text("$".to_string())),
+    };
+    Ok(Value::Text(runner.var(&name).unwrap_or_default()))
+}
+
+/// Split what's inside `${...}` into the name at its start, the index if
+/// it is followed by one, like `[1]` or `[@]`, and the rest
+fn reference(braced: &str) -> Option<(&str, Option<&str>, &str)> {
+    let name_len = match braced.chars().next()? {
+        '0'..='9' => braced
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(braced.len()),
+        '?' | '$' | '#' | '@' | '*' => 1,
+        c if c.is_ascii_alphabetic() || c == '_' => braced
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(braced.len()),
+        _ => return None,
+    };
+    let (name, rest) = braced.split_at(name_len);
+    match rest.strip_prefix('[') {
+        Some(rest) => {
+            let (index, rest) = rest.split_once(']')?;
+            Some((name, Some(index), rest))
+        }
+        None => Some((name, None, rest)),
+    }
+}
+
+/// Expand the inside of `${...}`
+///
+/// `${list[1]}` is a single element of an array, where negative indices
+/// count from the end, and `${list[@]}` all of them. `${#name}` is the
+/// length of a value, or the number of elements with `${#list[@]}`.
+/// `${name:-word}`, `${name:=word}`, `${name:+word}` and `${name:?word}`
+/// depend on whether the value is empty, or only on whether it is set
+/// without the colon. `${value:1:2}` takes characters out of the middle,
+/// and `${list[@]:1:2}` elements.
+fn braced_parameter(braced: &str, runner: &mut CommandRunner) -> Result<Value> {
+    let bad_substitution =
+        || ShellError::InvalidArgument(format!("${{{braced}}}: bad substitution"));
+
+    if let Some(counted) = braced
+        .strip_prefix('#')
+        .filter(|counted| !counted.is_empty())
+    {
+        let (name, index, rest) = reference(counted).ok_or_else(bad_substitution)?;
+        if !rest.is_empty() {
+            return Err(bad_substitution());
+        }
+        // `${#list[*]}` counts the elements rather than the joined string
+        let (name, index) = match (name, index) {
+            ("*", None) => ("@", None),
+            (name, Some("*")) => (name, Some("@")),
+            reference => reference,
+        };
+        let count = match lookup(name, index, runner)? {
+            Some(Value::Fields(fields)) => fields.len(),
+            Some(Value::Text(text)) => text.chars().count(),
+            None => 0,
+        };
+        return Ok(Value::Text(count.to_string()));
+    }
+
+    let (name, index, rest) = reference(braced).ok_or_else(bad_substitution)?;
+    let value = lookup(name, index, runner)?;
+    // What an unset value expands to, which for `"${list[@]}"` is no field
+    let unset = || match index {
+        Some("@") => Value::Fields(vec![]),
+        _ => Value::Text(String::new()),
+    };
+    let (colon, operator) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    // Whether `word` applies, depending on whether the value is set, and
+    // not empty with a colon
+    let missing = match &value {
+        None => true,
+        Some(value) => colon && value.is_null(),
+    };
+
+    let mut operator_chars = operator.chars();
+    match operator_chars.next() {
+        None if !colon => Ok(value.unwrap_or_else(unset)),
+        Some('-') if missing => Ok(Value::Text(expand_word(operator_chars.as_str(), runner)?)),
+        Some('=') if missing => {
+            if index.is_some() || !is_name(name) {
+                return Err(ShellError::InvalidArgument(format!(
+                    "${{{braced}}}: cannot assign in this way"
+                )));
+            }
+            let word = expand_word(operator_chars.as_str(), runner)?;
+            runner.set_var(name, word.clone());
+            Ok(Value::Text(word))
+        }
+        Some('+') if missing => Ok(Value::Text(String::new())),
+        Some('+') => Ok(Value::Text(expand_word(operator_chars.as_str(), runner)?)),
+        Some('?') if missing => {
+            let message = match operator_chars.as_str() {
+                "" => "parameter null or not set".to_string(),
+                word => expand_word(word, runner)?,
+            };
+            Err(ShellError::InvalidArgument(format!("{name}: {message}")))
+        }
+        Some('-' | '=' | '?') => Ok(value.unwrap_or_else(unset)),
+        _ if colon => {
+            // Positional parameters can be sliced from `$0` on
+            let value = match (name, index) {
+                ("@" | "*", None) => Some(Value::Fields(
+                    std::iter::once(runner.name.clone())
+                        .chain(runner.args.iter().cloned())
+                        .collect(),
+                )),
+                _ => value,
+            };
+            slice(value, operator, runner)
+        }
+        _ => Err(bad_substitution()),
+    }
+}
+
+/// The value of `name`, or of its element `index`, or `None` if unset
+fn lookup(name: &str, index: Option<&str>, runner: &mut CommandRunner) -> Result<Option<Value>> {
+    let elements = match (name, index) {
+        ("@", None) => return Ok(Some(Value::Fields(runner.args.clone()))),
+        ("*", None) => return Ok(Some(Value::Text(join(&runner.args, runner)))),
+        (name, None) => return Ok(runner.var(name).map(Value::Text)),
+        (name, Some(_)) if !is_name(name) => return Ok(None),
+        (name, Some(_)) => runner.array(name),
+    };
+    // An array without elements is as good as unset
+    match index {
+        _ if elements.is_empty() => Ok(None),
+        Some("@") => Ok(Some(Value::Fields(elements))),
+        Some("*") => Ok(Some(Value::Text(join(&elements, runner)))),
+        Some(index) => {
+            let index = arith::eval(&expand_word(index, runner)?, runner)?;
+            let len = elements.len() as i64;
+            let index = if index < 0 {
+                len.saturating_add(index)
+            } else {
+                index
+            };
+            Ok(usize::try_from(index)
+                .ok()
+                .and_then(|index| elements.get(index).cloned())
+                .map(Value::Text))
+        }
+        None => unreachable!("handled above"),
+    }
+}
+
+/// The part of `value` that `offset:length` refers to, in characters or in
+/// elements
+///
+/// Negative numbers count from the end. Offsets out of range leave nothing,
+/// while lengths are cut short.
+fn slice(value: Option<Value>, range: &str, runner: &mut CommandRunner) -> Result<Value> {
+    let (offset, length) = match range.split_once(':') {
+        Some((offset, length)) => (offset, Some(length)),
+        None => (range, None),
+    };
+    let offset = arith::eval(&expand_word(offset, runner)?, runner)?;
+    let length = match length {
+        Some(length) => Some(arith::eval(&expand_word(length, runner)?, runner)?),
+        None => None,
+    };
+
+    let bounds = |len: usize| -> Result<Option<Range<usize>>> {
+        let len = len as i64;
+        let start = if offset < 0 {
+            len.saturating_add(offset)
+        } else {
+            offset
+        };
+        if !(0..=len).contains(&start) {
+            return Ok(None);
+        }
+        let end = match length {
+            Some(length) if length < 0 => len.saturating_add(length),
+            Some(length) => start.saturating_add(length).min(len),
+            None => len,
+        };
+        if end < start {
+            return Err(ShellError::InvalidArgument(format!(
+                "{}: substring expression < 0",
+                length.unwrap_or_default()
+            )));
+        }
+        Ok(Some(start as usize..end as usize))
+    };
+    Ok(match value {
+        Some(Value::Fields(fields)) => {
+            Value::Fields(bounds(fields.len())?.map_or(vec![], |range| fields[range].to_vec()))
+        }
+        Some(Value::Text(text)) => {
+            let chars: Vec<char> = text.chars().collect();
+            Value::Text(
+                bounds(chars.len())?.map_or(String::new(), |range| chars[range].iter().collect()),
+            )
+        }
+        None => Value::Text(String::new()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner() -> CommandRunner {
+        let mut runner = CommandRunner::new();
+        let list = ["one", "two", "three"].map(String::from).to_vec();
+        runner.arrays.insert("list".into(), list);
+        runner.set_var("word", "hello".into());
+        runner.set_var("empty", String::new());
+        runner
+    }
+
+    fn expand(word: &str) -> Result<String> {
+        expand_word(word, &mut runner())
+    }
+
+    fn fields(word: &str, args: &[&str]) -> Vec<String> {
+        let mut runner = runner();
+        runner.args = args.iter().map(|arg| arg.to_string()).collect();
+        expand_fields(word, &mut runner).unwrap()
+    }
+
+    #[test]
+    fn quoted_lists_keep_a_field_per_element() {
+        assert_eq!(fields(r#""$@""#, &["a b", "", "c"]), ["a b", "", "c"]);
+        assert_eq!(fields(r#""<$@>""#, &["a b", "c"]), ["<a b", "c>"]);
+        assert_eq!(fields(r#""${list[@]}""#, &[]), ["one", "two", "three"]);
+        assert_eq!(fields(r#""${@:2}""#, &["a", "b", "c"]), ["b", "c"]);
+        assert!(fields(r#""$@""#, &[]).is_empty());
+        assert_eq!(fields(r#""$@""""#, &[]), [""]);
+    }
+
+    #[test]
+    fn unquoted_lists_are_split_further() {
+        assert_eq!(fields("$@", &["a b", "", "c"]), ["a", "b", "c"]);
+        assert_eq!(fields("${list[*]}", &[]), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn star_joins_with_the_first_character_of_ifs() {
+        assert_eq!(fields(r#""$*""#, &["a b", "c"]), ["a b c"]);
+        let mut runner = runner();
+        runner.args = vec!["a".into(), "b".into()];
+        runner.set_var("IFS", ",;".into());
+        assert_eq!(expand_word(r#""$*""#, &mut runner).unwrap(), "a,b");
+        assert_eq!(
+            expand_word(r#""${list[*]}""#, &mut runner).unwrap(),
+            "one,two,three"
+        );
+        runner.set_var("IFS", String::new());
+        assert_eq!(expand_word(r#""$*""#, &mut runner).unwrap(), "ab");
+    }
+
+    #[test]
+    fn unquoted_wildcards_expand_to_paths() {
+        let dir = std::env::temp_dir().join(format!("rush-expand-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.rs"), "").unwrap();
+        std::fs::write(dir.join("b.rs"), "").unwrap();
+        let mut runner = runner();
+        runner.pwd = dir.clone();
+        runner.set_var("pattern", "*.rs".into());
+        for word in ["*.rs", "$pattern", "{a,b}.rs"] {
+            let fields: Vec<String> = expand_braces(word)
+                .iter()
+                .flat_map(|word| expand_fields(word, &mut runner).unwrap())
+                .collect();
+            assert_eq!(fields, ["a.rs", "b.rs"], "{word}");
+        }
+        for word in [r#""*.rs""#, r"\*.rs", "'*.rs'", r#""$pattern""#] {
+            assert_eq!(
+                expand_fields(word, &mut runner).unwrap(),
+                ["*.rs"],
+                "{word}"
+            );
+        }
+        assert_eq!(expand_fields("*.txt", &mut runner).unwrap(), ["*.txt"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn indices_count_from_the_end_when_negative() {
+        assert_eq!(expand("${list[0]}").unwrap(), "one");
+        assert_eq!(expand("${list[-1]}").unwrap(), "three");
+        assert_eq!(expand("${list[1+1]}").unwrap(), "three");
+    }
+
+    #[test]
+    fn indices_out_of_range_expand_to_nothing() {
+        assert_eq!(expand("${list[3]}").unwrap(), "");
+        assert_eq!(expand("${list[-4]}").unwrap(), "");
+        assert_eq!(expand("${list[9223372036854775807]}").unwrap(), "");
+    }
+
+    #[test]
+    fn slices_take_elements_or_characters() {
+        assert_eq!(expand("${list[@]:1}").unwrap(), "two three");
+        assert_eq!(expand("${list[@]:0:2}").unwrap(), "one two");
+        assert_eq!(expand("${list[@]: -1}").unwrap(), "three");
+        assert_eq!(expand("${list[@]:1:-1}").unwrap(), "two");
+        assert_eq!(expand("${list[@]:5}").unwrap(), "");
+        assert_eq!(expand("${word:1:3}").unwrap(), "ell");
+        assert_eq!(expand("${word: -3}").unwrap(), "llo");
+        assert_eq!(expand("${word:1:-1}").unwrap(), "ell");
+        assert!(expand("${word:3:-3}").is_err());
+    }
+
+    #[test]
+    fn slices_saturate_instead_of_overflowing() {
+        assert_eq!(
+            expand("${list[@]:1:9223372036854775807}").unwrap(),
+            "two three"
+        );
+        assert_eq!(expand("${word:-9223372036854775807}").unwrap(), "hello");
+        assert_eq!(expand("${word: -9223372036854775807}").unwrap(), "");
+    }
+
+    #[test]
+    fn lengths_count_characters_or_elements() {
+        assert_eq!(expand("${#word}").unwrap(), "5");
+        assert_eq!(expand("${#list[@]}").unwrap(), "3");
+        assert_eq!(expand("${#list[*]}").unwrap(), "3");
+        assert_eq!(expand("${#list}").unwrap(), "3");
+        assert_eq!(expand("${#list[2]}").unwrap(), "5");
+        assert_eq!(expand("${#unset}").unwrap(), "0");
+    }
+
+    #[test]
+    fn defaults_apply_to_unset_or_empty_values() {
+        assert_eq!(expand("${unset:-default}").unwrap(), "default");
+        assert_eq!(expand("${empty:-default}").unwrap(), "default");
+        assert_eq!(expand("${empty-default}").unwrap(), "");
+        assert_eq!(expand("${word:-default}").unwrap(), "hello");
+        assert_eq!(expand("${list[@]:-1}").unwrap(), "one two three");
+        assert_eq!(expand("${unset:-${word}}").unwrap(), "hello");
+        assert_eq!(expand("${word:+other}").unwrap(), "other");
+        assert_eq!(expand("${unset:+other}").unwrap(), "");
+    }
+
+    #[test]
+    fn assign_defaults_and_errors() {
+        let mut runner = runner();
+        assert_eq!(expand_word("${new:=set}", &mut runner).unwrap(), "set");
+        assert_eq!(runner.var("new").as_deref(), Some("set"));
+        assert!(expand_word("${list[7]:=set}", &mut runner).is_err());
+        let error = expand_word("${unset:?is missing}", &mut runner).unwrap_err();
+        assert_eq!(error.to_string(), "unset: is missing");
+        assert!(expand_word("${%word}", &mut runner).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn tildes_expand_to_home_directories() {
+        let root = home_of("root").unwrap();
+        assert!(root.starts_with('/'));
+        assert_eq!(expand("~root").unwrap(), root);
+        assert_eq!(expand("~root/bin").unwrap(), format!("{root}/bin"));
+        let mut runner = runner();
+        runner.set_var("HOME", "/home/me".into());
+        assert_eq!(expand_word("~/x", &mut runner).unwrap(), "/home/me/x");
+    }
+
+    #[test]
+    fn unknown_users_and_quoted_tildes_are_left_alone() {
+        assert_eq!(expand("~no-such-user").unwrap(), "~no-such-user");
+        assert_eq!(expand("~no-such-user/x").unwrap(), "~no-such-user/x");
+        assert_eq!(expand("\"~root\"").unwrap(), "~root");
+        assert_eq!(expand("a~root").unwrap(), "a~root");
+    }
+}
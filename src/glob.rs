@@ -0,0 +1,231 @@
+//! Pathname expansion, which turns `*.rs` into the names of the files that
+//! match
+//!
+//! `*` matches any characters, `?` a single one and `[...]` one of those
+//! listed. A backslash makes the character after it match only itself.
+use std::path::Path;
+
+/// Whether `pattern` has any of `*`, `?` or `[` that isn't escaped
+pub(crate) fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+/// The paths matching `pattern`, relative to `pwd` unless it is absolute
+///
+/// They are sorted by code point, whatever order the directories list them
+/// in and whatever the locale. Like in other shells, wildcards don't match
+/// a `/` or the `.` at the start of hidden files.
+pub(crate) fn glob(pattern: &str, pwd: &Path) -> Vec<String> {
+    // Paths matched so far, with a trailing `/` if the next component goes
+    // inside them
+    let mut paths = vec![String::new()];
+    let components: Vec<&str> = pattern.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        if component.is_empty() {
+            // A leading or doubled `/` is kept, and one at the end leaves
+            // only directories
+            if i > 0 {
+                paths.retain(|path| pwd.join(path).is_dir());
+            }
+            if !last || i == 0 {
+                paths.iter_mut().for_each(|path| path.push('/'));
+            }
+            continue;
+        }
+        let separator = if last { "" } else { "/" };
+        paths = paths
+            .into_iter()
+            .flat_map(|path| {
+                let dir = pwd.join(if path.is_empty() { "." } else { &path });
+                entries(&dir, component)
+                    .into_iter()
+                    .map(move |name| format!("{path}{name}{separator}"))
+            })
+            .collect();
+    }
+    // Those that are directories for the next component might not be
+    paths.retain(|path| pwd.join(path).symlink_metadata().is_ok());
+    paths.sort();
+    paths
+}
+
+/// The names of the entries in `dir` matching `component`
+fn entries(dir: &Path, component: &str) -> Vec<String> {
+    if !has_wildcards(component) {
+        return vec![unescape(component)];
+    }
+    let Ok(entries) = dir.read_dir() else {
+        return vec![];
+    };
+    let pattern: Vec<char> = component.chars().collect();
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+        .filter(|name| matches(&pattern, &name.chars().collect::<Vec<_>>()))
+        .collect()
+}
+
+/// `pattern` without the backslashes escaping its characters
+fn unescape(pattern: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        unescaped.extend(if c == '\\' { chars.next() } else { Some(c) });
+    }
+    unescaped
+}
+
+/// Whether all of `name` matches `pattern`
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        // As few characters as possible, then more if the rest doesn't match
+        Some('*') => (0..=name.len()).any(|skip| matches(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches(&pattern[1..], &name[1..]),
+        Some('[') => match bracket(&pattern[1..]) {
+            Some((set, rest)) => {
+                name.first().is_some_and(|&c| set.contains(c)) && matches(rest, &name[1..])
+            }
+            // A `[` that isn't closed is just a `[`
+            None => name.first() == Some(&'[') && matches(&pattern[1..], &name[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && matches(&pattern[2..], &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && matches(&pattern[1..], &name[1..]),
+    }
+}
+
+/// The characters listed in a `[...]`, like `abc`, `a-z` or `!0-9`
+struct Set {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Set {
+    fn contains(&self, c: char) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(low, high)| (low..=high).contains(&c))
+            != self.negated
+    }
+}
+
+/// Parse what follows a `[` up to its `]`, and return the pattern after it
+///
+/// A `]` right at the start is one of the characters rather than the end.
+/// Returns `None` if there is no `]`.
+fn bracket(pattern: &[char]) -> Option<(Set, &[char])> {
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    let mut i = negated as usize;
+    let mut ranges = vec![];
+    let mut first = true;
+    loop {
+        let mut c = *pattern.get(i)?;
+        if c == ']' && !first {
+            return Some((Set { negated, ranges }, &pattern[i + 1..]));
+        }
+        first = false;
+        if c == '\\' {
+            i += 1;
+            c = *pattern.get(i)?;
+        }
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                ranges.push((c, high));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        super::matches(&pattern, &name.chars().collect::<Vec<_>>())
+    }
+
+    /// A fresh directory with empty files at `paths`
+    fn dir(name: &str, paths: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rush-glob-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for path in paths {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn wildcards_match_like_in_other_shells() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*", ""));
+        assert!(!matches("*.rs", "main.rs.bak"));
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", ".txt"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[", "["));
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "a"));
+    }
+
+    #[test]
+    fn only_unescaped_wildcards_count() {
+        assert!(has_wildcards("src/*.rs"));
+        assert!(!has_wildcards(r"\*.rs"));
+        assert!(!has_wildcards("main.rs"));
+    }
+
+    #[test]
+    fn paths_match_component_by_component() {
+        let dir = dir(
+            "paths",
+            &["a.rs", ".hidden.rs", "src/b.rs", "src/deep/c.rs"],
+        );
+        assert_eq!(glob("*.rs", &dir), ["a.rs"]);
+        assert_eq!(glob(".*.rs", &dir), [".hidden.rs"]);
+        assert_eq!(glob("*/*.rs", &dir), ["src/b.rs"]);
+        assert_eq!(glob("src/*/*", &dir), ["src/deep/c.rs"]);
+        assert_eq!(glob("*/", &dir), ["src/"]);
+        assert!(glob("*.txt", &dir).is_empty());
+        let absolute = format!("{}/src/*.rs", dir.display());
+        assert_eq!(
+            glob(&absolute, Path::new("/")),
+            [format!("{}/src/b.rs", dir.display())]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn matches_are_sorted_by_code_point() {
+        let names = ["b", "a", "B", "10", "9", "A", "_x", "é", "Z1", "z"];
+        let dir = dir("sorted", &names);
+        let sorted = ["10", "9", "A", "B", "Z1", "_x", "a", "b", "z", "é"];
+        assert_eq!(glob("*", &dir), sorted);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
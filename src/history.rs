@@ -0,0 +1,391 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{Result, ShellError};
+
+/// How many commands are kept unless `HISTSIZE` says otherwise
+pub(crate) const DEFAULT_SIZE: usize = 1000;
+
+/// A command that was run
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Entry {
+    pub(crate) command: String,
+    /// When it was run, in seconds since the epoch
+    ///
+    /// Unknown for entries loaded from a history file without timestamps.
+    pub(crate) time: Option<u64>,
+    /// Its exit status, once it is done
+    ///
+    /// Only known for commands run in this session.
+    pub(crate) status: Option<i32>,
+}
+
+/// The commands run so far, optionally kept in a file across sessions
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    entries: Vec<Entry>,
+    file: Option<PathBuf>,
+    /// How many times entries were removed, for those keeping a copy
+    pub(crate) removals: usize,
+    /// How many of the oldest entries were dropped to keep to the size
+    ///
+    /// Those keeping a copy can drop as many, without starting over.
+    pub(crate) trimmed: usize,
+    /// How many entries the history file holds
+    saved: usize,
+}
+
+impl History {
+    /// Load the entries in `path` and append new ones to it from now on
+    ///
+    /// Like bash, timestamps are stored as `#<epoch>` comment lines in front
+    /// of their command, which goes on until the next timestamp so that it
+    /// can span several lines. A missing file is fine: it gets created on
+    /// the first command.
+    pub(crate) fn open(&mut self, path: PathBuf) -> Result<()> {
+        match File::open(&path) {
+            Ok(file) => {
+                // Whether the last entry has a timestamp and so may go on
+                let mut open = false;
+                let mut time = None;
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    match line.strip_prefix('#').map(str::parse) {
+                        Some(Ok(epoch)) => time = Some(epoch),
+                        _ if time.is_none() && open => {
+                            if let Some(entry) = self.entries.last_mut() {
+                                entry.command.push('\n');
+                                entry.command.push_str(&line);
+                            }
+                        }
+                        _ => {
+                            open = time.is_some();
+                            self.saved += 1;
+                            self.entries.push(Entry {
+                                command: line,
+                                time: time.take(),
+                                status: None,
+                            });
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        self.file = Some(path);
+        Ok(())
+    }
+
+    /// A copy that isn't written to the history file, for subshells
+    pub(crate) fn in_memory(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            file: None,
+            removals: self.removals,
+            trimmed: self.trimmed,
+            saved: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, command: String) -> Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        if let Some(path) = &self.file {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "#{time}\n{command}")?;
+            self.saved += 1;
+        }
+        self.entries.push(Entry {
+            command,
+            time: Some(time),
+            status: None,
+        });
+        Ok(())
+    }
+
+    /// Record the exit status of the command added last
+    pub(crate) fn finish(&mut self, status: i32) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.status.get_or_insert(status);
+        }
+    }
+
+    /// Remove the entries in `range`, counting from 0, and rewrite the
+    /// history file without them
+    pub(crate) fn remove(&mut self, range: RangeInclusive<usize>) -> Result<()> {
+        self.entries.drain(range);
+        self.removals += 1;
+        self.save()
+    }
+
+    /// Keep only the newest `size` entries
+    ///
+    /// The history file is only cut down once it holds twice as many, so
+    /// that it isn't rewritten after every command once the history is full.
+    pub(crate) fn trim(&mut self, size: usize) -> Result<()> {
+        let excess = self.entries.len().saturating_sub(size);
+        self.entries.drain(..excess);
+        self.trimmed += excess;
+        if self.saved > size.saturating_mul(2) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Write all entries to the history file, replacing what it held
+    fn save(&mut self) -> Result<()> {
+        if let Some(path) = &self.file {
+            self.saved = self.entries.len();
+            let mut file = BufWriter::new(File::create(path)?);
+            for entry in &self.entries {
+                if let Some(time) = entry.time {
+                    writeln!(file, "#{time}")?;
+                }
+                writeln!(file, "{}", entry.command)?;
+            }
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    pub(crate) fn last(&self) -> Option<&Entry> {
+        self.entries.last()
+    }
+
+    /// Replace references to the previous command in `line`
+    ///
+    /// `!!` is the whole command, `!$` its last word, `!^` its first
+    /// argument, and `!!:N` its Nth word, starting with the command name at
+    /// 0. Nothing is replaced inside single quotes or after a backslash.
+    /// Returns `None` if there was nothing to replace.
+    pub(crate) fn expand(&self, line: &str) -> Result<Option<String>> {
+        let mut expanded = String::new();
+        let mut changed = false;
+        let (mut quoted, mut double_quoted) = (false, false);
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let reference = match c {
+                '!' if !quoted => reference(rest),
+                _ => None,
+            };
+            let Some((len, designator)) = reference else {
+                match c {
+                    '\'' if !double_quoted => quoted = !quoted,
+                    '"' if !quoted => double_quoted = !double_quoted,
+                    // Keep the escaped character as it is
+                    '\\' if !quoted => {
+                        let len = rest.chars().take(2).map(char::len_utf8).sum();
+                        expanded.push_str(&rest[..len]);
+                        rest = &rest[len..];
+                        continue;
+                    }
+                    _ => (),
+                }
+                expanded.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            };
+
+            let event = &rest[..len];
+            let previous = self
+                .entries
+                .last()
+                .ok_or_else(|| ShellError::InvalidArgument(format!("{event}: event not found")))?;
+            expanded.push_str(&select_words(&previous.command, designator, event)?);
+            changed = true;
+            rest = &rest[len..];
+        }
+        Ok(changed.then_some(expanded))
+    }
+}
+
+/// The history reference at the start of `text`, if any: its length and
+/// word designator
+fn reference(text: &str) -> Option<(usize, Option<&str>)> {
+    if let Some(words) = text.strip_prefix("!!:") {
+        let digits = words
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(words.len());
+        let len = match words.chars().next() {
+            Some('$' | '^') => 1,
+            _ if digits > 0 => digits,
+            // `!!` followed by some other `:`
+            _ => return Some((2, None)),
+        };
+        return Some((3 + len, Some(&words[..len])));
+    }
+    match text.get(..2)? {
+        "!!" => Some((2, None)),
+        "!$" => Some((2, Some("$"))),
+        "!^" => Some((2, Some("^"))),
+        _ => None,
+    }
+}
+
+/// The words of `command` picked by a history word `designator`: all of
+/// them without one, a single one otherwise
+fn select_words(command: &str, designator: Option<&str>, event: &str) -> Result<String> {
+    let Some(designator) = designator else {
+        return Ok(command.to_string());
+    };
+    let words = split_words(command);
+    let index = match designator {
+        "$" => Some(words.len().saturating_sub(1)),
+        "^" => Some(1),
+        number => number.parse().ok(),
+    };
+    index
+        .and_then(|index| words.get(index))
+        .map(|word| word.to_string())
+        .ok_or_else(|| ShellError::InvalidArgument(format!("{event}: bad word specifier")))
+}
+
+/// Split a command into words at unquoted whitespace, keeping the quotes
+fn split_words(command: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in command.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+        } else if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+        } else if c == '\'' || c == '"' {
+            quote = Some(c);
+        } else if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                words.push(&command[start..i]);
+            }
+            continue;
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(start) = start {
+        words.push(&command[start..]);
+    }
+    words
+}
+
+/// Format `time` as local time, using a `strftime(3)` format like the one
+/// in `HISTTIMEFORMAT`
+#[cfg(unix)]
+pub(crate) fn format_time(time: u64, format: &str) -> String {
+    let Ok(format) = std::ffi::CString::new(format) else {
+        return String::new();
+    };
+    let time = time as libc::time_t;
+    let mut buf = [0u8; 256];
+    // SAFETY: `tm` is only read after `localtime_r` filled it in, and
+    // `strftime` writes at most `buf.len()` bytes into `buf`
+    let len = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Format `time`; without `strftime`, this is just the seconds since the
+/// epoch
+#[cfg(not(unix))]
+pub(crate) fn format_time(time: u64, _format: &str) -> String {
+    format!("{time} ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    /// A history kept in a fresh file
+    fn history(name: &str) -> (History, PathBuf) {
+        let path = std::env::temp_dir().join(format!("rush-history-{name}-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut history = History::default();
+        history.open(path.clone()).unwrap();
+        (history, path)
+    }
+
+    fn commands(history: &History) -> Vec<&str> {
+        history.iter().map(|entry| entry.command.as_str()).collect()
+    }
+
+    /// The commands in the history file, without their timestamps
+    fn saved(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn trimming_keeps_the_newest_entries() {
+        let (mut history, path) = history("newest");
+        for i in 0..5 {
+            history.push(i.to_string()).unwrap();
+            history.trim(3).unwrap();
+        }
+        assert_eq!(commands(&history), ["2", "3", "4"]);
+        assert_eq!(history.trimmed, 2);
+        assert_eq!(history.removals, 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_file_is_cut_down_in_batches() {
+        let (mut history, path) = history("batches");
+        for i in 0..6 {
+            history.push(i.to_string()).unwrap();
+            history.trim(3).unwrap();
+        }
+        // Appended to until it holds twice the size
+        assert_eq!(saved(&path), ["0", "1", "2", "3", "4", "5"]);
+        history.push("6".into()).unwrap();
+        history.trim(3).unwrap();
+        assert_eq!(saved(&path), ["4", "5", "6"]);
+
+        let mut reloaded = History::default();
+        reloaded.open(path.clone()).unwrap();
+        assert_eq!(commands(&reloaded), ["4", "5", "6"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn removing_rewrites_the_file() {
+        let (mut history, path) = history("remove");
+        for command in ["a", "b", "c"] {
+            history.push(command.into()).unwrap();
+        }
+        history.remove(1..=1).unwrap();
+        assert_eq!(saved(&path), ["a", "c"]);
+        assert_eq!(history.removals, 1);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Ctrl-C and Ctrl-Z while commands run
+//!
+//! An interactive shell survives them, and only stops or suspends what it
+//! is running.
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        OnceLock,
+    },
+    thread::ThreadId,
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// The thread that called [`catch`], which runs the commands in the
+/// foreground
+static CATCHING_THREAD: OnceLock<ThreadId> = OnceLock::new();
+/// Whether stdin was a terminal when [`catch`] was called
+static TERMINAL: AtomicBool = AtomicBool::new(false);
+/// The process group of the command running in the foreground, or 0
+static FOREGROUND: AtomicI32 = AtomicI32::new(0);
+
+/// Note `SIGINT` instead of dying from it, ignore `SIGTSTP` instead of
+/// being suspended, and pass both on to the
+/// [foreground command](set_foreground)
+///
+/// Commands started afterwards still get the default handling, which is
+/// restored when they are executed.
+#[cfg(unix)]
+pub(crate) fn catch() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        let group = FOREGROUND.load(Ordering::Relaxed);
+        if group != 0 {
+            // SAFETY: `kill` is async-signal-safe
+            unsafe { libc::kill(-group, libc::SIGINT) };
+        }
+    }
+    extern "C" fn on_stop(_: libc::c_int) {
+        let group = FOREGROUND.load(Ordering::Relaxed);
+        if group != 0 {
+            // SAFETY: `kill` is async-signal-safe
+            unsafe { libc::kill(-group, libc::SIGTSTP) };
+        }
+    }
+    let _ = CATCHING_THREAD.set(std::thread::current().id());
+    // SAFETY: isatty only looks at the descriptor
+    TERMINAL.store(unsafe { libc::isatty(0) } == 1, Ordering::Relaxed);
+    // SAFETY: the handlers only touch atomics and send signals
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_interrupt;
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        let handler: extern "C" fn(libc::c_int) = on_stop;
+        libc::signal(libc::SIGTSTP, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn catch() {}
+
+/// Whether Ctrl-C and Ctrl-Z are caught by the shell, and commands started
+/// from this thread run in the foreground
+///
+/// Those get a process group of their own, so that the signals can go to
+/// all of their processes, and to nothing else.
+pub(crate) fn caught() -> bool {
+    CATCHING_THREAD.get() == Some(&std::thread::current().id())
+}
+
+/// Pass Ctrl-C and Ctrl-Z on to the process group `group` from now on, or
+/// to nothing with `None`
+///
+/// The terminal goes to the group as well, so that it gets them straight
+/// from there and can read from it, and back to the shell with `None`. If
+/// Ctrl-C was pressed while the group was being started, the group gets it
+/// right away. Does nothing unless the signals are [`caught`].
+#[cfg(unix)]
+pub(crate) fn set_foreground(group: Option<u32>) {
+    if !caught() {
+        return;
+    }
+    let group = group.map_or(0, |group| group as i32);
+    FOREGROUND.store(group, Ordering::Relaxed);
+    if TERMINAL.load(Ordering::Relaxed) {
+        // SAFETY: getpgrp has no requirements
+        let shell = unsafe { libc::getpgrp() };
+        give_terminal(if group != 0 { group } else { shell });
+    }
+    if group != 0 && interrupted() {
+        // SAFETY: sending a signal has no memory safety requirements
+        unsafe { libc::kill(-group, libc::SIGINT) };
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_foreground(_group: Option<u32>) {}
+
+/// Make `group` the foreground process group of the terminal on stdin
+#[cfg(unix)]
+fn give_terminal(group: i32) {
+    // SAFETY: the signal sets are initialized before use, and the mask is
+    // restored right after
+    unsafe {
+        // The shell isn't in the foreground when it takes the terminal back,
+        // which would stop it
+        let mut ttou: libc::sigset_t = std::mem::zeroed();
+        let mut previous: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut ttou);
+        libc::sigaddset(&mut ttou, libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &ttou, &mut previous);
+        libc::tcsetpgrp(0, group);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+    }
+}
+
+/// Whether Ctrl-C was pressed since the last [`clear`]
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Remember that Ctrl-C was pressed, when only the foreground command got it
+/// from the terminal
+pub(crate) fn raise() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Forget about earlier presses of Ctrl-C, before running a new command
+pub(crate) fn clear() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
@@ -0,0 +1,244 @@
+use std::{fmt::Display, process::Child, thread::JoinHandle};
+
+use crate::{
+    command::{try_wait_or_stop, wait_or_stop},
+    Result, ShellError,
+};
+
+/// What a background job runs in
+pub(crate) enum Process {
+    /// A single external command
+    Child(Child),
+    /// A pipeline of external commands, one process per stage
+    Pipeline(Vec<Child>),
+    /// Anything else, run by a subshell on its own thread
+    ///
+    /// The handle is taken once the thread has been joined.
+    Thread(Option<JoinHandle<i32>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JobState {
+    Running,
+    /// Stopped by a signal, like Ctrl-Z, with the status it reports
+    Stopped(i32),
+    Done(i32),
+}
+
+impl Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Padded, so that `jobs` can line up the commands
+        match self {
+            JobState::Running => f.pad("Running"),
+            JobState::Stopped(_) => f.pad("Stopped"),
+            JobState::Done(0) => f.pad("Done"),
+            JobState::Done(status) => f.pad(&format!("Exit {status}")),
+        }
+    }
+}
+
+/// A command started with `&`
+pub(crate) struct Job {
+    pub(crate) id: usize,
+    /// The command line, as shown by `jobs`
+    pub(crate) command: String,
+    pub(crate) process: Process,
+    pub(crate) state: JobState,
+    /// The process group of its own that the job runs in, if any
+    pub(crate) group: Option<u32>,
+}
+
+impl Job {
+    /// Process ID, if the job runs in its own process, or that of the
+    /// first stage of a pipeline
+    pub(crate) fn pid(&self) -> Option<u32> {
+        match &self.process {
+            Process::Child(child) => Some(child.id()),
+            Process::Pipeline(children) => children.first().map(Child::id),
+            Process::Thread(_) => None,
+        }
+    }
+
+    /// Send `signal` to all of the job's processes
+    ///
+    /// Jobs running on a thread of the shell can't be signaled.
+    #[cfg(unix)]
+    pub(crate) fn signal(&self, signal: i32) -> Result<()> {
+        let children = match &self.process {
+            Process::Child(child) => std::slice::from_ref(child),
+            Process::Pipeline(children) => children.as_slice(),
+            Process::Thread(_) => {
+                return Err(ShellError::InvalidArgument(format!(
+                    "kill: %{}: job runs inside the shell and can't be signaled",
+                    self.id
+                )));
+            }
+        };
+        for child in children {
+            // Stages that are done already are fine to skip
+            // SAFETY: kill only sends a signal
+            unsafe { libc::kill(child.id() as i32, signal) };
+        }
+        Ok(())
+    }
+
+    /// Let a stopped job carry on in the background
+    #[cfg(unix)]
+    pub(crate) fn resume(&mut self) -> Result<()> {
+        match self.group {
+            // SAFETY: kill only sends a signal
+            Some(group) => unsafe {
+                libc::kill(-(group as i32), libc::SIGCONT);
+            },
+            None => self.signal(libc::SIGCONT)?,
+        }
+        self.state = JobState::Running;
+        Ok(())
+    }
+
+    /// Check whether the job has finished or was stopped, without blocking
+    fn poll(&mut self) -> Result<JobState> {
+        if self.state != JobState::Running {
+            return Ok(self.state);
+        }
+        match &mut self.process {
+            Process::Child(child) => {
+                if let Some(state) = try_wait_or_stop(child)? {
+                    self.state = state;
+                }
+            }
+            Process::Pipeline(children) => {
+                // Like when running it in the foreground, the status is the
+                // last stage's
+                let mut state = JobState::Done(0);
+                for child in children {
+                    match try_wait_or_stop(child)? {
+                        Some(JobState::Stopped(status)) => {
+                            self.state = JobState::Stopped(status);
+                            return Ok(self.state);
+                        }
+                        Some(exit) => state = exit,
+                        None => return Ok(self.state),
+                    }
+                }
+                self.state = state;
+            }
+            Process::Thread(handle) => {
+                if handle.as_ref().is_some_and(|handle| handle.is_finished()) {
+                    let status = handle.take().map_or(1, |handle| handle.join().unwrap_or(1));
+                    self.state = JobState::Done(status);
+                }
+            }
+        }
+        Ok(self.state)
+    }
+
+    /// Block until the job has finished or was stopped, and return its
+    /// status
+    pub(crate) fn wait(&mut self) -> Result<i32> {
+        match &mut self.process {
+            Process::Child(child) => self.state = wait_or_stop(child)?,
+            Process::Pipeline(children) => {
+                for child in children {
+                    self.state = wait_or_stop(child)?;
+                    if let JobState::Stopped(_) = self.state {
+                        break;
+                    }
+                }
+            }
+            Process::Thread(handle) => {
+                if let Some(handle) = handle.take() {
+                    self.state = JobState::Done(handle.join().unwrap_or(1));
+                }
+            }
+        }
+        match self.state {
+            JobState::Done(status) | JobState::Stopped(status) => Ok(status),
+            JobState::Running => unreachable!("job {} is still running", self.id),
+        }
+    }
+}
+
+/// The background jobs of a shell
+///
+/// Jobs belong to the shell that started them, so a subshell starts out
+/// without any: cloning gives an empty list.
+#[derive(Default)]
+pub(crate) struct Jobs(Vec<Job>);
+
+impl Clone for Jobs {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Jobs {
+    /// Start tracking a new job, running in the process group `group` if
+    /// it has one of its own, and return it
+    pub(crate) fn add(
+        &mut self,
+        command: String,
+        process: Process,
+        group: Option<u32>,
+    ) -> &mut Job {
+        let id = self.0.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.0.push(Job {
+            id,
+            command,
+            process,
+            state: JobState::Running,
+            group,
+        });
+        let last = self.0.len() - 1;
+        &mut self.0[last]
+    }
+
+    /// Track a job again after it was [removed](Jobs::remove), as the most
+    /// recent one
+    pub(crate) fn restore(&mut self, job: Job) {
+        self.0.push(job);
+    }
+
+    pub(crate) fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.0.iter_mut().find(|job| job.id == id)
+    }
+
+    /// Update the state of all jobs
+    pub(crate) fn poll(&mut self) -> Result<()> {
+        for job in &mut self.0 {
+            job.poll()?;
+        }
+        Ok(())
+    }
+
+    /// Remove finished jobs from the list and return them, keeping stopped
+    /// ones
+    pub(crate) fn reap(&mut self) -> Result<Vec<Job>> {
+        self.poll()?;
+        let (done, running) = std::mem::take(&mut self.0)
+            .into_iter()
+            .partition(|job| matches!(job.state, JobState::Done(_)));
+        self.0 = running;
+        Ok(done)
+    }
+
+    /// Stop tracking job number `id` and return it
+    pub(crate) fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.0.iter().position(|job| job.id == id)?;
+        Some(self.0.remove(index))
+    }
+
+    /// Stop tracking all jobs and return them, oldest first
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = Job> + '_ {
+        self.0.drain(..)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.0.iter()
+    }
+
+    /// The number of the most recently started job
+    pub(crate) fn current(&self) -> Option<usize> {
+        self.0.last().map(|job| job.id)
+    }
+}
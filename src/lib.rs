@@ -0,0 +1,407 @@
+//! A tiny shell, usable as a library
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+mod arith;
+mod builtins;
+mod color;
+mod command;
+mod editor;
+mod error;
+mod expand;
+mod glob;
+mod history;
+mod interrupt;
+mod jobs;
+mod parser;
+mod profile;
+mod redirect;
+mod runner;
+
+pub use color::print_error;
+pub use command::{Command, CommandChain};
+pub use error::ShellError;
+pub use redirect::Redirect;
+
+use runner::CommandRunner;
+use serde::Serialize;
+
+pub type Result<T> = std::result::Result<T, ShellError>;
+
+/// Shown before reading a command, unless `PS1` is set
+///
+/// The status of the last command shows up in front if it failed.
+const PROMPT: &str = "\\?> ";
+/// Shown while reading the rest of a command that goes on over several
+/// lines, unless `PS2` is set
+const CONTINUATION_PROMPT: &str = "> ";
+
+/// Greeting shown when entering interactive mode
+pub fn banner() -> String {
+    format!(
+        "{} {}\nWelcome! Type `exit` to leave.\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Parse `input` into the commands it consists of, without running them
+///
+/// Nothing is expanded and aliases aren't replaced, so the result is the
+/// same whatever shell would run it. Input that isn't finished, like an
+/// unclosed quote or `if` without `fi`, is an error.
+pub fn parse(input: &str) -> Result<Vec<CommandChain>> {
+    parser::parse(input, &std::collections::HashMap::new())
+}
+
+/// Where [`Shell`] reads its commands from, one line at a time
+trait LineSource {
+    /// Read the next line, including its newline, or `None` at EOF
+    ///
+    /// `prompt` is shown first in interactive sessions, on `out` unless the
+    /// source has a better place for it.
+    fn next_line(&mut self, prompt: Option<&str>, out: &mut dyn Write) -> Result<Option<String>>;
+
+    /// Catch up with changes to the shell, before reading a command
+    fn refresh(&mut self, _runner: &CommandRunner) {}
+}
+
+/// Lines from a reader, with prompts written to the output
+struct Lines<R>(R);
+
+impl<R: BufRead> LineSource for Lines<R> {
+    fn next_line(&mut self, prompt: Option<&str>, out: &mut dyn Write) -> Result<Option<String>> {
+        if let Some(prompt) = prompt {
+            write!(out, "{prompt}")?;
+            out.flush()?;
+        }
+        let mut line = String::new();
+        Ok((self.0.read_line(&mut line)? > 0).then(|| runner::strip_cr(&line)))
+    }
+}
+
+/// The result of running some input through the shell
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Output {
+    /// Everything the commands wrote to stdout
+    pub stdout: Vec<u8>,
+    /// Exit status of the last command
+    pub status: i32,
+}
+
+/// A summary of a command the shell ran, reported with `--json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommandResult {
+    /// The command as it was given, before expansion
+    pub command: String,
+    /// Its exit status
+    pub status: i32,
+    /// How much it wrote to stdout
+    pub stdout_bytes: usize,
+    /// How long it took to run
+    pub duration_ms: u64,
+}
+
+/// What to do with an external command that is about to run, as decided
+/// by the hook set with [`Shell::set_pre_exec_hook`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Run it as it is
+    Allow,
+    /// Don't run it: it fails with status 126 instead
+    Deny,
+    /// Run this command instead
+    Rewrite(Command),
+}
+
+/// An embeddable shell
+pub struct Shell {
+    runner: CommandRunner,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self {
+            runner: CommandRunner::new(),
+        }
+    }
+
+    /// Parse and run a single line of input
+    pub fn run_line(&mut self, line: &str) -> Result<Output> {
+        let mut stdout = vec![];
+        let status = self.eval(line, &mut stdout)?;
+        Ok(Output { stdout, status })
+    }
+
+    /// Run every line from `reader` until EOF or `exit`
+    ///
+    /// Errors are reported on stderr and don't stop the remaining lines.
+    pub fn run_reader(&mut self, reader: impl BufRead) -> Result<Output> {
+        let mut stdout = vec![];
+        let status = self.run_lines(&mut Lines(reader), &mut stdout, None)?;
+        Ok(Output { stdout, status })
+    }
+
+    /// Run every line from `input` as a script, writing output to `output`
+    /// as it goes
+    ///
+    /// Unlike [`Shell::run_with`], no prompts are shown and the session
+    /// isn't interactive. Returns the status of the last command once
+    /// `input` hits EOF or `exit` is called.
+    pub fn run_input<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> Result<i32> {
+        self.run_lines(&mut Lines(input), &mut output, None)
+    }
+
+    /// Run an interactive session on the given streams
+    ///
+    /// Shows a prompt on `output` before reading each line from `input`.
+    /// Returns the status of the last command once `input` hits EOF or
+    /// `exit` is called.
+    pub fn run_with<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> Result<i32> {
+        self.runner.interactive = true;
+        self.run_lines(&mut Lines(input), &mut output, Some(PROMPT))
+    }
+
+    /// Run the script file at `path`, writing its output to `output`
+    ///
+    /// A leading `#!` line and byte order mark are skipped. Returns the
+    /// status of the last command, or [`ShellError::BinaryFile`] without
+    /// running anything if the file isn't text.
+    pub fn run_file<W: Write>(&mut self, path: impl AsRef<Path>, mut output: W) -> Result<i32> {
+        let contents = runner::read_script(path.as_ref())?;
+        let name = path.as_ref().display().to_string();
+        self.runner
+            .run_script(&name, runner::script_body(&contents), &mut output)
+    }
+
+    /// Run the commands in `script`, writing their output to `output`
+    ///
+    /// Like [`Shell::run_file`], with errors reported as coming from `-c`.
+    pub fn run_string<W: Write>(&mut self, script: &str, mut output: W) -> Result<i32> {
+        self.runner.run_script("-c", script, &mut output)
+    }
+
+    /// Run an interactive session on the terminal
+    ///
+    /// Like [`Shell::run_with`] on stdin and stdout, but with line editing
+    /// and tab completion of commands and file names. Falls back to reading
+    /// plain lines, with a warning, if the terminal doesn't support editing.
+    pub fn run_interactive(&mut self) -> Result<i32> {
+        self.runner.interactive = true;
+        interrupt::catch();
+        let mut input: Box<dyn LineSource> = match editor::LineEditor::new() {
+            Ok(editor) => Box::new(editor),
+            Err(e) => {
+                print_error(format_args!("line editing unavailable: {e}"));
+                Box::new(Lines(std::io::stdin().lock()))
+            }
+        };
+        self.run_lines(input.as_mut(), &mut std::io::stdout(), Some(PROMPT))
+    }
+
+    fn run_lines(
+        &mut self,
+        input: &mut dyn LineSource,
+        out: &mut dyn Write,
+        prompt: Option<&str>,
+    ) -> Result<i32> {
+        loop {
+            if prompt.is_some() {
+                self.runner.reap_jobs()?;
+            }
+            input.refresh(&self.runner);
+
+            let ps1 = prompt.map(|default| {
+                self.runner
+                    .prompt(&self.runner.var("PS1").unwrap_or(default.into()))
+            });
+            let Some(mut line) = input.next_line(ps1.as_deref(), out)? else {
+                // EOF
+                break;
+            };
+            while parser::needs_more(&mut line) {
+                let prompt = prompt.map(|_| {
+                    let ps2 = self.runner.var("PS2");
+                    self.runner
+                        .prompt(ps2.as_deref().unwrap_or(CONTINUATION_PROMPT))
+                });
+                match input.next_line(prompt.as_deref(), out)? {
+                    Some(more) => line.push_str(&more),
+                    None => break,
+                }
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Err(e) = self.eval(&line, out) {
+                print_error(e);
+            }
+            if self.exit_code().is_some() {
+                break;
+            }
+        }
+        Ok(self.runner.last_status)
+    }
+
+    /// Parse `line` and run it, writing output to `out`
+    fn eval(&mut self, line: &str, out: &mut dyn Write) -> Result<i32> {
+        // Like in bash, `!!` and friends only work in interactive sessions,
+        // and the command they make up is shown before it runs
+        let expanded = if self.runner.interactive {
+            let expanded = self.runner.history.expand(line);
+            expanded.inspect_err(|e| self.runner.last_status = e.status())?
+        } else {
+            None
+        };
+        let line = match &expanded {
+            Some(expanded) => {
+                writeln!(out, "{}", expanded.trim_end_matches('\n'))?;
+                expanded
+            }
+            None => line,
+        };
+
+        // Not being able to write the history file shouldn't stop anything
+        if let Err(e) = self.runner.remember(line) {
+            print_error(e);
+        }
+        interrupt::clear();
+        let result = self
+            .runner
+            .parse(line)
+            .and_then(|commands| self.runner.run(commands, out))
+            .inspect_err(|e| self.runner.last_status = e.status());
+        self.runner.history.finish(self.runner.last_status);
+        result
+    }
+
+    /// Keep history in the file at `path`
+    ///
+    /// Loads the commands already in there, and appends every command run
+    /// from now on.
+    pub fn set_history_file(&mut self, path: impl Into<PathBuf>) -> Result<()> {
+        self.runner.history.open(path.into())
+    }
+
+    /// Keep aliases in the file at `path`
+    ///
+    /// Defines the aliases already saved in there, and `alias -s` saves all
+    /// of them there.
+    pub fn set_aliases_file(&mut self, path: impl Into<PathBuf>) -> Result<()> {
+        self.runner.load_aliases(path.into())
+    }
+
+    /// Set `$0` to `name`, and the positional parameters `$1`, `$2`, ... to
+    /// `args`
+    pub fn set_args(&mut self, name: &str, args: &[String]) {
+        self.runner.name = name.to_string();
+        self.runner.args = args.to_vec();
+    }
+
+    /// Set the variable `name` to `value`, and pass it on to the commands
+    /// the shell runs, like `export name=value`
+    pub fn export(&mut self, name: &str, value: &str) {
+        self.runner.set_var(name, value.to_string());
+        self.runner.exported.insert(name.to_string());
+    }
+
+    /// Keep `cd` and redirections inside the directory at `root`
+    ///
+    /// Anything resolving to a path outside of it fails with
+    /// `sandbox: path escapes root`.
+    pub fn set_sandbox(&mut self, root: impl AsRef<Path>) -> Result<()> {
+        self.runner.sandbox = Some(runner::canonicalize(root.as_ref())?);
+        Ok(())
+    }
+
+    /// Print commands, expanded, instead of running them, like with
+    /// `set -n`
+    ///
+    /// Has no effect on interactive sessions.
+    pub fn set_noexec(&mut self, noexec: bool) {
+        if let Some(option) = self.runner.options.get_mut("noexec") {
+            *option = noexec;
+        }
+    }
+
+    /// Report each command, once it ran, as a [`CommandResult`] in a line
+    /// of JSON on stderr
+    ///
+    /// Only the commands given to the shell are reported, not the ones
+    /// inside them, like in a loop's body.
+    pub fn set_json(&mut self, json: bool) {
+        self.runner.json = json;
+    }
+
+    /// Let `exec` replace the whole process with its command, as in other
+    /// shells, instead of running it and then exiting
+    ///
+    /// Only for programs that are nothing but the shell, since everything
+    /// else in the process goes away too.
+    pub fn set_replace_on_exec(&mut self, replace: bool) {
+        self.runner.replace_on_exec = replace;
+    }
+
+    /// Call `hook` with each external command, once it is expanded and just
+    /// before it runs, to allow, deny or rewrite it
+    ///
+    /// That includes the stages of pipelines, background jobs and commands
+    /// run by `timeout`, `exec` and `coproc`, and those in subshells. A
+    /// rewritten command runs as an external one as well.
+    pub fn set_pre_exec_hook(&mut self, hook: impl FnMut(&Command) -> Decision + Send + 'static) {
+        self.runner.pre_exec = Some(Arc::new(Mutex::new(hook)));
+    }
+
+    /// Call `hook` with each external command that ran in the foreground
+    /// and its exit status
+    ///
+    /// The stages of a pipeline all get the status of the pipeline.
+    pub fn set_post_exec_hook(&mut self, hook: impl FnMut(&Command, i32) + Send + 'static) {
+        self.runner.post_exec = Some(Arc::new(Mutex::new(hook)));
+    }
+
+    /// Only let external commands with these names run
+    ///
+    /// Commands have to match exactly as they are given, so allowing `ls`
+    /// doesn't allow `/tmp/ls`: list full paths to allow those. Others
+    /// fail with status 126. Builtins aren't affected.
+    pub fn set_allowed_commands<S: Into<String>>(&mut self, names: impl IntoIterator<Item = S>) {
+        self.runner.allowed = Some(names.into_iter().map(Into::into).collect());
+    }
+
+    /// Don't let external commands with these names run, wherever they are
+    ///
+    /// Denying `rm` also denies `/bin/rm` and `./rm`. They fail with status
+    /// 126 instead. Builtins aren't affected.
+    pub fn set_denied_commands<S: Into<String>>(&mut self, names: impl IntoIterator<Item = S>) {
+        self.runner.denied = names.into_iter().map(Into::into).collect();
+    }
+
+    /// Names of all builtin commands
+    pub fn builtins() -> impl Iterator<Item = String> {
+        let mut names: Vec<String> = builtins::registry().into_keys().collect();
+        names.sort();
+        names.into_iter()
+    }
+
+    /// Where the time went, when profiling with `RUSTLAB_PROFILE=1`
+    pub fn profile(&self) -> Option<String> {
+        let profile = &self.runner.profile;
+        profile.enabled().then(|| profile.to_string())
+    }
+
+    /// The status passed to `exit`, once it has been called
+    pub fn exit_code(&self) -> Option<i32> {
+        self.runner.exit
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
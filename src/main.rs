@@ -1,175 +1,340 @@
-use std::{env, fmt::Display, io::Write, path::PathBuf, process::Stdio};
+use std::{
+    env,
+    io::{BufReader, IsTerminal, Write},
+    path::PathBuf,
+    time::Instant,
+};
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-const PROMPT: &str = "> ";
+use rush::{print_error, Result, Shell, ShellError};
 
-/// Show prompt
-fn show_prompt() -> Result<()> {
-    print!("{PROMPT}");
-    Ok(std::io::stdout().flush()?)
+/// What `--help` prints
+const USAGE: &str = "\
+usage: rush [options] [script]
+       rush [options] -c commands
+
+Runs the commands in `script`, or reads them from stdin, with a prompt if
+it is a terminal.
+
+options:
+  -c commands         run `commands` and exit
+  -n                  print commands instead of running them
+  --json              report each command's status, output size and
+                      duration as a line of JSON on stderr
+  -q, --quiet         don't show the banner
+  --rcfile PATH       source PATH instead of ~/.rustlabrc on startup
+  --sandbox DIR       keep `cd` and redirections inside DIR
+  --profile-startup   report how long sourcing the startup file took
+  --list-builtins     list the builtin commands and exit
+  --version           print the version and exit
+  -h, --help          print this help and exit
+
+environment:
+  RUSTLAB_RC          the startup file, instead of ~/.rustlabrc
+  RUSTLAB_HISTFILE    the history file, instead of ~/.rustlab_history
+  RUSTLAB_ALIASES     where `alias -s` saves aliases, instead of
+                      ~/.rustlab_aliases
+  HISTSIZE            how many commands to keep in the history (1000)
+  HISTCONTROL         ignorespace, ignoredups or ignoreboth, to leave
+                      commands out of the history
+  RUSTLAB_NO_BANNER   don't show the banner
+  SHELL_DEPTH         how many shells this one runs inside, counted up
+                      and passed on by each of them
+  RUSTLAB_MAX_DEPTH   how deeply shells may run inside each other (100)
+";
+
+/// How deeply shells may run inside each other, unless `RUSTLAB_MAX_DEPTH`
+/// says otherwise
+const MAX_DEPTH: u32 = 100;
+
+/// Whether to greet the user with a banner on startup
+///
+/// Only interactive sessions get one, and it can be turned off with
+/// `-q`/`--quiet` or by setting `RUSTLAB_NO_BANNER`, as `no_banner` says.
+fn wants_banner(args: &[String], interactive: bool, no_banner: bool) -> bool {
+    let quiet = args.iter().any(|arg| arg == "-q" || arg == "--quiet");
+    interactive && !quiet && !no_banner
 }
 
-#[derive(Debug)]
-struct Command {
-    bin: String,
-    args: Vec<String>,
+/// Where to keep the history of interactive sessions
+///
+/// `RUSTLAB_HISTFILE` if set, `~/.rustlab_history` otherwise.
+fn history_file() -> Option<PathBuf> {
+    env::var_os("RUSTLAB_HISTFILE")
+        .map(PathBuf::from)
+        .or_else(|| Some(env::home_dir()?.join(".rustlab_history")))
 }
 
-impl Command {
-    fn execute(&self, cwd: &PathBuf, input: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
-        let mut cmd = std::process::Command::new(&self.bin)
-            .args(&self.args)
-            .current_dir(cwd)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
-
-        // If we have input, write it to stdin
-        if let Some(input) = input {
-            if let Some(mut stdin) = cmd.stdin.take() {
-                stdin.write_all(&input)?;
-            }
-        }
+/// Where `alias -s` saves the aliases of interactive sessions
+///
+/// `RUSTLAB_ALIASES` if set, `~/.rustlab_aliases` otherwise.
+fn aliases_file() -> Option<PathBuf> {
+    env::var_os("RUSTLAB_ALIASES")
+        .map(PathBuf::from)
+        .or_else(|| Some(env::home_dir()?.join(".rustlab_aliases")))
+}
 
-        let output = cmd.wait_with_output()?;
-        Ok(Some(output.stdout))
-    }
+/// The startup file run before the first prompt
+///
+/// `--rcfile PATH` if given, then `RUSTLAB_RC` if set, `~/.rustlabrc`
+/// otherwise.
+fn rc_file(rcfile: Option<&String>) -> Option<PathBuf> {
+    rcfile
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("RUSTLAB_RC").map(PathBuf::from))
+        .or_else(|| Some(env::home_dir()?.join(".rustlabrc")))
 }
 
-impl Display for Command {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} {}", self.bin, self.args.join(" "))
+/// Where the options in `args` end
+///
+/// That's at the first argument that is neither an option nor the value of
+/// one: the script to run, or the name for `$0` with `-c`. Everything from
+/// there on is for the script.
+fn options_end(args: &[String]) -> usize {
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        match arg.as_str() {
+            "-c" | "--rcfile" | "--sandbox" => i += 2,
+            arg if arg.starts_with('-') => i += 1,
+            _ => return i,
+        }
     }
+    args.len()
 }
 
-/// A chain of commands
-///
-/// # Examples
+/// Stdin, without the buffer of `std::io::stdin()` that would keep what
+/// it read ahead to itself
 ///
-/// ```sh
-/// echo 1
-/// echo 1; echo 2
-/// echo "hello world" | wc
-/// ```
-enum CommandChain {
-    Command(Command),
-    Piped((Command, Command)),
+/// Reads descriptor 0 itself rather than a copy of it, which would take
+/// up a descriptor like 3 that scripts may want to open with `exec`.
+#[cfg(unix)]
+struct UnbufferedStdin;
+
+#[cfg(unix)]
+impl std::io::Read for UnbufferedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // SAFETY: `buf` is valid for writes of its length
+        let n = unsafe { libc::read(0, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
 }
 
-fn parse_command(cmd1: &str) -> Result<Command> {
-    let parts: Vec<String> = cmd1.split_whitespace().map(String::from).collect();
+#[cfg(unix)]
+fn unbuffered_stdin() -> UnbufferedStdin {
+    UnbufferedStdin
+}
 
-    let (cmd, args) = match parts.split_first() {
-        Some(list) => list,
-        None => return Err("No command given".into()),
-    };
-    Ok(Command {
-        bin: cmd.to_string(),
-        args: args.to_owned(),
-    })
+#[cfg(not(unix))]
+fn unbuffered_stdin() -> std::io::Stdin {
+    std::io::stdin()
 }
 
-/// Read a vector of commands from stdin
-fn parse_cmds() -> Result<Vec<CommandChain>> {
-    let mut buf = String::new();
-    std::io::stdin().read_line(&mut buf)?;
-
-    // First, split commands by `;`
-    // E.g. "cmd1; cmd2 | cmd3" => ["cmd1", "cmd2 | cmd3"]
-    let raw_commands: Vec<&str> = buf.trim().split_terminator(';').collect();
-    let mut commands = vec![];
-
-    for raw_command in raw_commands {
-        // Split by pipe (`|`)
-        // For now, only a single pipe is supported
-        let splitted: Vec<&str> = raw_command.split("|").collect();
-        match splitted.as_slice() {
-            [cmd1, cmd2] => {
-                let cmd1 = parse_command(cmd1)?;
-                let cmd2 = parse_command(cmd2)?;
-                commands.push(CommandChain::Piped((cmd1, cmd2)));
-            }
-            [cmd1] => {
-                let cmd = parse_command(cmd1)?;
-                commands.push(CommandChain::Command(cmd));
-            }
-            _ => {
-                return Err(format!("Expected one or two commands, got {raw_command}").into());
+/// Exit with `status`, which only keeps its lowest 8 bits like in other
+/// shells, so that `exit -1` exits with 255
+///
+/// Prints where the time went first when profiling.
+fn exit(shell: &Shell, status: i32) -> ! {
+    if let Some(profile) = shell.profile() {
+        eprint!("{profile}");
+    }
+    std::process::exit(status & 0xff)
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (args, operands) = args.split_at(options_end(&args));
+
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--version") {
+        println!("rush {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--list-builtins") {
+        let mut stdout = std::io::stdout().lock();
+        for name in Shell::builtins() {
+            // Stop quietly once the reader, like `head`, has gone away
+            if writeln!(stdout, "{name}").is_err() {
+                break;
             }
         }
+        return Ok(());
     }
 
-    Ok(commands)
-}
+    let mut shell = Shell::new();
+    shell.set_replace_on_exec(true);
 
-struct CommandRunner {
-    pwd: PathBuf,
-    history: Vec<String>,
-}
+    // `SHELL_DEPTH` counts how many shells run inside each other, so that
+    // a script that keeps starting the shell again is stopped
+    let depth = env::var("SHELL_DEPTH")
+        .ok()
+        .and_then(|depth| depth.parse::<u32>().ok())
+        .unwrap_or(0)
+        .saturating_add(1);
+    let max_depth = env::var("RUSTLAB_MAX_DEPTH")
+        .ok()
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or(MAX_DEPTH);
+    if depth > max_depth {
+        print_error(format_args!(
+            "shell depth ({depth}) too high, at most {max_depth} shells can run inside each other"
+        ));
+        exit(&shell, 1);
+    }
+    shell.export("SHELL_DEPTH", &depth.to_string());
 
-impl CommandRunner {
-    fn new() -> Self {
-        Self {
-            pwd: env::current_dir().expect("Cannot get current_dir"),
-            history: vec![],
+    // `--rcfile PATH` sources PATH instead of the usual startup file
+    let rcfile = args.iter().position(|arg| arg == "--rcfile");
+    if let Some(i) = rcfile {
+        if args.get(i + 1).is_none() {
+            print_error("--rcfile: option requires an argument");
+            exit(&shell, 2);
         }
     }
 
-    /// Execute command and return output
-    fn run(&mut self, chains: Vec<CommandChain>) -> Result<()> {
-        for chain in chains {
-            let output: Result<Option<_>> = match chain {
-                CommandChain::Command(command) => {
-                    self.history.push(command.to_string());
-
-                    match command.bin.as_ref() {
-                        "cd" => {
-                            // Expect one arg - the path to cd into
-                            let Some(path) = command.args.first() else {
-                                return Err("Expected a single path".into());
-                            };
-                            self.pwd = self.pwd.join(path).canonicalize()?;
-
-                            Ok(None)
-                        }
-                        "exit" => {
-                            let exit_code = match command.args.first() {
-                                Some(exit_code) => exit_code.parse()?,
-                                None => 0,
-                            };
-                            std::process::exit(exit_code);
-                        }
-                        "history" => {
-                            for command in &self.history {
-                                println!("{command}");
-                            }
-                            Ok(None)
-                        }
-                        _ => command.execute(&self.pwd, None),
-                    }
-                }
-                CommandChain::Piped((cmd1, cmd2)) => {
-                    // Pipe the output of one command into the other
-                    let output1 = cmd1.execute(&self.pwd, None)?.unwrap_or_default();
-                    let output2 = cmd2.execute(&self.pwd, Some(output1))?;
-                    Ok(output2)
+    // `--sandbox DIR` keeps `cd` and redirections inside DIR
+    let sandbox = args.iter().position(|arg| arg == "--sandbox");
+    if let Some(i) = sandbox {
+        let Some(root) = args.get(i + 1) else {
+            print_error("--sandbox: option requires an argument");
+            exit(&shell, 2);
+        };
+        if let Err(e) = shell.set_sandbox(root) {
+            print_error(format_args!("{root}: {e}"));
+            exit(&shell, 2);
+        }
+    }
+
+    // `-n` only shows what scripts would run
+    if args.iter().any(|arg| arg == "-n") {
+        shell.set_noexec(true);
+    }
+
+    if args.iter().any(|arg| arg == "--json") {
+        shell.set_json(true);
+    }
+
+    // `-c commands` runs the commands instead of reading them
+    if let Some(i) = args.iter().position(|arg| arg == "-c") {
+        let Some(script) = args.get(i + 1) else {
+            print_error("-c: option requires an argument");
+            exit(&shell, 2);
+        };
+        // `rush -c commands name args...` sets `$0` and the parameters
+        if let Some((name, rest)) = operands.split_first() {
+            shell.set_args(name, rest);
+        }
+        let status = shell
+            .run_string(script, std::io::stdout())
+            .unwrap_or_else(|e| {
+                print_error(&e);
+                e.status()
+            });
+        exit(&shell, shell.exit_code().unwrap_or(status));
+    }
+
+    // The first argument that isn't an option is a script to run, with the
+    // rest as its parameters
+    if let Some((script, rest)) = operands.split_first() {
+        shell.set_args(script, rest);
+        let status = shell
+            .run_file(script, std::io::stdout())
+            .unwrap_or_else(|e| {
+                print_error(format_args!("{script}: {e}"));
+                match e {
+                    ShellError::Io(e) if e.kind() == std::io::ErrorKind::NotFound => 127,
+                    e => e.status(),
                 }
-            };
+            });
+        exit(&shell, shell.exit_code().unwrap_or(status));
+    }
 
-            if let Ok(Some(output)) = output {
-                print!("{}", String::from_utf8(output)?);
+    let interactive = std::io::stdin().is_terminal();
+    let no_banner = env::var_os("RUSTLAB_NO_BANNER").is_some();
+    if wants_banner(args, interactive, no_banner) {
+        print!("{}", rush::banner());
+    }
+    if let Some(path) = history_file().filter(|_| interactive) {
+        if let Err(e) = shell.set_history_file(&path) {
+            print_error(format_args!("{}: {e}", path.display()));
+        }
+    }
+    if let Some(path) = aliases_file().filter(|_| interactive) {
+        if let Err(e) = shell.set_aliases_file(&path) {
+            print_error(format_args!("{}: {e}", path.display()));
+        }
+    }
+
+    // Errors in the startup file are reported line by line, so only a
+    // missing file is silently skipped, unless it was asked for by name
+    let profile_startup = args.iter().any(|arg| arg == "--profile-startup");
+    let rcfile = rcfile.and_then(|i| args.get(i + 1));
+    if let Some(path) = rc_file(rcfile) {
+        let start = Instant::now();
+        let sourced = match shell.run_file(&path, std::io::stdout()) {
+            Ok(_) => true,
+            Err(ShellError::Io(e))
+                if e.kind() == std::io::ErrorKind::NotFound && rcfile.is_none() =>
+            {
+                false
+            }
+            Err(e) if rcfile.is_some() => {
+                print_error(format_args!("{}: {e}", path.display()));
+                exit(&shell, 2);
             }
+            Err(e) => {
+                print_error(format_args!("{}: {e}", path.display()));
+                true
+            }
+        };
+        if sourced && profile_startup {
+            eprintln!("rush startup: {} {:.3?}", path.display(), start.elapsed());
         }
-        Ok(())
     }
+
+    let result = if interactive {
+        shell.run_interactive()
+    } else {
+        // Take commands a byte at a time, so that `read`, and the command
+        // `exec` replaces the shell with, get the lines right after theirs
+        let stdin = BufReader::with_capacity(1, unbuffered_stdin());
+        shell.run_input(stdin, std::io::stdout())
+    };
+    let status = result.unwrap_or_else(|e| {
+        // Most likely our output went away, so there is nothing left to do
+        print_error(&e);
+        exit(&shell, e.status());
+    });
+
+    // At EOF, that's the status of the last command
+    exit(&shell, shell.exit_code().unwrap_or(status));
 }
 
-fn main() -> Result<()> {
-    let mut runner = CommandRunner::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn interactive_sessions_get_a_banner() {
+        assert!(wants_banner(&args(&["rush"]), true, false));
+        let banner = rush::banner();
+        assert!(banner.starts_with(&format!("rush {}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(banner.contains("Welcome"));
+    }
 
-    loop {
-        show_prompt()?;
-        let Ok(commands) = parse_cmds() else { continue };
-        runner.run(commands)?;
+    #[test]
+    fn the_banner_can_be_turned_off() {
+        assert!(!wants_banner(&args(&["rush"]), false, false));
+        assert!(!wants_banner(&args(&["rush", "-q"]), true, false));
+        assert!(!wants_banner(&args(&["rush", "--quiet"]), true, false));
+        assert!(!wants_banner(&args(&["rush"]), true, true));
     }
 }
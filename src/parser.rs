@@ -0,0 +1,803 @@
+use std::collections::HashMap;
+
+use crate::{command::is_name, Command, CommandChain, Redirect, Result, ShellError};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A word, with quotes and escapes still in place
+    Word(String),
+    /// `;`, or the end of a line
+    Semi,
+    /// `|`
+    Pipe,
+    /// `&`
+    Amp,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// A redirection operator for a file descriptor, like `2>>`
+    Redirect(u32, RedirectOp),
+    /// The expression inside `(( ... ))`
+    Arith(String),
+    /// The delimiter of a here-document and the lines up to it, following
+    /// a `<<` redirection
+    Heredoc { delimiter: String, body: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RedirectOp {
+    /// `>`
+    Write,
+    /// `>>`
+    Append,
+    /// `<`
+    Read,
+    /// `>&` or `<&`
+    Dup,
+    /// `&>`, or `&>>` to append: both stdout and stderr
+    Both { append: bool },
+    /// `<<`, or `<<-` to strip leading tabs from the here-document
+    Heredoc,
+    /// `<<<`
+    HereString,
+}
+
+/// A `<<` redirection whose here-document hasn't been read yet
+struct PendingHeredoc {
+    /// Where the delimiter is in the tokens
+    index: usize,
+    strip_tabs: bool,
+}
+
+/// Split a line into tokens
+///
+/// Quotes, escapes and `$(...)` substitutions are kept verbatim inside the
+/// words; they are only interpreted when the word is expanded.
+fn tokenize(line: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    let mut heredocs = vec![];
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+            }
+            '\n' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                // Here-documents start on the line after their `<<`
+                read_heredocs(&mut chars, &mut tokens, &mut heredocs)?;
+                // A line ends a command like `;`, unless the command goes on
+                if matches!(
+                    tokens.last(),
+                    Some(Token::Word(_) | Token::Arith(_) | Token::Heredoc { .. })
+                ) {
+                    tokens.push(Token::Semi);
+                }
+            }
+            '&' if chars.peek() == Some(&'>') => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                chars.next();
+                let append = chars.next_if_eq(&'>').is_some();
+                tokens.push(Token::Redirect(1, RedirectOp::Both { append }));
+            }
+            ';' | '|' | '&' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                tokens.push(match (c, chars.next_if_eq(&c)) {
+                    (';', _) => Token::Semi,
+                    ('|', Some(_)) => Token::Or,
+                    ('|', None) => Token::Pipe,
+                    ('&', Some(_)) => Token::And,
+                    _ => Token::Amp,
+                });
+            }
+            '>' | '<' => {
+                // A number right in front of the operator is the fd, as in `2>`
+                let fd = match word.parse() {
+                    Ok(fd) if word.chars().all(|c| c.is_ascii_digit()) => {
+                        word.clear();
+                        fd
+                    }
+                    _ => {
+                        if !word.is_empty() {
+                            tokens.push(Token::Word(std::mem::take(&mut word)));
+                        }
+                        if c == '>' {
+                            1
+                        } else {
+                            0
+                        }
+                    }
+                };
+                let op = match (c, chars.peek()) {
+                    (_, Some('&')) => {
+                        chars.next();
+                        RedirectOp::Dup
+                    }
+                    ('<', Some('<')) => {
+                        chars.next();
+                        if chars.next_if_eq(&'<').is_some() {
+                            RedirectOp::HereString
+                        } else {
+                            heredocs.push(PendingHeredoc {
+                                // The delimiter is the next token after this one
+                                index: tokens.len() + 1,
+                                strip_tabs: chars.next_if_eq(&'-').is_some(),
+                            });
+                            RedirectOp::Heredoc
+                        }
+                    }
+                    ('<', _) => RedirectOp::Read,
+                    ('>', Some('>')) => {
+                        chars.next();
+                        RedirectOp::Append
+                    }
+                    _ => RedirectOp::Write,
+                };
+                tokens.push(Token::Redirect(fd, op));
+            }
+            '(' if word.is_empty() && chars.peek() == Some(&'(') => {
+                chars.next();
+                tokens.push(Token::Arith(scan_arith(&mut chars)?));
+            }
+            '#' if word.is_empty() => {
+                // Comment until the end of the line
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '(' if word.strip_suffix('=').is_some_and(is_name) => {
+                // An array assignment like `list=(a b c)`
+                word.push(c);
+                scan_array(&mut chars, &mut word)?;
+            }
+            _ => {
+                word.push(c);
+                scan_quoted(c, &mut chars, &mut word)?;
+            }
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    read_heredocs(&mut chars, &mut tokens, &mut heredocs)?;
+
+    Ok(tokens)
+}
+
+/// Read the bodies of the `heredocs` in the line just tokenized, in order
+///
+/// Each one goes up to the line holding just its delimiter, and replaces
+/// the delimiter in `tokens`.
+fn read_heredocs(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    tokens: &mut [Token],
+    heredocs: &mut Vec<PendingHeredoc>,
+) -> Result<()> {
+    for heredoc in heredocs.drain(..) {
+        let Some(Token::Word(delimiter)) = tokens.get(heredoc.index) else {
+            return Err(ShellError::ParseError(
+                "Expected a delimiter after `<<`".into(),
+            ));
+        };
+        // Quotes in the delimiter only turn off expansion of the body
+        let end: String = delimiter
+            .chars()
+            .filter(|c| !matches!(c, '\'' | '"' | '\\'))
+            .collect();
+
+        let mut body = String::new();
+        loop {
+            if chars.peek().is_none() {
+                return Err(ShellError::ParseError(format!(
+                    "Unterminated here-document, expected `{end}`"
+                )));
+            }
+            let mut line: String = std::iter::from_fn(|| chars.next_if(|&c| c != '\n')).collect();
+            line.extend(chars.next());
+            if heredoc.strip_tabs {
+                line = line.trim_start_matches('\t').to_string();
+            }
+            if line.strip_suffix('\n').unwrap_or(&line) == end {
+                break;
+            }
+            body.push_str(&line);
+        }
+
+        tokens[heredoc.index] = Token::Heredoc {
+            delimiter: delimiter.clone(),
+            body,
+        };
+    }
+    Ok(())
+}
+
+/// Copy the elements of an array assignment up to the closing `)` into
+/// `word`
+fn scan_array(chars: &mut std::iter::Peekable<std::str::Chars>, word: &mut String) -> Result<()> {
+    loop {
+        let Some(c) = chars.next() else {
+            return Err(ShellError::ParseError("Unterminated array".into()));
+        };
+        word.push(c);
+        match c {
+            ')' => return Ok(()),
+            '\\' | '\'' | '"' | '$' => scan_quoted(c, chars, word)?,
+            _ => (),
+        }
+    }
+}
+
+/// Split `input` into words, like the elements inside `(...)` of an array
+/// assignment
+pub(crate) fn split_words(input: &str) -> Result<Vec<String>> {
+    tokenize(input)?
+        .into_iter()
+        // Elements can be spread over several lines
+        .filter(|token| *token != Token::Semi)
+        .map(|token| match token {
+            Token::Word(word) => Ok(word),
+            token => Err(ShellError::ParseError(format!(
+                "Unexpected token {token:?}"
+            ))),
+        })
+        .collect()
+}
+
+/// Read the expression of a `((` command up to the matching `))`
+fn scan_arith(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String> {
+    let mut expr = String::new();
+    let mut depth = 0;
+    loop {
+        match chars.next() {
+            Some(')') if depth == 0 && chars.peek() == Some(&')') => {
+                chars.next();
+                return Ok(expr);
+            }
+            Some(c) => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => (),
+                }
+                expr.push(c);
+            }
+            None => return Err(ShellError::ParseError("Expected `))`".into())),
+        }
+    }
+}
+
+/// Copy everything belonging to the construct started by `c` into `word`
+///
+/// Handles quotes, backslash escapes, `${...}` and `$(...)`, so that the separators
+/// inside them don't end the word.
+fn scan_quoted(
+    c: char,
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    word: &mut String,
+) -> Result<()> {
+    match c {
+        '\\' => {
+            if let Some(next) = chars.next() {
+                word.push(next);
+            }
+        }
+        '\'' => loop {
+            match chars.next() {
+                Some('\'') => {
+                    word.push('\'');
+                    break;
+                }
+                Some(c) => word.push(c),
+                None => return Err(ShellError::ParseError("Unterminated single quote".into())),
+            }
+        },
+        '"' => loop {
+            match chars.next() {
+                Some('"') => {
+                    word.push('"');
+                    break;
+                }
+                Some(c @ ('\\' | '$')) => {
+                    word.push(c);
+                    scan_quoted(c, chars, word)?;
+                }
+                Some(c) => word.push(c),
+                None => return Err(ShellError::ParseError("Unterminated double quote".into())),
+            }
+        },
+        // `$'...'` can have escaped quotes inside
+        '$' if chars.peek() == Some(&'\'') => {
+            word.extend(chars.next());
+            loop {
+                match chars.next() {
+                    Some('\'') => {
+                        word.push('\'');
+                        break;
+                    }
+                    Some('\\') => {
+                        word.push('\\');
+                        word.extend(chars.next());
+                    }
+                    Some(c) => word.push(c),
+                    None => return Err(ShellError::ParseError("Unterminated single quote".into())),
+                }
+            }
+        }
+        '$' if chars.peek() == Some(&'{') => {
+            for c in chars.by_ref() {
+                word.push(c);
+                if c == '}' {
+                    return Ok(());
+                }
+            }
+            return Err(ShellError::ParseError("Unterminated `${`".into()));
+        }
+        '$' if chars.peek() == Some(&'(') => {
+            word.push('(');
+            chars.next();
+            let mut depth = 1;
+            while depth > 0 {
+                let Some(c) = chars.next() else {
+                    return Err(ShellError::ParseError(
+                        "Unterminated command substitution".into(),
+                    ));
+                };
+                word.push(c);
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    '\\' | '\'' | '"' | '$' => scan_quoted(c, chars, word)?,
+                    _ => (),
+                }
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Whether the command in `input` goes on in the next line of input
+///
+/// That is the case inside unterminated quotes, substitutions and compound
+/// commands like `if`, and after a trailing backslash. The backslash is
+/// removed along with the newline, so that the lines are joined without a
+/// space.
+pub(crate) fn needs_more(input: &mut String) -> bool {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        Err(ShellError::ParseError(msg)) => return msg.starts_with("Unterminated"),
+        Err(_) => return false,
+    };
+    let continued = matches!(tokens.last(), Some(Token::Word(word)) if word.ends_with("\\\n"))
+        && input.ends_with("\\\n");
+    if continued {
+        input.truncate(input.len() - 2);
+        return true;
+    }
+    matches!(parse(input, &HashMap::new()), Err(ShellError::ParseError(msg)) if msg.starts_with("Unterminated"))
+}
+
+/// Replace aliases in command position with the tokens they stand for
+///
+/// `seen` holds the aliases currently being expanded, so that an alias like
+/// `ls='ls -l'` doesn't expand forever.
+fn expand_aliases(
+    tokens: Vec<Token>,
+    aliases: &HashMap<String, String>,
+    seen: &mut Vec<String>,
+) -> Result<Vec<Token>> {
+    let mut expanded = vec![];
+    let mut command_start = true;
+
+    for token in tokens {
+        match token {
+            // Reserved words that another command follows
+            Token::Word(word)
+                if command_start
+                    && matches!(
+                        word.as_str(),
+                        "{" | "if" | "then" | "elif" | "else" | "while" | "until" | "do"
+                    ) =>
+            {
+                expanded.push(Token::Word(word));
+            }
+            Token::Word(word) if command_start => {
+                command_start = false;
+                match aliases.get(&word) {
+                    Some(value) if !seen.contains(&word) => {
+                        seen.push(word);
+                        expanded.extend(expand_aliases(tokenize(value)?, aliases, seen)?);
+                        seen.pop();
+                    }
+                    _ => expanded.push(Token::Word(word)),
+                }
+            }
+            Token::Word(_) => expanded.push(token),
+            _ => {
+                command_start = true;
+                expanded.push(token);
+            }
+        }
+    }
+
+    Ok(expanded)
+}
+
+fn parse_command(tokens: &[Token]) -> Result<Command> {
+    let mut words = vec![];
+    let mut redirects = vec![];
+
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => words.push(word.clone()),
+            Token::Redirect(fd, RedirectOp::Heredoc) => {
+                let Some(Token::Heredoc { delimiter, body }) = tokens.next() else {
+                    return Err(ShellError::ParseError(
+                        "Expected a here-document after `<<`".into(),
+                    ));
+                };
+                redirects.push(Redirect::Heredoc {
+                    fd: *fd,
+                    delimiter: delimiter.clone(),
+                    body: body.clone(),
+                });
+            }
+            Token::Redirect(fd, op) => {
+                let Some(Token::Word(target)) = tokens.next() else {
+                    return Err(ShellError::ParseError(
+                        "Expected a file name after redirection".into(),
+                    ));
+                };
+                if let RedirectOp::Both { append } = op {
+                    // The same as `> target 2>&1`
+                    redirects.push(Redirect::Write {
+                        fd: 1,
+                        path: target.clone(),
+                        append: *append,
+                    });
+                    redirects.push(Redirect::Dup {
+                        fd: 2,
+                        to: "1".into(),
+                    });
+                    continue;
+                }
+                redirects.push(parse_redirect(*fd, *op, target));
+            }
+            _ => (),
+        }
+    }
+
+    // Leading `NAME=value` words are variable assignments
+    let mut words = words.into_iter();
+    let mut assignments = vec![];
+    let mut bin = None;
+    for word in words.by_ref() {
+        match word.split_once('=') {
+            Some((name, value)) if is_name(name) => {
+                assignments.push((name.to_string(), value.to_string()));
+            }
+            _ => {
+                bin = Some(word);
+                break;
+            }
+        }
+    }
+
+    if bin.is_none() && assignments.is_empty() && redirects.is_empty() {
+        return Err(ShellError::ParseError("No command given".into()));
+    }
+    Ok(Command {
+        assignments,
+        bin: bin.unwrap_or_default(),
+        args: words.collect(),
+        redirects,
+        program: None,
+    })
+}
+
+fn parse_redirect(fd: u32, op: RedirectOp, target: &str) -> Redirect {
+    let path = target.to_string();
+    match op {
+        RedirectOp::Write => Redirect::Write {
+            fd,
+            path,
+            append: false,
+        },
+        RedirectOp::Append => Redirect::Write {
+            fd,
+            path,
+            append: true,
+        },
+        RedirectOp::Read => Redirect::Read { fd, path },
+        RedirectOp::Dup => Redirect::Dup { fd, to: path },
+        RedirectOp::HereString => Redirect::HereString { fd, text: path },
+        RedirectOp::Heredoc => unreachable!("here-documents have no target"),
+        RedirectOp::Both { .. } => unreachable!("`&>` stands for two redirections"),
+    }
+}
+
+/// Reserved words that end a part of a compound command, and can't start
+/// a command themselves
+const CLOSING: [&str; 6] = ["then", "elif", "else", "fi", "do", "done"];
+
+/// A recursive descent parser over the tokens of a line
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    /// Parse lists of pipelines separated by `;` or `&`
+    ///
+    /// Stops at the end of the input, at one of the reserved words in `end`
+    /// or at one that closes a compound command, which is left for the
+    /// caller to consume.
+    fn list(&mut self, end: &[&str]) -> Result<Vec<CommandChain>> {
+        let mut chains = vec![];
+
+        loop {
+            // Empty lines
+            while self.tokens.next_if_eq(&Token::Semi).is_some() {}
+            match self.tokens.peek() {
+                None => break,
+                Some(Token::Word(word))
+                    if end.contains(&word.as_str()) || CLOSING.contains(&word.as_str()) =>
+                {
+                    break
+                }
+                _ => (),
+            }
+
+            let chain = self.and_or()?;
+            match self.tokens.peek() {
+                Some(Token::Semi) => {
+                    self.tokens.next();
+                    chains.push(chain);
+                }
+                Some(Token::Amp) => {
+                    self.tokens.next();
+                    chains.push(CommandChain::Background(Box::new(chain)));
+                }
+                _ => {
+                    chains.push(chain);
+                    break;
+                }
+            }
+        }
+
+        Ok(chains)
+    }
+
+    /// Parse pipelines connected with `&&` or `||`
+    fn and_or(&mut self) -> Result<CommandChain> {
+        let mut chain = self.pipeline()?;
+        loop {
+            let make = match self.tokens.peek() {
+                Some(Token::And) => CommandChain::And,
+                Some(Token::Or) => CommandChain::Or,
+                _ => return Ok(chain),
+            };
+            self.tokens.next();
+            chain = make(Box::new(chain), Box::new(self.pipeline()?));
+        }
+    }
+
+    /// Parse commands connected with `|`
+    fn pipeline(&mut self) -> Result<CommandChain> {
+        let first = self.command()?;
+        if self.tokens.peek() != Some(&Token::Pipe) {
+            return Ok(first);
+        }
+
+        let CommandChain::Command(first) = first else {
+            return Err(ShellError::ParseError(
+                "Only simple commands can be piped".into(),
+            ));
+        };
+        let mut stages = vec![first];
+        while self.tokens.next_if_eq(&Token::Pipe).is_some() {
+            stages.push(self.simple()?);
+        }
+        Ok(CommandChain::Piped(stages))
+    }
+
+    /// Parse a simple command, a `{ ...; }` group, an `if`, a `for`, `while`
+    /// or `until` loop or a `(( ... ))` command
+    fn command(&mut self) -> Result<CommandChain> {
+        if let Some(Token::Arith(expr)) = self
+            .tokens
+            .next_if(|token| matches!(token, Token::Arith(_)))
+        {
+            return Ok(CommandChain::Arith(expr));
+        }
+        if self
+            .tokens
+            .next_if_eq(&Token::Word("{".to_string()))
+            .is_some()
+        {
+            let chains = self.list(&["}"])?;
+            self.reserved("{", &["}"])?;
+            if chains.is_empty() {
+                return Err(ShellError::ParseError("Expected a command in `{`".into()));
+            }
+            return Ok(CommandChain::Group(chains));
+        }
+        if self
+            .tokens
+            .next_if_eq(&Token::Word("if".to_string()))
+            .is_some()
+        {
+            return self.if_clause();
+        }
+        if self
+            .tokens
+            .next_if_eq(&Token::Word("for".to_string()))
+            .is_some()
+        {
+            return self.for_clause();
+        }
+        if let Some(Token::Word(keyword)) = self.tokens.next_if(
+            |token| matches!(token, Token::Word(word) if word == "while" || word == "until"),
+        ) {
+            return self.loop_clause(&keyword);
+        }
+
+        Ok(CommandChain::Command(self.simple()?))
+    }
+
+    /// Parse the rest of an `if` command, up to and including its `fi`
+    fn if_clause(&mut self) -> Result<CommandChain> {
+        let mut branches = vec![];
+        loop {
+            let condition = self.list(&["then"])?;
+            self.reserved("if", &["then"])?;
+            let body = self.list(&["elif", "else", "fi"])?;
+            let next = self.reserved("if", &["elif", "else", "fi"])?;
+            if condition.is_empty() || body.is_empty() {
+                return Err(ShellError::ParseError("Expected a command in `if`".into()));
+            }
+            branches.push((condition, body));
+
+            match next.as_str() {
+                "elif" => continue,
+                "else" => {
+                    let otherwise = self.list(&["fi"])?;
+                    self.reserved("if", &["fi"])?;
+                    if otherwise.is_empty() {
+                        return Err(ShellError::ParseError(
+                            "Expected a command after `else`".into(),
+                        ));
+                    }
+                    return Ok(CommandChain::If {
+                        branches,
+                        otherwise,
+                    });
+                }
+                _ => {
+                    return Ok(CommandChain::If {
+                        branches,
+                        otherwise: vec![],
+                    })
+                }
+            }
+        }
+    }
+
+    /// Parse the rest of a `for` loop, up to and including its `done`
+    fn for_clause(&mut self) -> Result<CommandChain> {
+        let name = match self.tokens.next() {
+            Some(Token::Word(name)) if is_name(&name) => name,
+            None => {
+                return Err(ShellError::ParseError(
+                    "Unterminated `for`, expected a variable name".into(),
+                ))
+            }
+            Some(token) => {
+                return Err(ShellError::ParseError(format!(
+                    "Expected a variable name after `for`, found {token:?}"
+                )))
+            }
+        };
+        let words = match self.tokens.next_if_eq(&Token::Word("in".to_string())) {
+            Some(_) => {
+                let mut words = vec![];
+                while let Some(Token::Word(word)) =
+                    self.tokens.next_if(|token| matches!(token, Token::Word(_)))
+                {
+                    words.push(word);
+                }
+                Some(words)
+            }
+            None => None,
+        };
+        // The words end at a `;` or the end of the line
+        self.tokens.next_if_eq(&Token::Semi);
+
+        self.reserved("for", &["do"])?;
+        let body = self.list(&["done"])?;
+        self.reserved("for", &["done"])?;
+        if body.is_empty() {
+            return Err(ShellError::ParseError("Expected a command in `for`".into()));
+        }
+        Ok(CommandChain::For { name, words, body })
+    }
+
+    /// Parse the rest of a `while` or `until` loop, up to and including its
+    /// `done`
+    fn loop_clause(&mut self, keyword: &str) -> Result<CommandChain> {
+        let condition = self.list(&["do"])?;
+        self.reserved(keyword, &["do"])?;
+        let body = self.list(&["done"])?;
+        self.reserved(keyword, &["done"])?;
+        if condition.is_empty() || body.is_empty() {
+            return Err(ShellError::ParseError(format!(
+                "Expected a command in `{keyword}`"
+            )));
+        }
+        Ok(CommandChain::While {
+            condition,
+            body,
+            until: keyword == "until",
+        })
+    }
+
+    /// Take the reserved word that goes on the `compound` command, which
+    /// must be one of `expected`
+    ///
+    /// Running out of tokens means that the command goes on in the next
+    /// line.
+    fn reserved(&mut self, compound: &str, expected: &[&str]) -> Result<String> {
+        let expected_words = expected.join("` or `");
+        match self.tokens.next() {
+            Some(Token::Word(word)) if expected.contains(&word.as_str()) => Ok(word),
+            None => Err(ShellError::ParseError(format!(
+                "Unterminated `{compound}`, expected `{expected_words}`"
+            ))),
+            Some(token) => Err(ShellError::ParseError(format!(
+                "Expected `{expected_words}`, found {token:?}"
+            ))),
+        }
+    }
+
+    fn simple(&mut self) -> Result<Command> {
+        let mut tokens = vec![];
+        while let Some(token) = self
+            .tokens
+            .next_if(|token| matches!(token, Token::Word(_) | Token::Redirect(..)))
+        {
+            tokens.push(token);
+            if matches!(tokens.last(), Some(Token::Redirect(..))) {
+                // The target of the redirection
+                tokens.extend(self.tokens.next());
+            }
+        }
+        parse_command(&tokens)
+    }
+}
+
+/// Parse a line of input into a vector of commands
+///
+/// Commands named in `aliases` are replaced by their definition.
+pub(crate) fn parse(line: &str, aliases: &HashMap<String, String>) -> Result<Vec<CommandChain>> {
+    let tokens = expand_aliases(tokenize(line)?, aliases, &mut vec![])?;
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+    };
+
+    let chains = parser.list(&[])?;
+    match parser.tokens.next() {
+        None => Ok(chains),
+        Some(token) => Err(ShellError::ParseError(format!(
+            "Unexpected token {token:?}"
+        ))),
+    }
+}
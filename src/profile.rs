@@ -0,0 +1,88 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A part of running commands that time is spent in
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Phase {
+    Parse,
+    Expand,
+    Builtin,
+    /// Starting child processes and waiting for them
+    Process,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [Phase::Parse, Phase::Expand, Phase::Builtin, Phase::Process];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Expand => "expand",
+            Phase::Builtin => "builtin",
+            Phase::Process => "process",
+        }
+    }
+}
+
+/// Time spent in each [`Phase`], when profiling with `RUSTLAB_PROFILE=1`
+///
+/// Phases can nest: a command substitution counts toward expansion as well
+/// as toward the phases of the commands inside it. Subshells share their
+/// profile with the shell they came from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Profile {
+    enabled: bool,
+    /// Total nanoseconds and number of times, by phase
+    phases: Arc<[(AtomicU64, AtomicU64); 4]>,
+}
+
+impl Profile {
+    pub(crate) fn from_env() -> Self {
+        Self {
+            enabled: std::env::var_os("RUSTLAB_PROFILE").is_some_and(|value| value == "1"),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start timing, if profiling
+    pub(crate) fn start(&self) -> Option<Instant> {
+        self.enabled.then(Instant::now)
+    }
+
+    /// Add the time since `start` to `phase`
+    pub(crate) fn record(&self, phase: Phase, start: Option<Instant>) {
+        if let Some(start) = start {
+            let (total, count) = &self.phases[phase as usize];
+            let elapsed = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+            total.fetch_add(elapsed, Ordering::Relaxed);
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "rush profile:")?;
+        for phase in Phase::ALL {
+            let (total, count) = &self.phases[phase as usize];
+            writeln!(
+                f,
+                "  {:<8} {:>8} calls {:>12.3?}",
+                phase.label(),
+                count.load(Ordering::Relaxed),
+                Duration::from_nanos(total.load(Ordering::Relaxed))
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use crate::Result;
+
+/// A redirection of one of a command's file descriptors
+///
+/// # Examples
+///
+/// ```sh
+/// ls > files.txt
+/// make 2>> errors.log
+/// sort < names.txt
+/// cargo build > build.log 2>&1
+/// cargo test &>> test.log
+/// cat <<EOF | grep x
+/// grep foo <<< "$text"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redirect {
+    /// `n> path`, or `n>> path` to append
+    Write { fd: u32, path: String, append: bool },
+    /// `n< path`
+    Read { fd: u32, path: String },
+    /// `n>&m` or `n<&m`: point `fd` wherever `to` points at this moment
+    ///
+    /// `to` is a word that expands to a file descriptor number.
+    Dup { fd: u32, to: String },
+    /// `n<< delimiter`, with the lines up to `delimiter` as input
+    ///
+    /// The body is expanded like a word in double quotes, unless the
+    /// delimiter is quoted.
+    Heredoc {
+        fd: u32,
+        delimiter: String,
+        body: String,
+    },
+    /// `n<<< word`, with the word and a newline as input
+    HereString { fd: u32, text: String },
+}
+
+impl Display for Redirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Redirect::Write { fd, path, append } => {
+                let fd = if *fd == 1 {
+                    String::new()
+                } else {
+                    fd.to_string()
+                };
+                let op = if *append { ">>" } else { ">" };
+                write!(f, "{fd}{op} {path}")
+            }
+            Redirect::Read { fd, path } => {
+                let fd = if *fd == 0 {
+                    String::new()
+                } else {
+                    fd.to_string()
+                };
+                write!(f, "{fd}< {path}")
+            }
+            Redirect::Dup { fd, to } => write!(f, "{fd}>&{to}"),
+            Redirect::Heredoc { fd, delimiter, .. } => {
+                let fd = if *fd == 0 {
+                    String::new()
+                } else {
+                    fd.to_string()
+                };
+                write!(f, "{fd}<< {delimiter}")
+            }
+            Redirect::HereString { fd, text } => {
+                let fd = if *fd == 0 {
+                    String::new()
+                } else {
+                    fd.to_string()
+                };
+                write!(f, "{fd}<<< {text}")
+            }
+        }
+    }
+}
+
+impl Redirect {
+    /// The descriptor this redirects
+    pub(crate) fn fd(&self) -> u32 {
+        match self {
+            Redirect::Write { fd, .. }
+            | Redirect::Read { fd, .. }
+            | Redirect::Dup { fd, .. }
+            | Redirect::Heredoc { fd, .. }
+            | Redirect::HereString { fd, .. } => *fd,
+        }
+    }
+
+    /// Open the file this redirects to, relative to `cwd`, or `None` for a
+    /// [`Redirect::Dup`]
+    ///
+    /// With a `umask`, files that get created have their permissions set by
+    /// that instead of the shell's.
+    pub(crate) fn open_file(&self, cwd: &Path, umask: Option<u32>) -> Result<Option<File>> {
+        Ok(Some(match self {
+            Redirect::Write { path, append, .. } => {
+                let path = cwd.join(path);
+                let umask = umask.filter(|_| !path.exists());
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(*append)
+                    .truncate(!*append)
+                    .open(&path)?;
+                // The shell's own umask was applied on creation, so start over
+                #[cfg(unix)]
+                if let Some(umask) = umask {
+                    use std::os::unix::fs::PermissionsExt;
+                    file.set_permissions(std::fs::Permissions::from_mode(0o666 & !umask))?;
+                }
+                #[cfg(not(unix))]
+                let _ = umask;
+                file
+            }
+            Redirect::Read { path, .. } => File::open(cwd.join(path))?,
+            Redirect::Dup { .. } => return Ok(None),
+            Redirect::Heredoc { body, .. } => pipe_from(body.clone().into_bytes())?,
+            Redirect::HereString { text, .. } => pipe_from(format!("{text}\n").into_bytes())?,
+        }))
+    }
+}
+
+/// A copy of the shell's open file descriptor `fd`
+#[cfg(unix)]
+pub(crate) fn dup_fd(fd: u32) -> Result<File> {
+    use std::os::fd::FromRawFd;
+
+    let bad_fd = || std::io::Error::other(format!("{fd}: bad file descriptor"));
+    let fd = i32::try_from(fd).map_err(|_| bad_fd())?;
+    // SAFETY: `fcntl` fails for descriptors that aren't open, and a
+    // successful call returns a new descriptor that nobody else owns
+    unsafe {
+        let copy = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3);
+        if copy < 0 {
+            return Err(bad_fd().into());
+        }
+        Ok(File::from_raw_fd(copy))
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn dup_fd(fd: u32) -> Result<File> {
+    Err(std::io::Error::other(format!("{fd}: bad file descriptor")).into())
+}
+
+/// A file to read `data` from, fed to it through a pipe
+fn pipe_from(data: Vec<u8>) -> Result<File> {
+    let (reader, mut writer) = std::io::pipe()?;
+    // Write from another thread, so that data that doesn't fit into the
+    // pipe can't block us. Stops early if the reader goes away.
+    std::thread::spawn(move || {
+        let _ = writer.write_all(&data);
+    });
+
+    #[cfg(unix)]
+    let reader = std::os::fd::OwnedFd::from(reader);
+    #[cfg(windows)]
+    let reader = std::os::windows::io::OwnedHandle::from(reader);
+    Ok(File::from(reader))
+}
+
+/// Where one of the standard streams of a command ends up
+#[derive(Debug)]
+pub(crate) enum Target {
+    /// Connected to the shell: stdin gets the command's input and stdout
+    /// is captured as its output
+    Piped,
+    /// The shell's stderr
+    Stderr,
+    File(File),
+}
+
+impl Target {
+    fn try_clone(&self) -> Result<Self> {
+        Ok(match self {
+            Target::Piped => Target::Piped,
+            Target::Stderr => Target::Stderr,
+            Target::File(file) => Target::File(file.try_clone()?),
+        })
+    }
+}
+
+/// The standard streams of a command after applying its redirections
+#[derive(Debug)]
+pub(crate) struct Streams {
+    pub(crate) stdin: Target,
+    pub(crate) stdout: Target,
+    pub(crate) stderr: Target,
+}
+
+impl Streams {
+    /// Apply `redirects` from left to right, opening files relative to `cwd`
+    ///
+    /// Just like in other shells the order matters: in `cmd > out 2>&1`
+    /// both streams end up in `out`, but in `cmd 2>&1 > out` only stdout
+    /// does. Every file is opened even if a later redirection replaces it,
+    /// so `cmd > a > b` creates (or truncates) `a` but writes to `b`. New
+    /// files get their permissions from `umask`, if given.
+    pub(crate) fn open(redirects: &[Redirect], cwd: &Path, umask: Option<u32>) -> Result<Self> {
+        let mut streams = Streams {
+            stdin: Target::Piped,
+            stdout: Target::Piped,
+            stderr: Target::Stderr,
+        };
+
+        for redirect in redirects {
+            match redirect {
+                Redirect::Dup { fd, to } => {
+                    let to: u32 = to
+                        .parse()
+                        .map_err(|_| std::io::Error::other(format!("{to}: bad file descriptor")))?;
+                    let target = match to {
+                        0..=2 => streams.get_mut(to)?.try_clone()?,
+                        // One of the shell's own descriptors, like a `coproc` pipe
+                        _ => Target::File(dup_fd(to)?),
+                    };
+                    *streams.get_mut(*fd)? = target;
+                }
+                _ => {
+                    if let Some(file) = redirect.open_file(cwd, umask)? {
+                        *streams.get_mut(redirect.fd())? = Target::File(file);
+                    }
+                }
+            }
+        }
+
+        Ok(streams)
+    }
+
+    fn get_mut(&mut self, fd: u32) -> Result<&mut Target> {
+        match fd {
+            0 => Ok(&mut self.stdin),
+            1 => Ok(&mut self.stdout),
+            2 => Ok(&mut self.stderr),
+            _ => Err(std::io::Error::other(format!("{fd}: bad file descriptor")).into()),
+        }
+    }
+}
@@ -0,0 +1,1662 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fs::File,
+    io::{PipeReader, Read, Write},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    arith,
+    builtins::{self, Builtin},
+    command::{exit_code, is_executable, Environment, Ran},
+    expand::{expand_braces, expand_fields, expand_heredoc, expand_word},
+    history::{self, History},
+    interrupt,
+    jobs::{JobState, Jobs, Process},
+    parser, print_error,
+    profile::{Phase, Profile},
+    redirect::{self, Redirect, Streams, Target},
+    Command, CommandChain, CommandResult, Decision, Output, Result, ShellError,
+};
+
+/// Read the script file at `path`
+///
+/// Fails for files that don't look like text: those with NUL bytes near
+/// the start, like most executables, or that aren't valid UTF-8.
+pub(crate) fn read_script(path: &Path) -> Result<String> {
+    let contents = std::fs::read(path)?;
+    if contents.iter().take(1024).any(|&byte| byte == 0) {
+        return Err(ShellError::BinaryFile);
+    }
+    String::from_utf8(contents).map_err(|_| ShellError::BinaryFile)
+}
+
+/// `line` with a Windows style `\r\n` ending turned into `\n`
+///
+/// A `\r` at the very end of the input goes too.
+pub(crate) fn strip_cr(line: &str) -> String {
+    let (text, newline) = match line.strip_suffix('\n') {
+        Some(text) => (text, "\n"),
+        None => (line, ""),
+    };
+    format!("{}{newline}", text.strip_suffix('\r').unwrap_or(text))
+}
+
+/// The part of a script file holding its commands
+///
+/// Skips a UTF-8 byte order mark and a `#!` interpreter line at the start.
+pub(crate) fn script_body(contents: &str) -> &str {
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    if contents.starts_with("#!") {
+        contents.split_once('\n').map_or("", |(_, rest)| rest)
+    } else {
+        contents
+    }
+}
+
+/// The elements between the parentheses of an array assignment like
+/// `list=(a b c)`
+fn array_literal(value: &str) -> Option<&str> {
+    value.strip_prefix('(')?.strip_suffix(')')
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: `geteuid` always succeeds
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+/// The absolute path of `path`, with symlinks resolved
+///
+/// On Windows, this avoids `\\?\C:\dir` style paths where `C:\dir` does,
+/// as many programs can't handle those.
+pub(crate) fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    let path = path.canonicalize()?;
+    #[cfg(windows)]
+    if let Some(rest) = path.to_str().and_then(|path| path.strip_prefix(r"\\?\")) {
+        // Network paths look like `\\?\UNC\server\share` and are kept as is
+        if !rest.starts_with(r"UNC\") {
+            return Ok(PathBuf::from(rest));
+        }
+    }
+    Ok(path)
+}
+
+/// The reading end of a pipe as a file, for builtins to read from
+#[cfg(unix)]
+fn pipe_file(pipe: PipeReader) -> File {
+    File::from(std::os::fd::OwnedFd::from(pipe))
+}
+
+#[cfg(windows)]
+fn pipe_file(pipe: PipeReader) -> File {
+    File::from(std::os::windows::io::OwnedHandle::from(pipe))
+}
+
+/// The file names the command `name` can have in a `PATH` directory
+///
+/// On Windows, that's `name` with each extension in `PATHEXT` added, unless
+/// it already has one.
+#[cfg(windows)]
+fn executable_names(name: &str, pathext: Option<String>) -> Vec<String> {
+    if Path::new(name).extension().is_some() {
+        return vec![name.to_string()];
+    }
+    let pathext = pathext.unwrap_or_else(|| ".COM;.EXE;.BAT;.CMD".into());
+    pathext
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| format!("{name}{}", ext.to_lowercase()))
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn executable_names(name: &str, _pathext: Option<String>) -> Vec<String> {
+    vec![name.to_string()]
+}
+
+/// Where `break` and `continue` leave the loops they are in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Flow {
+    /// Leave this many loops
+    Break(usize),
+    /// Go on with the next round of the loop this many levels up
+    Continue(usize),
+}
+
+/// Passes writes on to `inner`, counting the bytes
+struct Counted<'a> {
+    inner: &'a mut dyn Write,
+    bytes: usize,
+}
+
+impl Write for Counted<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Options changed with `set -o`/`set +o`
+#[derive(Clone)]
+pub(crate) struct Options {
+    /// Make sure a command found in the hash table is still there before
+    /// running it
+    checkhash: bool,
+    /// Keep a command that spans several lines as a single history entry
+    cmdhist: bool,
+    /// Expand aliases even when not interactive
+    expand_aliases: bool,
+    /// Stop as soon as a command fails (`set -e`)
+    errexit: bool,
+    /// Print commands instead of running them (`set -n`), unless interactive
+    noexec: bool,
+    /// Give pipelines the status of the last stage that failed, instead of
+    /// the status of the last stage
+    pipefail: bool,
+    /// Print commands to stderr before running them (`set -x`)
+    xtrace: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            checkhash: false,
+            cmdhist: true,
+            expand_aliases: false,
+            errexit: false,
+            noexec: false,
+            pipefail: false,
+            xtrace: false,
+        }
+    }
+}
+
+impl Options {
+    /// Look up an option by the name used with `set -o`
+    pub(crate) fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "checkhash" => Some(&mut self.checkhash),
+            "cmdhist" => Some(&mut self.cmdhist),
+            "errexit" => Some(&mut self.errexit),
+            "expand_aliases" => Some(&mut self.expand_aliases),
+            "noexec" => Some(&mut self.noexec),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// The name of the option set with a single letter flag, like `-e`
+    pub(crate) fn long_name(flag: char) -> Option<&'static str> {
+        match flag {
+            'e' => Some("errexit"),
+            'n' => Some("noexec"),
+            'x' => Some("xtrace"),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn list(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("checkhash", self.checkhash),
+            ("cmdhist", self.cmdhist),
+            ("errexit", self.errexit),
+            ("expand_aliases", self.expand_aliases),
+            ("noexec", self.noexec),
+            ("pipefail", self.pipefail),
+            ("xtrace", self.xtrace),
+        ]
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CommandRunner {
+    pub(crate) pwd: PathBuf,
+    /// The file creation mask set with `umask`, if it was, for the files
+    /// and processes the shell creates instead of its process's own
+    pub(crate) umask: Option<u32>,
+    /// Where `cd -` goes back to
+    pub(crate) prev_dir: Option<PathBuf>,
+    /// The directories `pushd` left, the most recent one last
+    pub(crate) dir_stack: Vec<PathBuf>,
+    pub(crate) history: History,
+    pub(crate) aliases: HashMap<String, String>,
+    /// Where `alias -s` saves the aliases, if anywhere
+    pub(crate) aliases_file: Option<PathBuf>,
+    /// The words that the arguments of commands complete to, by command,
+    /// as set with `complete -W`
+    pub(crate) completions: HashMap<String, Vec<String>>,
+    /// Shell variables
+    pub(crate) vars: HashMap<String, String>,
+    /// Array variables, like `list` after `list=(a b c)`
+    pub(crate) arrays: HashMap<String, Vec<String>>,
+    /// Names of the variables passed on to child processes
+    pub(crate) exported: HashSet<String>,
+    /// Variables inherited from the environment that were unset, which
+    /// lookups no longer fall back to
+    pub(crate) unset: HashSet<String>,
+    pub(crate) options: Options,
+    pub(crate) jobs: Jobs,
+    /// The shell's ends of the pipes to each `coproc`, by name, until they
+    /// are closed with `exec n>&-`
+    #[cfg(unix)]
+    pub(crate) coprocs: HashMap<String, [Option<Arc<std::os::fd::OwnedFd>>; 2]>,
+    /// Descriptors opened by `exec`, like 3 after `exec 3< file`
+    #[cfg(unix)]
+    fds: HashMap<u32, Arc<std::os::fd::OwnedFd>>,
+    pub(crate) builtins: Arc<HashMap<String, Box<dyn Builtin>>>,
+    /// `$0`, the name of the shell or of the script it runs
+    pub(crate) name: String,
+    /// The positional parameters `$1`, `$2`, ...
+    pub(crate) args: Vec<String>,
+    /// Where commands were found in `PATH`, by name, as listed by `hash`
+    pub(crate) path_cache: HashMap<String, PathBuf>,
+    /// Builtins turned off with `enable -n`
+    pub(crate) disabled: HashSet<String>,
+    /// Where the builtin being run has its stdin redirected to, if anywhere
+    pub(crate) stdin: Option<Arc<File>>,
+    /// Whether we're running a command whose status is being tested, like
+    /// the left side of `&&`, where `set -e` doesn't apply
+    tested: bool,
+    /// The script and line number being run, for error messages
+    location: Option<(String, usize)>,
+    /// Whether we're reading commands from a user rather than a script
+    pub(crate) interactive: bool,
+    /// The directory that `cd` and redirections have to stay inside, if
+    /// any, canonicalized
+    pub(crate) sandbox: Option<PathBuf>,
+    pub(crate) profile: Profile,
+    /// Exit status of the last command (`$?`)
+    pub(crate) last_status: i32,
+    /// Exit status of the last command substitution in the command being
+    /// expanded, which a command with only assignments returns
+    substituted: Option<i32>,
+    /// Set by `exit`; no further commands run once this is set
+    pub(crate) exit: Option<i32>,
+    /// Set by `break` and `continue`, to stop the commands up to the loop
+    /// they are meant for
+    pub(crate) flow: Option<Flow>,
+    /// How many loops the commands being run are in
+    pub(crate) loops: usize,
+    /// Report each command as a line of JSON on stderr
+    pub(crate) json: bool,
+    /// Whether `exec` replaces the process with its command, rather than
+    /// running it and exiting
+    pub(crate) replace_on_exec: bool,
+    /// Decides about each external command before it runs
+    pub(crate) pre_exec: Option<PreExecHook>,
+    /// Gets each external command that ran, with its status
+    pub(crate) post_exec: Option<PostExecHook>,
+    /// The only external commands that may run, if limited
+    pub(crate) allowed: Option<HashSet<String>>,
+    /// External commands that may not run, by file name
+    pub(crate) denied: HashSet<String>,
+}
+
+type PreExecHook = Arc<Mutex<dyn FnMut(&Command) -> Decision + Send>>;
+type PostExecHook = Arc<Mutex<dyn FnMut(&Command, i32) + Send>>;
+
+impl CommandRunner {
+    pub(crate) fn new() -> Self {
+        Self {
+            pwd: env::current_dir().expect("Cannot get current_dir"),
+            umask: None,
+            prev_dir: None,
+            dir_stack: vec![],
+            history: History::default(),
+            aliases: HashMap::new(),
+            aliases_file: None,
+            completions: HashMap::new(),
+            vars: HashMap::new(),
+            arrays: HashMap::new(),
+            exported: HashSet::new(),
+            unset: HashSet::new(),
+            options: Options::default(),
+            jobs: Jobs::default(),
+            #[cfg(unix)]
+            coprocs: HashMap::new(),
+            #[cfg(unix)]
+            fds: HashMap::new(),
+            builtins: Arc::new(builtins::registry()),
+            name: "rush".into(),
+            args: vec![],
+            path_cache: HashMap::new(),
+            disabled: HashSet::new(),
+            stdin: None,
+            tested: false,
+            location: None,
+            interactive: false,
+            sandbox: None,
+            profile: Profile::from_env(),
+            last_status: 0,
+            substituted: None,
+            exit: None,
+            flow: None,
+            loops: 0,
+            json: false,
+            replace_on_exec: false,
+            pre_exec: None,
+            post_exec: None,
+            allowed: None,
+            denied: HashSet::new(),
+        }
+    }
+
+    /// A copy of this runner for running commands in a subshell
+    ///
+    /// Changes made by the subshell, like `cd` or `exit`, don't affect us.
+    fn subshell(&self) -> Self {
+        Self {
+            history: self.history.in_memory(),
+            exit: None,
+            flow: None,
+            loops: 0,
+            json: false,
+            replace_on_exec: false,
+            ..self.clone()
+        }
+    }
+
+    /// Parse a line of input, expanding aliases if they are enabled
+    ///
+    /// Like in bash, aliases are only expanded in interactive mode unless
+    /// `set -o expand_aliases` is given.
+    pub(crate) fn parse(&self, line: &str) -> Result<Vec<CommandChain>> {
+        let start = self.profile.start();
+        let chains = if self.interactive || self.options.expand_aliases {
+            parser::parse(line, &self.aliases)
+        } else {
+            parser::parse(line, &HashMap::new())
+        };
+        self.profile.record(Phase::Parse, start);
+        chains
+    }
+
+    /// Run `script` in a subshell and return what it wrote to stdout
+    ///
+    /// An `exit` inside the script only ends the subshell.
+    pub(crate) fn substitute(&mut self, script: &str) -> Result<String> {
+        let mut subshell = self.subshell();
+        let mut stdout = vec![];
+        subshell.run(self.parse(script)?, &mut stdout)?;
+
+        self.last_status = subshell.last_status;
+        self.substituted = Some(subshell.last_status);
+        Ok(String::from_utf8(stdout)?)
+    }
+
+    /// Look up the value of a variable
+    ///
+    /// Falls back to the environment the shell was started with.
+    pub(crate) fn var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(std::process::id().to_string()),
+            "0" => Some(self.name.clone()),
+            "#" => Some(self.args.len().to_string()),
+            "@" | "*" => Some(self.args.join(" ")),
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => {
+                let index: usize = name.parse().ok()?;
+                self.args.get(index.checked_sub(1)?).cloned()
+            }
+            _ => self
+                .vars
+                .get(name)
+                .cloned()
+                // An array on its own stands for its first element
+                .or_else(|| self.arrays.get(name)?.first().cloned())
+                .or_else(|| env::var(name).ok().filter(|_| !self.unset.contains(name))),
+        }
+    }
+
+    /// Define the aliases saved in the file at `path`, and save them there
+    /// from now on
+    ///
+    /// Each line of the file is a `name=value` pair, as `alias` takes it. A
+    /// missing file is fine: it gets created when the aliases are saved.
+    pub(crate) fn load_aliases(&mut self, path: PathBuf) -> Result<()> {
+        let saved = match std::fs::read_to_string(&path) {
+            Ok(saved) => saved,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for (name, value) in saved.lines().filter_map(|line| line.split_once('=')) {
+            self.aliases.insert(name.to_string(), value.to_string());
+        }
+        self.aliases_file = Some(path);
+        Ok(())
+    }
+
+    /// Write all aliases to the aliases file, replacing what it held
+    ///
+    /// Does nothing if there is no aliases file.
+    pub(crate) fn save_aliases(&self) -> Result<()> {
+        let Some(path) = &self.aliases_file else {
+            return Ok(());
+        };
+        let mut aliases: Vec<_> = self.aliases.iter().collect();
+        aliases.sort();
+        let mut file = std::io::BufWriter::new(File::create(path)?);
+        for (name, value) in aliases {
+            writeln!(file, "{name}={value}")?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Add a command read from the user to the history
+    ///
+    /// A command spanning several lines becomes a single entry, unless the
+    /// `cmdhist` option is turned off. Entries `HISTCONTROL` says to ignore
+    /// are left out.
+    pub(crate) fn remember(&mut self, command: &str) -> Result<()> {
+        let size = self.history_size();
+        if size == 0 {
+            return Ok(());
+        }
+        let command = command.trim_end_matches('\n');
+        let entries: Vec<&str> = if self.options.cmdhist {
+            vec![command]
+        } else {
+            command
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect()
+        };
+        let control = self.var("HISTCONTROL").unwrap_or_default();
+        let ignores = |name| {
+            control
+                .split(':')
+                .any(|value| value == name || value == "ignoreboth")
+        };
+        let (ignore_space, ignore_dups) = (ignores("ignorespace"), ignores("ignoredups"));
+        for entry in entries {
+            let duplicate = self
+                .history
+                .last()
+                .is_some_and(|last| last.command == entry);
+            if !(ignore_space && entry.starts_with(' ') || ignore_dups && duplicate) {
+                self.history.push(entry.to_string())?;
+            }
+        }
+        self.history.trim(size)
+    }
+
+    /// How many commands the history keeps, from `HISTSIZE`
+    ///
+    /// Like in bash, a negative size means no limit, and 0 turns the
+    /// history off.
+    pub(crate) fn history_size(&self) -> usize {
+        match self
+            .var("HISTSIZE")
+            .and_then(|size| size.parse::<i64>().ok())
+        {
+            Some(size) => usize::try_from(size).unwrap_or(usize::MAX),
+            None => history::DEFAULT_SIZE,
+        }
+    }
+
+    /// `path` for display, starting with `~` if it is in the home directory
+    pub(crate) fn tilde_path(&self, path: &Path) -> String {
+        let home = self.var("HOME").map(PathBuf::from);
+        match home.as_ref().and_then(|home| path.strip_prefix(home).ok()) {
+            Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+            Some(rest) => format!("~{}{}", std::path::MAIN_SEPARATOR, rest.display()),
+            None => path.display().to_string(),
+        }
+    }
+
+    /// Render a prompt like `PS1`, replacing its backslash escapes
+    ///
+    /// Supports `\w` and `\W` for the working directory and its last
+    /// component, `\u` for the user, `\$` for `#` as root and `$` otherwise,
+    /// `\?` for the status of the last command in brackets if it failed, and
+    /// `\\` for a backslash.
+    pub(crate) fn prompt(&self, template: &str) -> String {
+        let pwd = self.tilde_path(&self.pwd);
+
+        let mut prompt = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                prompt.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('w') => prompt.push_str(&pwd),
+                Some('W') => {
+                    prompt.push_str(
+                        pwd.rsplit(std::path::is_separator)
+                            .next()
+                            .unwrap_or_default(),
+                    );
+                }
+                Some('u') => prompt.extend(self.var("USER")),
+                Some('$') => prompt.push(if is_root() { '#' } else { '$' }),
+                Some('?') if self.last_status != 0 => {
+                    prompt.push_str(&format!("[{}] ", self.last_status));
+                }
+                Some('?') => (),
+                Some('\\') => prompt.push('\\'),
+                Some(c) => {
+                    prompt.push('\\');
+                    prompt.push(c);
+                }
+                None => prompt.push('\\'),
+            }
+        }
+        prompt
+    }
+
+    /// The elements of an array variable
+    ///
+    /// A plain variable counts as an array with a single element.
+    pub(crate) fn array(&self, name: &str) -> Vec<String> {
+        match self.arrays.get(name) {
+            Some(elements) => elements.clone(),
+            None => self.var(name).into_iter().collect(),
+        }
+    }
+
+    /// Set a shell variable
+    ///
+    /// Changing `PATH` forgets where commands were found.
+    pub(crate) fn set_var(&mut self, name: &str, value: String) {
+        if name == "PATH" {
+            self.path_cache.clear();
+        }
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// Remove a variable or array, and stop passing it to child processes
+    ///
+    /// One that the shell inherited stays in its process's environment,
+    /// but is hidden from lookups and children from then on. Subshells
+    /// have their own copy of that, so it doesn't leak out of them.
+    pub(crate) fn unset_var(&mut self, name: &str) {
+        if name == "PATH" {
+            self.path_cache.clear();
+        }
+        self.vars.remove(name);
+        self.arrays.remove(name);
+        self.exported.remove(name);
+        self.unset.insert(name.to_string());
+    }
+
+    /// What child processes get on top of the shell's own environment
+    pub(crate) fn env(&self) -> Environment {
+        let vars = self
+            .exported
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.vars.get(name)?.clone())))
+            .collect();
+        Environment {
+            vars,
+            unset: self.unset.clone(),
+        }
+    }
+
+    /// Expand all words of a command, and look it up in `PATH` if it is an
+    /// external one
+    ///
+    /// Fails if the files it redirects to are outside the sandbox.
+    fn expand(&mut self, command: &Command) -> Result<Command> {
+        let start = self.profile.start();
+        let expanded = self.expand_words(command);
+        self.profile.record(Phase::Expand, start);
+        let mut expanded = expanded?;
+        // `PATH=dir cmd` looks `cmd` up in `dir`, which is left to the OS
+        let own_path = expanded.assignments.iter().any(|(name, _)| name == "PATH");
+        if !expanded.bin.is_empty() && !own_path && !self.is_builtin(&expanded.bin) {
+            expanded.program = self.resolve_in_path(&expanded.bin);
+        }
+        for redirect in &expanded.redirects {
+            if let Redirect::Write { path, .. } | Redirect::Read { path, .. } = redirect {
+                self.confine(&self.pwd.join(path))?;
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Let the pre-exec hook allow, deny or rewrite `command`, an external
+    /// command about to run, then check the result against the allowed and
+    /// denied commands
+    pub(crate) fn vet(&mut self, command: Command) -> Result<Command> {
+        let command = match &self.pre_exec {
+            Some(hook) => {
+                let decision = hook.lock().expect("pre-exec hook panicked")(&command);
+                match decision {
+                    Decision::Allow => command,
+                    Decision::Deny => return Err(ShellError::Denied(command.bin)),
+                    // Don't run the old program under a new name
+                    Decision::Rewrite(mut rewritten) => {
+                        if rewritten.bin != command.bin {
+                            rewritten.program = self.resolve_in_path(&rewritten.bin);
+                        }
+                        rewritten
+                    }
+                }
+            }
+            None => command,
+        };
+
+        let name = Path::new(&command.bin)
+            .file_name()
+            .map_or(command.bin.as_str(), |name| {
+                name.to_str().unwrap_or_default()
+            });
+        let allowed = self
+            .allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&command.bin));
+        if !allowed || self.denied.contains(name) {
+            return Err(ShellError::Denied(command.bin));
+        }
+        Ok(command)
+    }
+
+    /// Tell the post-exec hook that `command` exited with `status`
+    pub(crate) fn ran(&self, command: &Command, status: i32) {
+        if let Some(hook) = &self.post_exec {
+            hook.lock().expect("post-exec hook panicked")(command, status);
+        }
+    }
+
+    /// Where the command `name` is in `PATH`, remembering it for next time
+    ///
+    /// With `checkhash`, a remembered path is only used if it's still an
+    /// executable file. Names with a `/` (or `\` on Windows) aren't looked up.
+    pub(crate) fn resolve_in_path(&mut self, name: &str) -> Option<PathBuf> {
+        if name.contains(std::path::is_separator) {
+            return None;
+        }
+        if let Some(path) = self.path_cache.get(name) {
+            if !self.options.checkhash || is_executable(path) {
+                return Some(path.clone());
+            }
+            self.path_cache.remove(name);
+        }
+
+        let path_var = self.var("PATH").unwrap_or_default();
+        let names = executable_names(name, self.var("PATHEXT"));
+        let path = env::split_paths(&path_var)
+            .map(|dir| self.pwd.join(dir))
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .find(|path| is_executable(path))?;
+        self.path_cache.insert(name.to_string(), path.clone());
+        Some(path)
+    }
+
+    /// Check that `path` is inside the sandbox, if there is one
+    ///
+    /// Symlinks are followed, and a file that doesn't exist yet counts as
+    /// being where its directory is.
+    pub(crate) fn confine(&self, path: &Path) -> Result<()> {
+        let Some(root) = &self.sandbox else {
+            return Ok(());
+        };
+        let resolved = canonicalize(path).or_else(|e| {
+            let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+                return Err(e);
+            };
+            Ok(canonicalize(dir)?.join(name))
+        });
+        match resolved {
+            Ok(resolved) if !resolved.starts_with(root) => Err(ShellError::InvalidArgument(
+                "sandbox: path escapes root".into(),
+            )),
+            // Paths that can't be resolved can't be opened either
+            _ => Ok(()),
+        }
+    }
+
+    /// Expand `words` like the arguments of a command
+    ///
+    /// Brace expansion comes first, and can turn one word into several, and
+    /// so can splitting the results of other expansions into fields.
+    fn expand_list<'a>(
+        &mut self,
+        words: impl IntoIterator<Item = &'a String>,
+    ) -> Result<Vec<String>> {
+        let brace_expanded: Vec<String> = words
+            .into_iter()
+            .flat_map(|word| expand_braces(word))
+            .collect();
+        let mut expanded = Vec::new();
+        for word in brace_expanded {
+            expanded.extend(expand_fields(&word, self)?);
+        }
+        Ok(expanded)
+    }
+
+    fn expand_words(&mut self, command: &Command) -> Result<Command> {
+        self.substituted = None;
+        let assignments = command
+            .assignments
+            .iter()
+            .map(|(name, value)| {
+                // Array elements are expanded once they are assigned
+                let value = match array_literal(value) {
+                    Some(_) => value.clone(),
+                    None => expand_word(value, self)?,
+                };
+                Ok((name.clone(), value))
+            })
+            .collect::<Result<_>>()?;
+
+        let words = std::iter::once(&command.bin).chain(&command.args);
+        let mut words = self.expand_list(words)?.into_iter();
+
+        Ok(Command {
+            assignments,
+            bin: words.next().unwrap_or_default(),
+            args: words.collect(),
+            redirects: command
+                .redirects
+                .iter()
+                .map(|redirect| {
+                    Ok(match redirect {
+                        Redirect::Write { fd, path, append } => Redirect::Write {
+                            fd: *fd,
+                            path: expand_word(path, self)?,
+                            append: *append,
+                        },
+                        Redirect::Read { fd, path } => Redirect::Read {
+                            fd: *fd,
+                            path: expand_word(path, self)?,
+                        },
+                        Redirect::Dup { fd, to } => Redirect::Dup {
+                            fd: *fd,
+                            to: expand_word(to, self)?,
+                        },
+                        Redirect::Heredoc {
+                            fd,
+                            delimiter,
+                            body,
+                        } => Redirect::Heredoc {
+                            fd: *fd,
+                            delimiter: delimiter.clone(),
+                            body: if delimiter.contains(['\'', '"', '\\']) {
+                                body.clone()
+                            } else {
+                                expand_heredoc(body, self)?
+                            },
+                        },
+                        Redirect::HereString { fd, text } => Redirect::HereString {
+                            fd: *fd,
+                            text: expand_word(text, self)?,
+                        },
+                    })
+                })
+                .collect::<Result<_>>()?,
+            program: None,
+        })
+    }
+
+    /// With `set -x`, print an expanded command about to run
+    fn trace(&self, command: &dyn std::fmt::Display) {
+        if self.options.xtrace {
+            eprintln!("+ {command}");
+        }
+    }
+
+    /// Report an error on stderr and set `$?` accordingly
+    ///
+    /// Errors in scripts come with the file name and line number.
+    fn report(&mut self, e: ShellError) {
+        match &self.location {
+            Some((name, line)) => print_error(format_args!("{name}: line {line}: {e}")),
+            None => print_error(&e),
+        }
+        self.last_status = e.status();
+    }
+
+    /// Run the commands in `script` line by line
+    ///
+    /// Like at the prompt, errors are reported and don't stop the script.
+    pub(crate) fn run_script(
+        &mut self,
+        name: &str,
+        script: &str,
+        out: &mut dyn Write,
+    ) -> Result<i32> {
+        let outer = self.location.take();
+        let result = self.run_script_lines(name, script, out);
+        self.location = outer;
+        result
+    }
+
+    fn run_script_lines(&mut self, name: &str, script: &str, out: &mut dyn Write) -> Result<i32> {
+        let mut lines = script.split_inclusive('\n').map(strip_cr).zip(1..);
+        while let Some((mut line, number)) = lines.next() {
+            self.location = Some((name.to_string(), number));
+            while parser::needs_more(&mut line) {
+                let Some((next, _)) = lines.next() else {
+                    break;
+                };
+                line.push_str(&next);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.parse(&line) {
+                Ok(chains) => {
+                    self.run(chains, out)?;
+                }
+                Err(e) => self.report(e),
+            }
+            if self.exit.is_some() {
+                break;
+            }
+        }
+        Ok(self.last_status)
+    }
+
+    /// Set `$?` to the status of a finished command and write its output
+    fn write_output(&mut self, output: Output, out: &mut dyn Write) -> Result<()> {
+        self.last_status = output.status;
+        out.write_all(&output.stdout)?;
+        Ok(())
+    }
+
+    /// Execute commands, writing their output to `out`
+    ///
+    /// A failing command, or a failure to write its output, is reported on
+    /// stderr and doesn't stop the remaining ones. The exception is a broken
+    /// pipe, which is returned as an error. Returns the exit status of the
+    /// last command.
+    pub(crate) fn run(&mut self, chains: Vec<CommandChain>, out: &mut dyn Write) -> Result<i32> {
+        for chain in chains {
+            // Commands run by this one aren't reported on their own
+            let json = std::mem::replace(&mut self.json, false);
+            let report = json.then(|| (chain.to_string(), Instant::now()));
+            let mut out = Counted {
+                inner: out,
+                bytes: 0,
+            };
+
+            // These apply `set -e` to the commands inside them instead
+            let compound = matches!(
+                chain,
+                CommandChain::Group(_)
+                    | CommandChain::If { .. }
+                    | CommandChain::For { .. }
+                    | CommandChain::While { .. }
+                    | CommandChain::And(..)
+                    | CommandChain::Or(..)
+            );
+            let result = self
+                .run_chain(chain, &mut out)
+                .and_then(|output| self.write_output(output, &mut out));
+            self.json = json;
+            match result {
+                Ok(()) => (),
+                // Nobody reads our output anymore, so stop the whole list
+                Err(ShellError::Io(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                    return Err(e.into());
+                }
+                Err(e) => self.report(e),
+            }
+            if let Some((command, start)) = report {
+                let result = CommandResult {
+                    command,
+                    status: self.last_status,
+                    stdout_bytes: out.bytes,
+                    duration_ms: start.elapsed().as_millis() as u64,
+                };
+                if let Ok(json) = serde_json::to_string(&result) {
+                    eprintln!("{json}");
+                }
+            }
+
+            if self.options.errexit && self.last_status != 0 && !self.tested && !compound {
+                self.exit = Some(self.last_status);
+            }
+            // Ctrl-C stops the whole command line
+            if self.exit.is_some() || self.flow.is_some() || interrupt::interrupted() {
+                break;
+            }
+        }
+        Ok(self.last_status)
+    }
+
+    fn run_chain(&mut self, chain: CommandChain, out: &mut dyn Write) -> Result<Output> {
+        // Like in bash, an interactive shell has to keep running commands
+        if self.options.noexec && !self.interactive {
+            self.dry_run(chain, out)?;
+            return Ok(Output::default());
+        }
+        let output = match chain {
+            CommandChain::Command(command) => {
+                let command = self.expand(&command)?;
+                self.trace(&command);
+                self.run_command(&command, out)?
+            }
+            CommandChain::Piped(stages)
+                if stages
+                    .iter()
+                    .any(|stage| stage.bin.is_empty() || self.is_builtin(&stage.bin)) =>
+            {
+                self.run_pipeline_with_builtins(stages)?
+            }
+            CommandChain::Piped(stages) => {
+                let stages = stages
+                    .iter()
+                    .map(|stage| {
+                        let stage = self.expand(stage)?;
+                        self.vet(stage)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let pipeline: Vec<String> = stages.iter().map(|stage| stage.to_string()).collect();
+                self.trace(&pipeline.join(" | "));
+
+                let start = self.profile.start();
+                let result = Command::run_pipeline(
+                    &stages,
+                    &self.pwd,
+                    &self.env(),
+                    self.umask,
+                    self.options.pipefail,
+                );
+                self.profile.record(Phase::Process, start);
+                let output = self.foreground(result?, pipeline.join(" | "));
+                for stage in &stages {
+                    self.ran(stage, output.status);
+                }
+                output
+            }
+            CommandChain::Group(chains) => Output {
+                status: self.run(chains, out)?,
+                ..Output::default()
+            },
+            CommandChain::If {
+                branches,
+                otherwise,
+            } => self.run_if(branches, otherwise, out)?,
+            CommandChain::For { name, words, body } => {
+                let words = match words {
+                    Some(words) => self.expand_list(&words)?,
+                    None => self.args.clone(),
+                };
+                self.loops += 1;
+                let output = self.run_for(&name, words, &body, out);
+                self.loops -= 1;
+                output?
+            }
+            CommandChain::While {
+                condition,
+                body,
+                until,
+            } => {
+                self.loops += 1;
+                let output = self.run_loop(&condition, &body, until, out);
+                self.loops -= 1;
+                output?
+            }
+            CommandChain::And(first, second) => self.run_conditional(*first, *second, true, out)?,
+            CommandChain::Or(first, second) => self.run_conditional(*first, *second, false, out)?,
+            CommandChain::Background(chain) => self.run_background(*chain)?,
+            CommandChain::Arith(expr) => {
+                // The expression is expanded as if it was in double quotes
+                let expr = expand_word(&format!("\"{expr}\""), self)?;
+                Output {
+                    status: arith::status(arith::eval(&expr, self)?),
+                    ..Output::default()
+                }
+            }
+        };
+        Ok(output)
+    }
+
+    /// Run a pipeline with builtins among its stages
+    ///
+    /// Like in other shells, those run in a subshell, here on a thread of
+    /// their own, with the pipes from and to the stages next to them as
+    /// their stdin and stdout. The others get a process each, and all of
+    /// them run side by side.
+    fn run_pipeline_with_builtins(&mut self, stages: Vec<Command>) -> Result<Output> {
+        let (mut reader, writer) = std::io::pipe()?;
+        let mut output = Some(writer);
+        let mut input = None;
+        let mut processes = vec![];
+        let count = stages.len();
+        for (i, stage) in stages.into_iter().enumerate() {
+            let (next_input, stdout) = if i + 1 == count {
+                (
+                    None,
+                    output
+                        .take()
+                        .expect("only the last stage writes the output"),
+                )
+            } else {
+                let (reader, writer) = std::io::pipe()?;
+                (Some(reader), writer)
+            };
+            if stage.bin.is_empty() || self.is_builtin(&stage.bin) {
+                let mut subshell = self.subshell();
+                subshell.stdin = input.take().map(|pipe| Arc::new(pipe_file(pipe)));
+                processes.push(Process::Thread(Some(std::thread::spawn(move || {
+                    let mut stdout = stdout;
+                    match subshell.run(vec![CommandChain::Command(stage)], &mut stdout) {
+                        Ok(_) => (),
+                        // The next stage stopped reading
+                        Err(ShellError::Io(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+                        Err(e) => subshell.report(e),
+                    }
+                    subshell.last_status
+                }))));
+            } else {
+                let stage = self.expand(&stage)?;
+                let stage = self.vet(stage)?;
+                self.trace(&stage);
+                let stdin = input.take().map_or_else(Stdio::null, Stdio::from);
+                let env = self.env();
+                let child =
+                    stage.spawn_with(&self.pwd, &env, self.umask, stdin, Some(stdout), None)?;
+                processes.push(Process::Child(child));
+            }
+            input = next_input;
+        }
+
+        // Everything writing to the pipe has it now, so it ends once they do
+        let mut stdout = vec![];
+        reader.read_to_end(&mut stdout)?;
+        let mut status = 0;
+        let mut failed = 0;
+        for process in processes {
+            status = match process {
+                Process::Child(mut child) => exit_code(child.wait()?),
+                Process::Thread(handle) => handle.map_or(1, |handle| handle.join().unwrap_or(1)),
+                Process::Pipeline(_) => unreachable!("stages are started one by one"),
+            };
+            if status != 0 {
+                failed = status;
+            }
+        }
+        Ok(Output {
+            stdout,
+            status: if self.options.pipefail {
+                failed
+            } else {
+                status
+            },
+        })
+    }
+
+    /// Run `first`, then `second` if `first` succeeded (for `&&`) or
+    /// failed (for `||`)
+    fn run_conditional(
+        &mut self,
+        first: CommandChain,
+        second: CommandChain,
+        and: bool,
+        out: &mut dyn Write,
+    ) -> Result<Output> {
+        let tested = std::mem::replace(&mut self.tested, true);
+        let status = self.run(vec![first], out);
+        self.tested = tested;
+
+        let mut status = status?;
+        if (status == 0) == and && self.exit.is_none() && self.flow.is_none() {
+            status = self.run(vec![second], out)?;
+        }
+        Ok(Output {
+            status,
+            ..Output::default()
+        })
+    }
+
+    /// Run the body of the first branch whose condition succeeds, or
+    /// `otherwise` if none does
+    ///
+    /// Succeeds without running anything if there's no body to run.
+    fn run_if(
+        &mut self,
+        branches: Vec<(Vec<CommandChain>, Vec<CommandChain>)>,
+        otherwise: Vec<CommandChain>,
+        out: &mut dyn Write,
+    ) -> Result<Output> {
+        let mut body = otherwise;
+        for (condition, then) in branches {
+            let tested = std::mem::replace(&mut self.tested, true);
+            let status = self.run(condition, out);
+            self.tested = tested;
+            if self.exit.is_some() || self.flow.is_some() {
+                return Ok(Output {
+                    status: status?,
+                    ..Output::default()
+                });
+            }
+            if status? == 0 {
+                body = then;
+                break;
+            }
+        }
+        Ok(Output {
+            status: if body.is_empty() {
+                0
+            } else {
+                self.run(body, out)?
+            },
+            ..Output::default()
+        })
+    }
+
+    /// Run `body` once for each of `words`, with the variable `name` set to it
+    ///
+    /// Returns the status of the last command of the body, or 0 if it
+    /// didn't run. Ctrl-C stops the loop with status 130.
+    fn run_for(
+        &mut self,
+        name: &str,
+        words: Vec<String>,
+        body: &[CommandChain],
+        out: &mut dyn Write,
+    ) -> Result<Output> {
+        let mut status = 0;
+        for word in words {
+            self.set_var(name, word);
+            status = self.run(body.to_vec(), out)?;
+            if self.leave_loop() {
+                break;
+            }
+        }
+        if interrupt::interrupted() {
+            status = 130;
+        }
+        Ok(Output {
+            status,
+            ..Output::default()
+        })
+    }
+
+    /// Run `body` as long as `condition` succeeds, or fails if `until` is
+    /// set
+    ///
+    /// Returns the status of the last command of the body, or 0 if it
+    /// didn't run. Ctrl-C stops the loop with status 130.
+    fn run_loop(
+        &mut self,
+        condition: &[CommandChain],
+        body: &[CommandChain],
+        until: bool,
+        out: &mut dyn Write,
+    ) -> Result<Output> {
+        let mut status = 0;
+        loop {
+            let tested = std::mem::replace(&mut self.tested, true);
+            let result = self.run(condition.to_vec(), out);
+            self.tested = tested;
+            if (result? == 0) == until || self.leave_loop() {
+                break;
+            }
+
+            status = self.run(body.to_vec(), out)?;
+            if self.leave_loop() {
+                break;
+            }
+        }
+        if interrupt::interrupted() {
+            status = 130;
+        }
+        Ok(Output {
+            status,
+            ..Output::default()
+        })
+    }
+
+    /// Whether the loop being run has to stop, after `exit`, Ctrl-C or a
+    /// `break` or `continue` meant for it or one around it
+    fn leave_loop(&mut self) -> bool {
+        if interrupt::interrupted() {
+            return true;
+        }
+        match self.flow.take() {
+            None => self.exit.is_some(),
+            Some(Flow::Continue(1)) => false,
+            Some(Flow::Break(1)) => true,
+            Some(Flow::Break(levels)) => {
+                self.flow = Some(Flow::Break(levels - 1));
+                true
+            }
+            Some(Flow::Continue(levels)) => {
+                self.flow = Some(Flow::Continue(levels - 1));
+                true
+            }
+        }
+    }
+
+    /// Print the commands in `chain` to `out`, expanded, instead of running
+    /// them, for `set -n`
+    ///
+    /// Every branch of a condition and the body of a loop show up once.
+    /// Command substitutions are shown the same way, as the commands in them.
+    fn dry_run(&mut self, chain: CommandChain, out: &mut dyn Write) -> Result<()> {
+        match chain {
+            CommandChain::Command(command) => writeln!(out, "{}", self.expand(&command)?)?,
+            CommandChain::Piped(stages) => {
+                let stages = stages
+                    .iter()
+                    .map(|stage| Ok(self.expand(stage)?.to_string()))
+                    .collect::<Result<Vec<_>>>()?;
+                writeln!(out, "{}", stages.join(" | "))?;
+            }
+            CommandChain::Group(chains) | CommandChain::For { body: chains, .. } => {
+                for chain in chains {
+                    self.dry_run(chain, out)?;
+                }
+            }
+            CommandChain::If {
+                branches,
+                otherwise,
+            } => {
+                let chains = branches
+                    .into_iter()
+                    .flat_map(|(condition, body)| condition.into_iter().chain(body));
+                for chain in chains.chain(otherwise) {
+                    self.dry_run(chain, out)?;
+                }
+            }
+            CommandChain::While {
+                condition, body, ..
+            } => {
+                for chain in condition.into_iter().chain(body) {
+                    self.dry_run(chain, out)?;
+                }
+            }
+            CommandChain::And(first, second) | CommandChain::Or(first, second) => {
+                self.dry_run(*first, out)?;
+                self.dry_run(*second, out)?;
+            }
+            CommandChain::Background(chain) => self.dry_run(*chain, out)?,
+            chain @ CommandChain::Arith(_) => writeln!(out, "{chain}")?,
+        }
+        Ok(())
+    }
+
+    /// The output of a command that ran in the foreground, making it a
+    /// job if it was stopped
+    pub(crate) fn foreground(&mut self, ran: Ran, text: String) -> Output {
+        match ran {
+            Ran::Finished(output) => output,
+            Ran::Stopped {
+                output,
+                process,
+                group,
+            } => {
+                let job = self.jobs.add(text, process, group);
+                job.state = JobState::Stopped(output.status);
+                eprintln!("\n[{}]+  {:<24}{}", job.id, job.state, job.command);
+                output
+            }
+        }
+    }
+
+    /// Start `chain` as a background job
+    ///
+    /// When the shell catches Ctrl-C and Ctrl-Z, the job gets a process
+    /// group of its own, out of their reach.
+    fn run_background(&mut self, chain: CommandChain) -> Result<Output> {
+        let text = chain.to_string();
+        let group = interrupt::caught();
+        let process = match chain {
+            CommandChain::Command(command)
+                if !command.bin.is_empty() && !self.is_builtin(&command.bin) =>
+            {
+                let command = self.expand(&command)?;
+                let command = self.vet(command)?;
+                Process::Child(command.spawn(&self.pwd, &self.env(), self.umask, group)?)
+            }
+            // Pipelines of external commands get a process for each stage,
+            // so that they run side by side and can be signaled
+            CommandChain::Piped(stages)
+                if stages
+                    .iter()
+                    .all(|stage| !stage.bin.is_empty() && !self.is_builtin(&stage.bin)) =>
+            {
+                let stages = stages
+                    .iter()
+                    .map(|stage| {
+                        let stage = self.expand(stage)?;
+                        self.vet(stage)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Process::Pipeline(Command::spawn_pipeline(
+                    &stages,
+                    &self.pwd,
+                    &self.env(),
+                    self.umask,
+                    group,
+                )?)
+            }
+            chain => {
+                // Run in a subshell on its own thread, so that builtins and
+                // whole groups can be sent to the background too
+                let mut subshell = self.subshell();
+                Process::Thread(Some(std::thread::spawn(move || {
+                    let mut stdout = std::io::stdout();
+                    if let Err(e) = subshell.run(vec![chain], &mut stdout) {
+                        subshell.report(e);
+                    }
+                    subshell.last_status
+                })))
+            }
+        };
+
+        let job = self.jobs.add(text, process, None);
+        if group {
+            // The first process leads the group
+            job.group = job.pid();
+        }
+        match job.pid() {
+            Some(pid) => eprintln!("[{}] {pid}", job.id),
+            None => eprintln!("[{}]", job.id),
+        }
+        Ok(Output::default())
+    }
+
+    /// Print a notice for each background job that has finished since the
+    /// last call, and forget about it
+    pub(crate) fn reap_jobs(&mut self) -> Result<()> {
+        let current = self.jobs.current();
+        for job in self.jobs.reap()? {
+            let marker = if Some(job.id) == current { "+" } else { " " };
+            eprintln!("[{}]{marker}  {:<24}{}", job.id, job.state, job.command);
+        }
+        Ok(())
+    }
+
+    /// Whether `name` refers to an enabled builtin
+    pub(crate) fn is_builtin(&self, name: &str) -> bool {
+        self.builtins.contains_key(name) && !self.disabled.contains(name)
+    }
+
+    /// Run a single command, be it a builtin or an external one
+    pub(crate) fn run_command(&mut self, command: &Command, out: &mut dyn Write) -> Result<Output> {
+        // `exec` without a command keeps its redirections open in the shell
+        if command.bin == "exec" && command.args.is_empty() && self.is_builtin("exec") {
+            self.open_fds(&command.redirects)?;
+            return Ok(Output::default());
+        }
+        #[cfg(unix)]
+        if command.bin == "exec" && self.replace_on_exec && self.is_builtin("exec") {
+            return self.exec(command, out);
+        }
+        let is_builtin = command.bin.is_empty() || self.is_builtin(&command.bin);
+        if !is_builtin {
+            let command = self.vet(command.clone())?;
+            let start = self.profile.start();
+            let result = command.execute(&self.pwd, &self.env(), self.umask, None, None);
+            self.profile.record(Phase::Process, start);
+            let output = self.foreground(result?, command.to_string());
+            self.ran(&command, output.status);
+            return Ok(output);
+        }
+        if command.redirects.is_empty() {
+            return self.builtin(command, out);
+        }
+
+        // Builtins write to `out`, so send that wherever stdout was redirected
+        let streams = Streams::open(&command.redirects, &self.pwd, self.umask)?;
+        if let Target::File(file) = streams.stdin {
+            self.stdin = Some(Arc::new(file));
+        }
+        let mut stdout = vec![];
+        let output = self.builtin(command, &mut stdout);
+        self.stdin = None;
+        let output = output?;
+        match streams.stdout {
+            Target::Piped => out.write_all(&stdout)?,
+            Target::Stderr => std::io::stderr().write_all(&stdout)?,
+            Target::File(mut file) => file.write_all(&stdout)?,
+        }
+        Ok(output)
+    }
+
+    /// Replace the shell's process with the command given to `exec`, which
+    /// gets the redirections of `exec` itself
+    ///
+    /// Only returns if the command couldn't be run, and the shell goes on.
+    #[cfg(unix)]
+    fn exec(&mut self, exec: &Command, out: &mut dyn Write) -> Result<Output> {
+        let (bin, args) = exec.args.split_first().expect("exec without a command");
+        let command = Command {
+            assignments: exec.assignments.clone(),
+            bin: bin.clone(),
+            args: args.to_vec(),
+            redirects: exec.redirects.clone(),
+            program: self.resolve_in_path(bin),
+        };
+        let command = self.vet(command)?;
+        // What the shell wrote so far comes before the command's output
+        out.flush()?;
+        Err(command.exec(&self.pwd, &self.env(), self.umask))
+    }
+
+    /// Open the files in `redirects` as the shell's own descriptors, for the
+    /// rest of the session, or close them with `n<&-`
+    ///
+    /// Child processes inherit them, and `<&n` or `read -u n` read from them.
+    #[cfg(unix)]
+    fn open_fds(&mut self, redirects: &[Redirect]) -> Result<()> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+        for redirect in redirects {
+            let fd = redirect.fd();
+            if fd <= 2 {
+                return Err(ShellError::InvalidArgument(format!(
+                    "exec: {fd}: only descriptors above 2 can be opened"
+                )));
+            }
+            // Replacing one of our own is fine, but not a descriptor the
+            // shell uses for something else
+            self.fds.remove(&fd);
+            if matches!(redirect, Redirect::Dup { to, .. } if to == "-") {
+                self.close_coproc_fd(fd);
+                continue;
+            }
+            let raw = fd as i32;
+            // SAFETY: `fcntl` only checks whether `raw` is open
+            if unsafe { libc::fcntl(raw, libc::F_GETFD) } != -1 {
+                return Err(ShellError::InvalidArgument(format!(
+                    "exec: {fd}: descriptor in use by the shell"
+                )));
+            }
+
+            let file = match redirect {
+                Redirect::Dup { to, .. } => {
+                    let to = to.parse().map_err(|_| {
+                        ShellError::InvalidArgument(format!("exec: {to}: bad file descriptor"))
+                    })?;
+                    redirect::dup_fd(to)?
+                }
+                _ => match redirect.open_file(&self.pwd, self.umask)? {
+                    Some(file) => file,
+                    None => unreachable!("only dups have no file"),
+                },
+            };
+            let owned = if file.as_raw_fd() == raw {
+                // It got the number we wanted, but mustn't be closed when
+                // running other programs
+                // SAFETY: `raw` is open, as `file`
+                unsafe { libc::fcntl(raw, libc::F_SETFD, 0) };
+                OwnedFd::from(file)
+            } else {
+                // SAFETY: `dup2` onto a descriptor that isn't open makes a
+                // new one that nobody else owns
+                unsafe {
+                    if libc::dup2(file.as_raw_fd(), raw) < 0 {
+                        return Err(std::io::Error::last_os_error().into());
+                    }
+                    OwnedFd::from_raw_fd(raw)
+                }
+            };
+            self.fds.insert(fd, Arc::new(owned));
+        }
+        Ok(())
+    }
+
+    /// Close the end of a `coproc` pipe numbered `fd`, if it is one, and
+    /// clear it in the coproc's array
+    #[cfg(unix)]
+    fn close_coproc_fd(&mut self, fd: u32) {
+        use std::os::fd::AsRawFd;
+
+        for (name, ends) in &mut self.coprocs {
+            for (i, end) in ends.iter_mut().enumerate() {
+                if end.as_ref().is_some_and(|end| end.as_raw_fd() == fd as i32) {
+                    *end = None;
+                    if let Some(slot) = self.arrays.get_mut(name).and_then(|fds| fds.get_mut(i)) {
+                        slot.clear();
+                    }
+                }
+            }
+        }
+        self.coprocs
+            .retain(|_, ends| ends.iter().any(Option::is_some));
+    }
+
+    #[cfg(not(unix))]
+    fn open_fds(&mut self, _: &[Redirect]) -> Result<()> {
+        Err(ShellError::InvalidArgument(
+            "exec: opening descriptors is not supported on this platform".into(),
+        ))
+    }
+
+    /// Run a builtin, or set the variables of an assignment-only command
+    fn builtin(&mut self, command: &Command, out: &mut dyn Write) -> Result<Output> {
+        if command.bin.is_empty() {
+            // Only assignments, which stay set for the session
+            for (name, value) in &command.assignments {
+                match array_literal(value) {
+                    Some(elements) => {
+                        let elements = parser::split_words(elements)?
+                            .iter()
+                            .map(|element| expand_fields(element, self))
+                            .collect::<Result<Vec<_>>>()?
+                            .concat();
+                        self.vars.remove(name);
+                        self.arrays.insert(name.clone(), elements);
+                    }
+                    None => self.set_var(name, value.clone()),
+                }
+            }
+            return Ok(Output {
+                status: self.substituted.unwrap_or(0),
+                ..Output::default()
+            });
+        }
+
+        let builtins = Arc::clone(&self.builtins);
+        let Some(builtin) = builtins.get(&command.bin) else {
+            unreachable!("{} is not a builtin", command.bin);
+        };
+        // Assignments in front of a builtin, like `IFS=: read`, only last
+        // while it runs
+        let saved: Vec<(String, Option<String>)> = command
+            .assignments
+            .iter()
+            .map(|(name, value)| {
+                let old = self.vars.get(name).cloned();
+                self.set_var(name, value.clone());
+                (name.clone(), old)
+            })
+            .collect();
+        let start = self.profile.start();
+        let status = builtin.run(self, &command.args, out);
+        self.profile.record(Phase::Builtin, start);
+        for (name, old) in saved.into_iter().rev() {
+            match old {
+                Some(value) => self.set_var(&name, value),
+                None => {
+                    if name == "PATH" {
+                        self.path_cache.clear();
+                    }
+                    self.vars.remove(&name);
+                }
+            }
+        }
+        Ok(Output {
+            status: status?,
+            ..Output::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A runner in `pwd`, with `home` as its home directory
+    fn runner_in(pwd: &Path, home: &Path) -> CommandRunner {
+        let mut runner = CommandRunner::new();
+        runner.pwd = pwd.to_path_buf();
+        runner.set_var("HOME", home.to_string_lossy().into_owned());
+        runner
+    }
+
+    #[test]
+    fn prompts_use_the_platform_separator() {
+        let home = std::env::temp_dir().join("home");
+        let runner = runner_in(&home.join("src").join("rush"), &home);
+        let separator = std::path::MAIN_SEPARATOR;
+        assert_eq!(
+            runner.prompt(r"\w"),
+            format!("~{separator}src{separator}rush")
+        );
+        assert_eq!(runner.prompt(r"\W"), "rush");
+        assert_eq!(runner_in(&home, &home).prompt(r"\w"), "~");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn commands_are_found_by_their_name_alone() {
+        assert_eq!(executable_names("tool", Some(".EXE".into())), ["tool"]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn commands_are_found_with_the_extensions_in_pathext() {
+        let names = executable_names("tool", Some(".EXE;.BAT".into()));
+        assert_eq!(names, ["tool.exe", "tool.bat"]);
+        assert_eq!(executable_names("tool.cmd", None), ["tool.cmd"]);
+        assert_eq!(executable_names("tool", None).len(), 4);
+
+        let dir = std::env::temp_dir().join(format!("rush-pathext-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tool.bat"), "@echo tool\r\n").unwrap();
+        let mut runner = CommandRunner::new();
+        runner.set_var("PATH", dir.to_string_lossy().into_owned());
+        runner.set_var("PATHEXT", ".EXE;.BAT".into());
+        assert_eq!(runner.resolve_in_path("tool"), Some(dir.join("tool.bat")));
+        assert_eq!(runner.resolve_in_path("missing"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn canonical_paths_have_no_verbatim_prefix() {
+        let path = canonicalize(&std::env::temp_dir()).unwrap();
+        assert!(path.is_absolute());
+        assert!(
+            !path.to_string_lossy().starts_with(r"\\?\"),
+            "{}",
+            path.display()
+        );
+    }
+}
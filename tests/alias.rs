@@ -0,0 +1,27 @@
+use rush::Shell;
+
+const SCRIPT: &str = "alias greet='echo hi'\ngreet\n";
+
+#[test]
+fn aliases_are_inert_in_scripts() {
+    let output = Shell::new().run_reader(SCRIPT.as_bytes()).unwrap();
+    assert!(output.stdout.is_empty());
+    assert_eq!(output.status, 127);
+}
+
+#[test]
+fn expand_aliases_turns_them_on_in_scripts() {
+    let script = format!("set -o expand_aliases\n{SCRIPT}");
+    let output = Shell::new().run_reader(script.as_bytes()).unwrap();
+    assert_eq!(output.stdout, b"hi\n");
+}
+
+#[test]
+fn aliases_expand_in_interactive_sessions() {
+    let mut output = vec![];
+    Shell::new()
+        .run_with(SCRIPT.as_bytes(), &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("hi\n"), "{output}");
+}
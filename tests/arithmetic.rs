@@ -0,0 +1,23 @@
+mod common;
+
+use common::run;
+use rush::Shell;
+
+#[test]
+fn let_assigns_the_result() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "let x=2+3"), (String::new(), 0));
+    assert_eq!(run(&mut shell, "echo $x").0, "5\n");
+    // The status is that of the last expression
+    assert_eq!(run(&mut shell, r#"let "y = x * 2" z=0"#).1, 1);
+    assert_eq!(run(&mut shell, "echo $y $z").0, "10 0\n");
+}
+
+#[test]
+fn double_parentheses_succeed_on_nonzero_values() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "(( 0 ))").1, 1);
+    assert_eq!(run(&mut shell, "(( 7 ))").1, 0);
+    assert_eq!(run(&mut shell, "(( x = 4 )); echo $x"), ("4\n".into(), 0));
+    assert_eq!(run(&mut shell, "(( x > 4 ))").1, 1);
+}
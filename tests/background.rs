@@ -0,0 +1,52 @@
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use rush::Shell;
+
+#[test]
+fn backgrounded_groups_run_while_the_shell_goes_on() {
+    let file = std::env::temp_dir().join(format!("rush-background-{}", std::process::id()));
+    let _ = fs::remove_file(&file);
+    let mut shell = Shell::new();
+    let start = Instant::now();
+    let line = format!("{{ sleep 0.5; echo done > {}; }} &", file.display());
+    assert_eq!(shell.run_line(&line).unwrap().status, 0);
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert!(!file.exists());
+
+    shell.run_line("wait").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "done\n");
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn backgrounded_builtins_run_in_a_subshell() {
+    let mut shell = Shell::new();
+    let output = shell
+        .run_line("x=1; { x=2; cd /; } & wait; echo $x")
+        .unwrap();
+    assert_eq!(output.stdout, b"1\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn coprocs_filter_what_is_written_to_them() {
+    let dir = std::env::temp_dir().join(format!("rush-coproc-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // The descriptor to close is only known once the coproc runs
+    let script = "coproc C tr a-z A-Z\n\
+                  echo hi >&${C[1]}\n\
+                  echo \"exec ${C[1]}>&-\" > close\n\
+                  . ./close\n\
+                  read line <&${C[0]}\n\
+                  echo \"$line [${C[1]}]\"\n";
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["-c", script])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "HI []\n");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,253 @@
+mod common;
+
+use common::run;
+use rush::Shell;
+
+#[test]
+fn type_tells_what_kind_of_command_a_name_is() {
+    let mut shell = Shell::new();
+    run(&mut shell, "alias ll='ls -l'");
+    let (out, status) = run(&mut shell, "type ll cd");
+    assert_eq!(out, "ll is aliased to `ls -l'\ncd is a shell builtin\n");
+    assert_eq!(status, 0);
+    let (out, _) = run(&mut shell, "type sh");
+    assert!(out.starts_with("sh is /"), "{out}");
+    assert_eq!(run(&mut shell, "type no-such-command").1, 1);
+}
+
+#[test]
+fn type_skips_wrappers_and_assignments() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "type sudo cd").0, "cd is a shell builtin\n");
+    let (out, _) = run(&mut shell, "type env -i A=1 B=2 cd");
+    assert_eq!(out, "cd is a shell builtin\n");
+    assert_eq!(
+        run(&mut shell, "type command builtin cd").0,
+        "cd is a shell builtin\n"
+    );
+}
+
+#[test]
+fn complete_sets_and_lists_words_for_commands() {
+    let mut shell = Shell::new();
+    run(&mut shell, "complete -W 'push commit' git");
+    run(&mut shell, "complete -W 'a b' x");
+    let (out, _) = run(&mut shell, "complete");
+    assert_eq!(out, "complete -W 'push commit' git\ncomplete -W 'a b' x\n");
+    run(&mut shell, "complete -r x");
+    assert_eq!(
+        run(&mut shell, "complete -p").0,
+        "complete -W 'push commit' git\n"
+    );
+    assert_eq!(run(&mut shell, "complete -r x").1, 1);
+}
+
+#[test]
+fn list_builtins_prints_each_once() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rush"))
+        .arg("--list-builtins")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let listed: Vec<String> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    let mut builtins: Vec<String> = Shell::builtins().collect();
+    builtins.sort();
+    let mut sorted = listed.clone();
+    sorted.sort();
+    assert_eq!(sorted, builtins);
+    sorted.dedup();
+    assert_eq!(sorted.len(), listed.len());
+    assert!(listed.iter().any(|name| name == "cd"));
+}
+
+#[test]
+fn cd_dash_prints_where_it_goes() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "cd /tmp; cd /"), (String::new(), 0));
+    assert_eq!(run(&mut shell, "cd -"), ("/tmp\n".into(), 0));
+    assert_eq!(run(&mut shell, "echo $PWD $OLDPWD").0, "/tmp /\n");
+    assert_eq!(run(&mut shell, "cd /usr").0, "");
+}
+
+#[test]
+fn disabled_builtins_leave_the_name_to_external_commands() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "enable -n echo"), (String::new(), 0));
+    let (out, _) = run(&mut shell, "type echo");
+    assert!(out.starts_with("echo is /"), "{out}");
+    assert_eq!(run(&mut shell, "echo external").0, "external\n");
+    assert_eq!(run(&mut shell, "enable -n").0, "enable -n echo\n");
+    assert!(!run(&mut shell, "enable").0.contains("enable echo\n"));
+
+    run(&mut shell, "enable echo");
+    assert_eq!(run(&mut shell, "type echo").0, "echo is a shell builtin\n");
+    assert!(run(&mut shell, "enable").0.contains("enable echo\n"));
+}
+
+/// A file holding `text`, for `read` to read from
+fn input(name: &str, text: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("rush-read-{name}-{}", std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn read_splits_fields_by_ifs() {
+    let mut shell = Shell::new();
+    let file = input("fields", "one two three four\n");
+    run(&mut shell, &format!("read a b c < {}", file.display()));
+    assert_eq!(
+        run(&mut shell, r#"echo "[$a] [$b] [$c]""#).0,
+        "[one] [two] [three four]\n"
+    );
+    std::fs::remove_file(file).unwrap();
+
+    let file = input("ifs", "1:2:3\n");
+    run(&mut shell, &format!("IFS=: read x y < {}", file.display()));
+    assert_eq!(run(&mut shell, r#"echo "[$x] [$y]""#).0, "[1] [2:3]\n");
+    // The assignment was only for `read`
+    assert_eq!(run(&mut shell, "echo $a,$b").0, "one,two\n");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn read_joins_continued_lines_unless_raw() {
+    let mut shell = Shell::new();
+    let file = input("continued", "first \\\nsecond\n");
+    run(&mut shell, &format!("read line < {}", file.display()));
+    assert_eq!(run(&mut shell, r#"echo "[$line]""#).0, "[first second]\n");
+    run(&mut shell, &format!("read -r line < {}", file.display()));
+    assert_eq!(run(&mut shell, r#"echo "[$line]""#).0, "[first \\]\n");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn read_u_takes_lines_from_an_opened_descriptor() {
+    let file = std::env::temp_dir().join(format!("rush-read-u-{}", std::process::id()));
+    std::fs::write(&file, "l1\nl2 x\n").unwrap();
+    let script = format!(
+        "exec 3< {}\nread -u 3 a\nread -u 3 b c\nread -u 3 d; echo $?\n\
+         exec 3<&-\nread -u 3 e; echo $?\necho \"[$a] [$b] [$c]\"\n",
+        file.display()
+    );
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["-c", &script])
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"1\n1\n[l1] [l2] [x]\n");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn cd_reports_symlink_loops() {
+    let dir = std::env::temp_dir().join(format!("rush-cd-loop-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::os::unix::fs::symlink("b", dir.join("a")).unwrap();
+    std::os::unix::fs::symlink("a", dir.join("b")).unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["-c", &format!("cd {}/a; echo $? alive", dir.display())])
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"1 alive\n");
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        error.ends_with("cd: too many levels of symbolic links\n"),
+        "{error}"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn found_commands_are_remembered_until_hash_r_or_a_new_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("rush-hash-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let install = |name: &str| {
+        let tool = dir.join(name).join("tool");
+        std::fs::create_dir_all(tool.parent().unwrap()).unwrap();
+        std::fs::write(&tool, format!("#!/bin/sh\necho {name}\n")).unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    install("first");
+    install("second");
+    let path = format!("PATH={0}/first:{0}/second", dir.display());
+
+    let mut shell = Shell::new();
+    run(&mut shell, &path);
+    assert_eq!(run(&mut shell, "tool").0, "first\n");
+    // The remembered path is tried even though the binary is gone
+    std::fs::remove_file(dir.join("first/tool")).unwrap();
+    assert_ne!(run(&mut shell, "tool").1, 0);
+
+    run(&mut shell, "hash -r");
+    assert_eq!(run(&mut shell, "tool").0, "second\n");
+    // A binary put back earlier in PATH isn't noticed...
+    install("first");
+    assert_eq!(run(&mut shell, "tool").0, "second\n");
+    // ...until PATH is set again
+    run(&mut shell, &path);
+    assert_eq!(run(&mut shell, "tool").0, "first\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn checkhash_looks_commands_up_again_once_they_are_gone() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("rush-checkhash-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    for name in ["first", "second"] {
+        let tool = dir.join(name).join("tool");
+        std::fs::create_dir_all(tool.parent().unwrap()).unwrap();
+        std::fs::write(&tool, format!("#!/bin/sh\necho {name}\n")).unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let first = dir.join("first/tool");
+
+    let mut shell = Shell::new();
+    run(
+        &mut shell,
+        &format!("PATH={0}/first:{0}/second", dir.display()),
+    );
+    assert_eq!(run(&mut shell, "tool").0, "first\n");
+    assert_eq!(
+        run(&mut shell, "hash").0,
+        format!("tool\t{}\n", first.display())
+    );
+    std::fs::remove_file(&first).unwrap();
+
+    // The remembered path is used as long as checkhash is off
+    assert_ne!(run(&mut shell, "tool").1, 0);
+    run(&mut shell, "set -o checkhash");
+    assert_eq!(run(&mut shell, "tool"), ("second\n".into(), 0));
+    let second = dir.join("second/tool");
+    assert_eq!(
+        run(&mut shell, "hash").0,
+        format!("tool\t{}\n", second.display())
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn timeout_stops_commands_that_run_too_long() {
+    let mut shell = Shell::new();
+    let start = std::time::Instant::now();
+    assert_eq!(run(&mut shell, "timeout 0.1 sleep 5").1, 124);
+    assert!(start.elapsed() < std::time::Duration::from_secs(3));
+
+    assert_eq!(run(&mut shell, "timeout 5 true"), (String::new(), 0));
+    assert_eq!(run(&mut shell, "timeout 1 echo hi"), ("hi\n".into(), 0));
+    // Without a program of the same name, a builtin can't be timed
+    assert_ne!(run(&mut shell, "timeout 1 cd").1, 0);
+}
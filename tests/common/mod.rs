@@ -0,0 +1,7 @@
+use rush::Shell;
+
+/// Run `line` in `shell`, returning its output and status
+pub fn run(shell: &mut Shell, line: &str) -> (String, i32) {
+    let output = shell.run_line(line).unwrap();
+    (String::from_utf8(output.stdout).unwrap(), output.status)
+}
@@ -0,0 +1,39 @@
+use std::process::Command;
+
+/// Run `commands` with the shell binary, started at `depth` with at most
+/// `max` shells inside each other
+fn run_at(depth: &str, max: &str, commands: &str) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["-c", commands])
+        .env("SHELL_DEPTH", depth)
+        .env("RUSTLAB_MAX_DEPTH", max)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn shells_count_their_depth_up_and_pass_it_on() {
+    let output = run_at("2", "10", r#"echo $SHELL_DEPTH; sh -c 'echo $SHELL_DEPTH'"#);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n3\n");
+}
+
+#[test]
+fn shells_nested_too_deeply_refuse_to_start() {
+    let output = run_at("5", "5", "echo started");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains("shell depth (6) too high"), "{error}");
+}
+
+#[test]
+fn runaway_recursion_stops_at_the_limit() {
+    let rush = env!("CARGO_BIN_EXE_rush");
+    let output = run_at(
+        "0",
+        "3",
+        &format!("{rush} -c '{rush} -c \"{rush} -c {rush}\"'"),
+    );
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains("shell depth (4) too high"), "{error}");
+}
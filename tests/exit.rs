@@ -0,0 +1,36 @@
+use std::process::{Command, ExitStatus};
+
+/// Run `commands` with `rush -c`
+fn status(commands: &str) -> ExitStatus {
+    Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["-c", commands])
+        .status()
+        .unwrap()
+}
+
+#[test]
+fn c_exits_with_the_last_status() {
+    assert_eq!(status("false").code(), Some(1));
+    assert_eq!(status("true").code(), Some(0));
+    assert_eq!(status("false; true").code(), Some(0));
+    assert_eq!(status("no-such-command").code(), Some(127));
+}
+
+#[test]
+fn exit_statuses_are_masked_to_a_byte() {
+    assert_eq!(status("exit 300").code(), Some(44));
+    assert_eq!(status("exit -1").code(), Some(255));
+}
+
+#[test]
+fn commands_killed_by_a_signal_give_128_plus_it() {
+    assert_eq!(status(r#"sh -c 'kill -TERM $$'"#).code(), Some(143));
+}
+
+#[test]
+#[cfg(unix)]
+fn killing_the_shell_itself_ends_it_by_the_signal() {
+    use std::os::unix::process::ExitStatusExt;
+
+    assert_eq!(status("kill -TERM $$; echo survived").signal(), Some(15));
+}
@@ -0,0 +1,44 @@
+use rush::Shell;
+
+/// Run `script`, returning what it wrote to stdout
+fn run(script: &str) -> String {
+    let mut shell = Shell::new();
+    let output = shell.run_reader(script.as_bytes()).unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn the_body_is_the_commands_stdin() {
+    assert_eq!(
+        run("cat <<EOF\none\n  two\nEOF\necho after\n"),
+        "one\n  two\nafter\n"
+    );
+    assert_eq!(run("cat <<EOF | wc -l\na\nb\nc\nEOF\n").trim(), "3");
+}
+
+#[test]
+fn variables_are_expanded_unless_the_delimiter_is_quoted() {
+    let script = "name=world\ncat <<EOF\nhello $name $((1 + 2))\nEOF\n";
+    assert_eq!(run(script), "hello world 3\n");
+    let script = "name=world\ncat <<'EOF'\nhello $name\nEOF\n";
+    assert_eq!(run(script), "hello $name\n");
+}
+
+#[test]
+fn a_dash_strips_leading_tabs() {
+    assert_eq!(run("cat <<-EOF\n\tone\n\t\ttwo\n\tEOF\n"), "one\ntwo\n");
+    assert_eq!(run("cat <<EOF\n\tone\nEOF\n"), "\tone\n");
+}
+
+#[test]
+fn several_bodies_follow_the_line_in_order() {
+    assert_eq!(run("cat <<A; cat <<B\none\nA\ntwo\nB\n"), "one\ntwo\n");
+}
+
+#[test]
+fn the_body_feeds_the_head_of_a_pipeline() {
+    let script = "cat <<EOF | grep x | tr a-z A-Z\naxe\nbee\nbox\nEOF\n";
+    assert_eq!(run(script), "AXE\nBOX\n");
+    let script = "cat <<A | cat; cat <<B | wc -l\none\nA\n1\n2\nB\n";
+    assert_eq!(run(script).replace(' ', ""), "one\n2\n");
+}
@@ -0,0 +1,192 @@
+use std::fs;
+
+use rush::Shell;
+
+/// Run `input` as an interactive session, which keeps a history, returning
+/// what it wrote
+fn session(shell: &mut Shell, input: &str) -> String {
+    let mut output = vec![];
+    shell.run_with(input.as_bytes(), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+/// Whether `output` lists `command` with a year in angle brackets in front
+fn has_year(output: &str, command: &str) -> bool {
+    output.lines().any(|line| {
+        line.split_once('<')
+            .and_then(|(_, rest)| rest.split_once("> "))
+            .is_some_and(|(year, rest)| {
+                year.len() == 4 && year.chars().all(|c| c.is_ascii_digit()) && rest == command
+            })
+    })
+}
+
+#[test]
+fn histtimeformat_puts_the_time_in_front_of_entries() {
+    let output = session(
+        &mut Shell::new(),
+        "echo hi\nHISTTIMEFORMAT='<%Y> '\nhistory\nunset HISTTIMEFORMAT\nhistory\n",
+    );
+    assert!(has_year(&output, "echo hi"), "{output}");
+    assert!(output.contains("    1  echo hi\n"), "{output}");
+}
+
+#[test]
+fn timestamps_are_kept_in_the_history_file() {
+    let path = std::env::temp_dir().join(format!("rush-history-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let mut shell = Shell::new();
+    shell.set_history_file(&path).unwrap();
+    session(&mut shell, "echo hi\n");
+    let saved = fs::read_to_string(&path).unwrap();
+    let (time, command) = saved.split_once('\n').unwrap();
+    assert!(
+        time.strip_prefix('#').unwrap().parse::<u64>().is_ok(),
+        "{saved}"
+    );
+    assert_eq!(command, "echo hi\n");
+
+    let mut shell = Shell::new();
+    shell.set_history_file(&path).unwrap();
+    let output = session(&mut shell, "HISTTIMEFORMAT='<%Y> '; history\n");
+    assert!(has_year(&output, "echo hi"), "{output}");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn history_d_deletes_entries_and_renumbers() {
+    let path = std::env::temp_dir().join(format!("rush-history-d-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let mut shell = Shell::new();
+    shell.set_history_file(&path).unwrap();
+    let output = session(
+        &mut shell,
+        "echo a\necho b\necho c\nhistory -d 2\nhistory\nhistory -d 1-2\nhistory -d 99\n",
+    );
+    assert!(
+        output.contains("    1  echo a\n    2  echo c\n    3  history -d 2\n"),
+        "{output}"
+    );
+    let saved = fs::read_to_string(&path).unwrap();
+    let commands: Vec<&str> = saved
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert_eq!(
+        commands,
+        ["history -d 2", "history", "history -d 1-2", "history -d 99"]
+    );
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn history_d_rejects_positions_out_of_range() {
+    let output = Shell::new().run_line("history -d 5").unwrap();
+    assert_eq!(output.status, 1);
+}
+
+#[test]
+fn commands_spanning_lines_are_one_entry() {
+    let output = session(
+        &mut Shell::new(),
+        "echo one \\\ntwo\nif true; then\necho x\nfi\nhistory\n",
+    );
+    let listed = "    1  echo one two\n    2  if true; then\necho x\nfi\n    3  history\n";
+    assert!(output.contains(listed), "{output}");
+}
+
+#[test]
+fn without_cmdhist_each_line_is_an_entry() {
+    let output = session(
+        &mut Shell::new(),
+        "set +o cmdhist\nif true; then\necho x\nfi\nhistory\n",
+    );
+    assert!(
+        output.contains("    2  if true; then\n    3  echo x\n    4  fi\n"),
+        "{output}"
+    );
+}
+
+#[test]
+fn history_json_exports_commands_times_and_statuses() {
+    let output = session(&mut Shell::new(), "echo a\nfalse\nhistory --json\n");
+    // Between the prompts, the one after `false` showing its status
+    let json = &output[output.find("[\n").unwrap()..=output.rfind(']').unwrap()];
+    let entries: serde_json::Value = serde_json::from_str(json).unwrap();
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0]["command"], "echo a");
+    assert_eq!(entries[0]["status"], 0);
+    assert_eq!(entries[1]["command"], "false");
+    assert_eq!(entries[1]["status"], 1);
+    assert!(entries[1]["time"].as_u64().is_some());
+    // Still running while it was exported
+    assert!(entries[2]["status"].is_null());
+}
+
+#[test]
+fn word_designators_pick_words_of_the_previous_command() {
+    let output = session(
+        &mut Shell::new(),
+        "echo one two three\necho !$ !^ !!:2\necho !!\n",
+    );
+    // Each expanded line is shown before it runs
+    let expected = "one two three\n\
+        echo three one two\nthree one two\n\
+        echo echo three one two\necho three one two\n";
+    assert_eq!(output.replace("> ", ""), expected);
+}
+
+#[test]
+fn history_references_are_left_alone_in_single_quotes() {
+    let output = session(&mut Shell::new(), "echo a\necho '!!' \"!!\"\n");
+    assert!(output.ends_with("!! echo a\n> "), "{output}");
+}
+
+/// The commands `history` lists after `input`, without their numbers
+fn remembered(input: &str) -> Vec<String> {
+    let output = session(&mut Shell::new(), &format!("{input}history\n"));
+    output
+        .lines()
+        .filter_map(|line| line.trim_start_matches(['>', ' ']).split_once("  "))
+        .map(|(_, command)| command.to_string())
+        .collect()
+}
+
+#[test]
+fn every_command_is_remembered_by_default() {
+    assert_eq!(
+        remembered("echo a\necho a\n echo b\n"),
+        ["echo a", "echo a", " echo b", "history"]
+    );
+}
+
+#[test]
+fn histcontrol_leaves_out_duplicates_and_commands_after_a_space() {
+    let commands = "echo a\necho a\n echo b\necho a\n";
+    assert_eq!(
+        remembered(&format!("HISTCONTROL=ignoredups\n{commands}")),
+        [
+            "HISTCONTROL=ignoredups",
+            "echo a",
+            " echo b",
+            "echo a",
+            "history"
+        ]
+    );
+    assert_eq!(
+        remembered(&format!("HISTCONTROL=ignorespace\n{commands}")),
+        [
+            "HISTCONTROL=ignorespace",
+            "echo a",
+            "echo a",
+            "echo a",
+            "history"
+        ]
+    );
+    // The last `echo a` is the same as the one remembered before it
+    assert_eq!(
+        remembered(&format!("HISTCONTROL=ignoreboth\n{commands}")),
+        ["HISTCONTROL=ignoreboth", "echo a", "history"]
+    );
+}
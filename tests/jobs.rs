@@ -0,0 +1,51 @@
+use rush::Shell;
+
+/// Starts a job that is done by the time the next line is read
+const FINISHED_JOB: &str = "true &\nsleep 0.2\njobs\n";
+
+#[test]
+fn finished_jobs_are_reaped_before_the_prompt() {
+    let mut output = vec![];
+    Shell::new()
+        .run_with(FINISHED_JOB.as_bytes(), &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(!output.contains("Done"), "{output}");
+}
+
+#[test]
+fn scripts_leave_finished_jobs_to_jobs() {
+    let output = Shell::new().run_reader(FINISHED_JOB.as_bytes()).unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert!(output.contains("Done"), "{output}");
+    assert!(output.contains("true"), "{output}");
+
+    let script = "sh -c 'exit 2' &\nsleep 0.2\njobs\n";
+    let output = Shell::new().run_reader(script.as_bytes()).unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert!(output.contains("Exit 2"), "{output}");
+}
+
+#[test]
+fn background_pipelines_are_a_single_job() {
+    let mut shell = Shell::new();
+    shell.run_line("sleep 0.3 | cat &").unwrap();
+    let jobs = String::from_utf8(shell.run_line("jobs").unwrap().stdout).unwrap();
+    assert_eq!(jobs.lines().count(), 1, "{jobs}");
+    assert!(jobs.starts_with("[1]+  Running"), "{jobs}");
+    assert!(jobs.trim_end().ends_with("sleep 0.3 | cat &"), "{jobs}");
+
+    // With the status of the last stage
+    shell.run_line("sleep 0.1 | sh -c 'exit 5' &").unwrap();
+    assert_eq!(shell.run_line("wait %2").unwrap().status, 5);
+}
+
+#[test]
+fn kill_stops_every_stage_of_a_pipeline_job() {
+    let mut shell = Shell::new();
+    shell.run_line("sleep 5 | sleep 5 &").unwrap();
+    let start = std::time::Instant::now();
+    shell.run_line("kill %1").unwrap();
+    assert_eq!(shell.run_line("wait %1").unwrap().status, 143);
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+}
@@ -0,0 +1,50 @@
+use std::{
+    cell::RefCell,
+    io::{self, ErrorKind, Write},
+    rc::Rc,
+};
+
+use rush::Shell;
+
+/// Keeps what is written to it, except that writes starting with `fail`
+/// fail and those starting with `broken` fail with a broken pipe
+#[derive(Clone, Default)]
+struct Flaky(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Flaky {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.starts_with(b"fail") {
+            Err(io::Error::other("disk full"))
+        } else if buf.starts_with(b"broken") {
+            Err(ErrorKind::BrokenPipe.into())
+        } else {
+            self.0.borrow_mut().write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn written(out: &Flaky) -> String {
+    String::from_utf8(out.0.borrow().clone()).unwrap()
+}
+
+#[test]
+fn a_failed_write_sets_the_status_and_the_list_goes_on() {
+    let out = Flaky::default();
+    let status = Shell::new()
+        .run_string("echo one; echo fail; echo $?; echo two", out.clone())
+        .unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(written(&out), "one\n1\ntwo\n");
+}
+
+#[test]
+fn a_broken_pipe_stops_the_list() {
+    let out = Flaky::default();
+    let result = Shell::new().run_string("echo one; echo broken; echo two", out.clone());
+    assert!(matches!(result, Err(rush::ShellError::Io(e)) if e.kind() == ErrorKind::BrokenPipe));
+    assert_eq!(written(&out), "one\n");
+}
@@ -0,0 +1,53 @@
+use rush::{parse, Command, CommandChain, Redirect};
+
+/// The command with `bin` and `args` and nothing else
+fn command(bin: &str, args: &[&str]) -> Command {
+    Command {
+        assignments: vec![],
+        bin: bin.into(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        redirects: vec![],
+        program: None,
+    }
+}
+
+#[test]
+fn pipelines_parse_into_their_stages() {
+    let chains = parse("echo a | wc").unwrap();
+    let expected = CommandChain::Piped(vec![command("echo", &["a"]), command("wc", &[])]);
+    assert_eq!(chains, [expected]);
+}
+
+#[test]
+fn operators_and_redirects_are_kept() {
+    let chains = parse("make && echo ok > log || echo failed; sleep 1 &").unwrap();
+    let mut ok = command("echo", &["ok"]);
+    ok.redirects.push(Redirect::Write {
+        fd: 1,
+        path: "log".into(),
+        append: false,
+    });
+    let and = CommandChain::And(
+        Box::new(CommandChain::Command(command("make", &[]))),
+        Box::new(CommandChain::Command(ok)),
+    );
+    let or = CommandChain::Or(
+        Box::new(and),
+        Box::new(CommandChain::Command(command("echo", &["failed"]))),
+    );
+    let background =
+        CommandChain::Background(Box::new(CommandChain::Command(command("sleep", &["1"]))));
+    assert_eq!(chains, [or, background]);
+}
+
+#[test]
+fn nothing_is_expanded_or_run() {
+    let chains = parse("FOO=$HOME cd ~ && rm *").unwrap();
+    assert_eq!(chains[0].to_string(), "FOO=$HOME cd ~ && rm *");
+}
+
+#[test]
+fn unfinished_input_is_an_error() {
+    assert!(parse("echo 'unclosed").is_err());
+    assert!(parse("if true; then echo").is_err());
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::run;
+use rush::Shell;
+
+#[test]
+fn builtins_write_into_the_pipe() {
+    let mut shell = Shell::new();
+    let (help, _) = run(&mut shell, "help | head -3");
+    assert_eq!(help.lines().count(), 3);
+    let (pwd, _) = run(&mut shell, "pwd | cat");
+    assert_eq!(
+        pwd.trim(),
+        std::env::current_dir().unwrap().to_str().unwrap()
+    );
+    shell.run_line("alias ll='ls -l'").unwrap();
+    let aliases = shell.run_line("alias | cat").unwrap().stdout;
+    assert_eq!(String::from_utf8(aliases).unwrap(), "alias ll='ls -l'\n");
+}
+
+#[test]
+fn builtins_read_from_the_pipe() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "echo x | read line").1, 0);
+    assert_eq!(run(&mut shell, "true | read line").1, 1);
+    assert_eq!(run(&mut shell, "echo hi | cat | read line").1, 0);
+}
+
+#[test]
+fn builtins_run_in_a_subshell() {
+    let mut shell = Shell::new();
+    assert_eq!(
+        run(&mut shell, r#"echo x | read line; echo "[$line]""#).0,
+        "[]\n"
+    );
+    let pwd = std::env::current_dir().unwrap();
+    assert_eq!(
+        run(&mut shell, "cd / | true; pwd").0.trim(),
+        pwd.to_str().unwrap()
+    );
+}
+
+#[test]
+fn status_is_the_last_stage_s() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "echo x | false").1, 1);
+    assert_eq!(run(&mut shell, "false | echo x").1, 0);
+    assert_eq!(run(&mut shell, "set -o pipefail; false | echo x").1, 1);
+}
+
+#[test]
+fn pipefail_reports_a_failing_stage() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "false | true").1, 0);
+    assert_eq!(run(&mut shell, "set -o pipefail; false | true").1, 1);
+    assert_eq!(run(&mut shell, "set -o pipefail; true | true").1, 0);
+    // Like in bash, the last stage that failed
+    let line = "set -o pipefail; sh -c 'exit 2' | sh -c 'exit 3' | true";
+    assert_eq!(run(&mut shell, line).1, 3);
+    assert_eq!(
+        run(
+            &mut shell,
+            "set -o pipefail; sh -c 'exit 2' | echo x | true"
+        )
+        .1,
+        2
+    );
+    assert_eq!(
+        run(&mut shell, "set -o pipefail; set +o pipefail; false | true").1,
+        0
+    );
+}
@@ -0,0 +1,33 @@
+use std::process::Command;
+
+/// Run `commands` with `rush -c`, with `RUSTLAB_PROFILE` set to `profile`,
+/// and return what it wrote to stderr
+fn stderr(profile: &str, commands: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(["-c", commands])
+        .env("RUSTLAB_PROFILE", profile)
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"hi\n");
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn profiling_prints_a_summary_of_each_phase_on_exit() {
+    let summary = stderr("1", "echo hi; sh -c true");
+    assert!(summary.starts_with("rush profile:\n"), "{summary}");
+    for phase in ["parse", "expand", "builtin", "process"] {
+        assert!(
+            summary
+                .lines()
+                .any(|line| line.trim_start().starts_with(phase)),
+            "{summary}"
+        );
+    }
+    assert!(summary.contains("process         1 calls"), "{summary}");
+}
+
+#[test]
+fn profiling_is_off_by_default() {
+    assert_eq!(stderr("0", "echo hi"), "");
+}
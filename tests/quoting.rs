@@ -0,0 +1,25 @@
+use rush::Shell;
+
+/// The arguments `words` expand to, each followed by a `|`
+fn args(words: &str) -> String {
+    let output = Shell::new()
+        .run_line(&format!("printf '%s|' {words}"))
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn ansi_c_quotes_interpret_escapes() {
+    assert_eq!(args(r"$'a\nb'"), "a\nb|");
+    assert_eq!(args(r"$'a\tb' $'\r'"), "a\tb|\r|");
+    assert_eq!(args(r"$'\x41\x62'"), "Ab|");
+    assert_eq!(args(r"$'caf\u00e9 \u263a'"), "café ☺|");
+    assert_eq!(args(r"$'\101'"), "A|");
+    assert_eq!(args(r"$'it\'s' $'back\\slash'"), "it's|back\\slash|");
+}
+
+#[test]
+fn ansi_c_quotes_make_a_single_literal_argument() {
+    assert_eq!(args(r"$'two words' $'$HOME *'"), "two words|$HOME *|");
+    assert_eq!(args(r"pre$'\t'post"), "pre\tpost|");
+}
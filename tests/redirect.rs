@@ -0,0 +1,88 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rush::Shell;
+
+/// Writes `out` to stdout and `err` to stderr
+const BOTH: &str = "sh -c 'echo out; echo err >&2'";
+
+/// A fresh directory for the files a test redirects to
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rush-redirect-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `line` in `dir`, returning what it wrote to stdout
+fn run(dir: &Path, line: &str) -> String {
+    let mut shell = Shell::new();
+    shell.run_line(&format!("cd {}", dir.display())).unwrap();
+    let output = shell.run_line(line).unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn read(dir: &Path, name: &str) -> String {
+    fs::read_to_string(dir.join(name)).unwrap()
+}
+
+#[test]
+fn stderr_joins_the_pipe_of_its_stage() {
+    let dir = dir("pipe");
+    assert_eq!(run(&dir, "ls /nonexistent 2>&1 | wc -l").trim(), "1");
+    assert_eq!(run(&dir, &format!("{BOTH} 2>&1 | sort")), "err\nout\n");
+    assert_eq!(
+        run(&dir, &format!("{BOTH} 2>&1 | cat | sort")),
+        "err\nout\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stderr_follows_stdout_as_it_was_when_duplicated() {
+    let dir = dir("order");
+    run(&dir, &format!("{BOTH} > both 2>&1"));
+    assert_eq!(read(&dir, "both"), "out\nerr\n");
+    assert_eq!(run(&dir, &format!("{BOTH} 2>&1 > out | cat")), "err\n");
+    assert_eq!(read(&dir, "out"), "out\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn each_stage_has_its_own_redirects() {
+    let dir = dir("stages");
+    assert_eq!(run(&dir, &format!("{BOTH} 2> err | cat > out")), "");
+    assert_eq!(read(&dir, "out"), "out\n");
+    assert_eq!(read(&dir, "err"), "err\n");
+    run(&dir, &format!("{BOTH} 2>&1 | sort > sorted"));
+    assert_eq!(read(&dir, "sorted"), "err\nout\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn the_last_redirect_of_an_fd_wins() {
+    let dir = dir("last");
+    assert_eq!(run(&dir, "echo hi > a > b"), "");
+    assert_eq!(read(&dir, "a"), "");
+    assert_eq!(read(&dir, "b"), "hi\n");
+    // Even one that held something is emptied
+    run(&dir, "echo again > b > a");
+    assert_eq!(read(&dir, "a"), "again\n");
+    assert_eq!(read(&dir, "b"), "");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn appends_keep_what_the_file_held() {
+    let dir = dir("append");
+    fs::write(dir.join("log"), "old\n").unwrap();
+    run(&dir, &format!("{BOTH} >> log 2>&1"));
+    assert_eq!(read(&dir, "log"), "old\nout\nerr\n");
+    run(&dir, &format!("{BOTH} &>> log"));
+    assert_eq!(read(&dir, "log"), "old\nout\nerr\nout\nerr\n");
+    assert_eq!(run(&dir, &format!("{BOTH} 2>> log")), "out\n");
+    assert_eq!(read(&dir, "log"), "old\nout\nerr\nout\nerr\nerr\n");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,50 @@
+use std::fs;
+
+use rush::Shell;
+
+#[test]
+fn cd_and_redirects_stay_inside_the_root() {
+    let dir = std::env::temp_dir().join(format!("rush-sandbox-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let root = dir.join("root");
+    fs::create_dir_all(root.join("sub")).unwrap();
+
+    let mut shell = Shell::new();
+    shell.set_sandbox(&root).unwrap();
+    let run = |shell: &mut Shell, line: &str| {
+        let output = shell.run_line(line).unwrap();
+        (String::from_utf8(output.stdout).unwrap(), output.status)
+    };
+    assert_eq!(run(&mut shell, &format!("cd {}", root.display())).1, 0);
+    assert_eq!(
+        run(&mut shell, "echo in > sub/file; cat sub/file").0,
+        "in\n"
+    );
+    assert_eq!(run(&mut shell, "cd sub").1, 0);
+
+    assert_eq!(run(&mut shell, "echo out > ../../outside").1, 1);
+    assert!(!dir.join("outside").exists());
+    assert_eq!(run(&mut shell, "cd ../..").1, 1);
+    assert_eq!(run(&mut shell, "cd /").1, 1);
+    assert_eq!(
+        run(&mut shell, "pwd").0,
+        format!("{}\n", root.join("sub").canonicalize().unwrap().display())
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn escapes_are_reported() {
+    let root = std::env::temp_dir().join(format!("rush-sandbox-flag-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rush"))
+        .arg("--sandbox")
+        .arg(&root)
+        .args(["-c", "cd /"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.ends_with("sandbox: path escapes root\n"), "{error}");
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,67 @@
+use std::{fs, path::PathBuf};
+
+use rush::Shell;
+
+/// A script file starting with a byte order mark and a shebang
+fn script(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rush-script-{name}-{}", std::process::id()));
+    fs::write(&path, "\u{feff}#!/usr/bin/env rush\necho one\necho two\n").unwrap();
+    path
+}
+
+#[test]
+fn scripts_skip_a_bom_and_shebang() {
+    let path = script("run");
+    let mut stdout = vec![];
+    let status = Shell::new().run_file(&path, &mut stdout).unwrap();
+    assert_eq!(
+        (String::from_utf8(stdout).unwrap().as_str(), status),
+        ("one\ntwo\n", 0)
+    );
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn sourced_scripts_skip_a_bom_and_shebang() {
+    let path = script("source");
+    let output = Shell::new()
+        .run_line(&format!("source {}", path.display()))
+        .unwrap();
+    assert_eq!(output.stdout, b"one\ntwo\n");
+    assert_eq!(output.status, 0);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn binary_files_are_not_run() {
+    let path = std::env::temp_dir().join(format!("rush-script-binary-{}", std::process::id()));
+    fs::write(&path, b"\x7fELF\x02\x01\x01\0\0\0echo garbage\n").unwrap();
+    let mut stdout = vec![];
+    let result = Shell::new().run_file(&path, &mut stdout);
+    assert!(matches!(result, Err(rush::ShellError::BinaryFile)));
+    assert!(stdout.is_empty());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_rush"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(126));
+    assert!(output.stdout.is_empty());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.ends_with(": cannot execute binary file\n"), "{error}");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn crlf_lines_run_like_lf_ones() {
+    let script = "x=1\r\necho \"[$x]\"\r\ncd /\r\npwd\r\n";
+    let output = Shell::new().run_reader(script.as_bytes()).unwrap();
+    assert_eq!(output.stdout, b"[1]\n/\n");
+
+    let path = std::env::temp_dir().join(format!("rush-script-crlf-{}", std::process::id()));
+    fs::write(&path, script).unwrap();
+    let mut stdout = vec![];
+    Shell::new().run_file(&path, &mut stdout).unwrap();
+    assert_eq!(stdout, b"[1]\n/\n");
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,83 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// A startup file in `dir` that echoes its `name`
+fn rc(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    fs::write(&path, format!("echo {name}\n")).unwrap();
+    path
+}
+
+/// A fresh directory for the files of a test
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rush-startup-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Start the shell with `args` and `default` as the default startup file,
+/// feeding it `echo hi` on stdin
+fn start(args: &[&str], default: &Path) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rush"))
+        .args(args)
+        .env("RUSTLAB_RC", default)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"echo hi\n").unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn profile_startup_times_the_rc_file() {
+    let dir = dir("profile");
+    let rc = rc(&dir, "sourced");
+    let rc = rc.to_str().unwrap();
+
+    let output = start(&["--rcfile", rc, "--profile-startup"], &dir.join("none"));
+    assert_eq!(output.stdout, b"sourced\nhi\n");
+    let timing = String::from_utf8(output.stderr).unwrap();
+    let line = timing
+        .strip_prefix(&format!("rush startup: {rc} "))
+        .unwrap();
+    assert!(line.trim_end().ends_with('s'), "{timing}");
+    assert!(start(&["--rcfile", rc], &dir.join("none"))
+        .stderr
+        .is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rcfile_is_sourced_instead_of_the_default() {
+    let dir = dir("rcfile");
+    let (rc, default) = (rc(&dir, "sourced"), rc(&dir, "default"));
+    let output = start(&["--rcfile", rc.to_str().unwrap()], &default);
+    assert_eq!(output.stdout, b"sourced\nhi\n");
+    assert_eq!(start(&[], &default).stdout, b"default\nhi\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn only_a_missing_default_is_skipped() {
+    let dir = dir("missing");
+    let missing = dir.join("missing");
+    let output = start(&[], &missing);
+    assert_eq!(
+        (output.status.code(), output.stdout),
+        (Some(0), b"hi\n".to_vec())
+    );
+
+    let output = start(&["--rcfile", missing.to_str().unwrap()], &missing);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains(missing.to_str().unwrap()), "{error}");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,41 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Run the shell binary with `script` piped to its stdin
+fn run_piped(script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rush"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn scripts_on_stdin_can_open_descriptor_3() {
+    let file = std::env::temp_dir().join(format!("rush-stdin-{}", std::process::id()));
+    std::fs::write(&file, "from the file\n").unwrap();
+    let script = format!(
+        "exec 3< {}\nread -u 3 line\necho \"[$line]\"\n",
+        file.display()
+    );
+    assert_eq!(run_piped(&script), "[from the file]\n");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn read_takes_the_line_after_its_own() {
+    assert_eq!(
+        run_piped("read x\nnext line\necho \"[$x]\"\n"),
+        "[next line]\n"
+    );
+}
@@ -0,0 +1,22 @@
+mod common;
+
+use common::run;
+use rush::Shell;
+
+#[test]
+fn exit_only_ends_the_substitution() {
+    let mut shell = Shell::new();
+    let (out, status) = run(&mut shell, "echo $(echo a; exit; echo b)");
+    assert_eq!((out.as_str(), status), ("a\n", 0));
+    assert_eq!(run(&mut shell, "echo still here").0, "still here\n");
+    assert_eq!(shell.exit_code(), None);
+}
+
+#[test]
+fn assignments_return_the_status_of_their_substitution() {
+    let mut shell = Shell::new();
+    assert_eq!(run(&mut shell, "x=$(echo a; exit 4)").1, 4);
+    assert_eq!(run(&mut shell, "echo $x").0, "a\n");
+    assert_eq!(run(&mut shell, "false; y=$?; echo $? $y").0, "0 1\n");
+    assert_eq!(run(&mut shell, "$(exit 3)").1, 3);
+}
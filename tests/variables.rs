@@ -0,0 +1,57 @@
+use rush::Shell;
+
+fn run(shell: &mut Shell, line: &str) -> String {
+    String::from_utf8(shell.run_line(line).unwrap().stdout).unwrap()
+}
+
+#[test]
+fn unset_in_a_subshell_stays_there() {
+    let mut shell = Shell::new();
+    let path = std::env::var("PATH").unwrap();
+    assert_eq!(run(&mut shell, "echo $(unset PATH)"), "\n");
+    assert_eq!(run(&mut shell, "echo $PATH"), format!("{path}\n"));
+}
+
+#[test]
+fn unset_hides_inherited_variables_from_lookups_and_children() {
+    let mut shell = Shell::new();
+    assert_eq!(
+        run(
+            &mut shell,
+            r#"unset CARGO_MANIFEST_DIR; echo "[$CARGO_MANIFEST_DIR]""#
+        ),
+        "[]\n"
+    );
+    assert_eq!(
+        run(&mut shell, r#"/bin/sh -c 'echo "[$CARGO_MANIFEST_DIR]"'"#),
+        "[]\n"
+    );
+    // The shell's process keeps its environment, for other shells in it
+    assert!(std::env::var_os("CARGO_MANIFEST_DIR").is_some());
+    assert_ne!(run(&mut Shell::new(), "echo $CARGO_MANIFEST_DIR"), "\n");
+}
+
+#[test]
+fn variables_set_after_unset_are_only_passed_on_once_exported() {
+    let mut shell = Shell::new();
+    let child = r#"/bin/sh -c 'echo "[$CARGO_MANIFEST_DIR]"'"#;
+    run(
+        &mut shell,
+        "unset CARGO_MANIFEST_DIR; CARGO_MANIFEST_DIR=/nowhere",
+    );
+    assert_eq!(run(&mut shell, child), "[]\n");
+    run(&mut shell, "export CARGO_MANIFEST_DIR");
+    assert_eq!(run(&mut shell, child), "[/nowhere]\n");
+}
+
+#[test]
+fn bare_assignments_stay_set_for_the_session() {
+    let mut shell = Shell::new();
+    let output = shell.run_line("FOO=bar BAZ=qux").unwrap();
+    assert_eq!(output.status, 0);
+    assert_eq!(run(&mut shell, "echo $FOO $BAZ"), "bar qux\n");
+    // Shell variables, which children don't get unless exported
+    assert_eq!(run(&mut shell, r#"sh -c 'echo "[$FOO]"'"#), "[]\n");
+    // Only those without a command
+    assert_eq!(run(&mut shell, "FOO=other true; echo $FOO"), "bar\n");
+}